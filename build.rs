@@ -1,15 +1,41 @@
 use std::env;
+use std::fs;
 use std::path::PathBuf;
 
+include!("src/ffi_network_modules.rs");
+
+/// Returns the source of the extern functions that are generated by macros,
+/// which cbindgen doesn't expand.
+fn ffi_network_modules_src() -> String {
+    let mut src = String::new();
+    macro_rules! source {
+        ($($item:item)*) => {
+            $(
+                src.push_str(stringify!($item));
+                src.push('\n');
+            )*
+        };
+    }
+    ffi_network_modules!(source);
+    src
+}
+
 fn main() {
     let crate_dir =
         env::var("CARGO_MANIFEST_DIR").expect("CARGO_MANIFEST_DIR env var is not defined");
     let out_dir = PathBuf::from("ffi");
+    let src = PathBuf::from(env::var("OUT_DIR").expect("OUT_DIR env var is not defined"))
+        .join("ffi_network_modules.rs");
+    fs::write(&src, ffi_network_modules_src()).expect("Unable to write the FFI source");
 
     let config = cbindgen::Config::from_file("cbindgen.toml")
         .expect("Unable to find cbindgen.toml configuration file");
 
-    cbindgen::generate_with_config(&crate_dir, config)
+    cbindgen::Builder::new()
+        .with_config(config)
+        .with_crate(&crate_dir)
+        .with_src(&src)
+        .generate()
         .unwrap()
         .write_to_file(out_dir.join("network_module_ffi.h"));
 }
@@ -0,0 +1,199 @@
+//! Decodes the `hoip` data messages of a pcap file into a CSV with one row per
+//! sample on stdout.
+//!
+//! The pcap can be written by `NetworkModule::start_capture` or taken with
+//! tcpdump, e.g. `tcpdump -i lo -w capture.pcap udp port 13370`.
+//!
+//! Usage: pcap_to_csv <capture.pcap> [--dof 3|6|7] [--port <port>]
+//!                    [--crc32 | --hmac <key>]
+extern crate network_emulator;
+
+use csv::Writer;
+use network_emulator::{
+    capture::{Datagram, PcapReader},
+    hoip::{
+        Integrity, MessageRef, Packet, Payload6DoFM2S, Payload6DoFS2M, Payload7DoFM2S,
+        Payload7DoFS2M, PayloadM2S, PayloadS2M, PayloadType, Serializable,
+    },
+    sampling::Sample,
+};
+use serde::Serialize;
+use std::{env, error::Error, io, process};
+
+#[derive(Debug, Serialize)]
+struct Record {
+    time: f64,
+    src: String,
+    dst: String,
+    payload_type: String,
+    sampling_scheme: String,
+    num_samples: u8,
+    delay_indicator: String,
+    threshold: u16,
+    rott: u32,
+    timestamp: u64,
+    sequence: u16,
+    redundancy: u8,
+    compressed: bool,
+    redundant: bool,
+    sample_ts: u64,
+    values: String,
+}
+
+struct Args {
+    path: String,
+    dof: u8,
+    port: Option<u16>,
+    integrity: Integrity,
+}
+
+fn parse_args() -> Result<Args, String> {
+    let mut args = env::args().skip(1);
+    let mut path = None;
+    let mut dof = 3;
+    let mut port = None;
+    let mut integrity = Integrity::None;
+    while let Some(arg) = args.next() {
+        let mut value = |name: &str| args.next().ok_or(format!("{} needs a value", name));
+        match arg.as_str() {
+            "--dof" => {
+                dof = match value("--dof")?.as_str() {
+                    "3" => 3,
+                    "6" => 6,
+                    "7" => 7,
+                    dof => return Err(format!("unsupported DoF {}", dof)),
+                }
+            }
+            "--port" => port = Some(value("--port")?.parse().map_err(|e| format!("{}", e))?),
+            "--crc32" => integrity = Integrity::Crc32,
+            "--hmac" => integrity = Integrity::hmac(value("--hmac")?.as_bytes()),
+            _ if path.is_none() && !arg.starts_with("--") => path = Some(arg),
+            _ => return Err(format!("unexpected argument {}", arg)),
+        }
+    }
+    Ok(Args {
+        path: path.ok_or("missing pcap file")?,
+        dof,
+        port,
+        integrity,
+    })
+}
+
+fn write_samples<T: Serializable + Sample, W: io::Write>(
+    wtr: &mut Writer<W>,
+    datagram: &Datagram,
+    msg: &MessageRef,
+) -> Result<(), Box<dyn Error>> {
+    let samples = msg.samples::<T>()?.map(|sample| (false, sample));
+    let redundant = msg.redundant_samples::<T>()?.map(|sample| (true, sample));
+    for (redundant, sample) in samples.chain(redundant) {
+        let (sample_ts, sample) = sample?;
+        let values = (0..T::num_channels())
+            .flat_map(|c| sample.channel(c).to_vec())
+            .map(|v| v.to_string())
+            .collect::<Vec<_>>()
+            .join(" ");
+        wtr.serialize(Record {
+            time: datagram.ts.as_secs_f64(),
+            src: datagram.src.to_string(),
+            dst: datagram.dst.to_string(),
+            payload_type: format!("{:?}", msg.payload_type()),
+            sampling_scheme: format!("{:?}", msg.sampling_scheme()),
+            num_samples: msg.num_samples(),
+            delay_indicator: format!("{:?}", msg.delay_indicator()),
+            threshold: msg.threshold(),
+            rott: msg.rott(),
+            timestamp: msg.timestamp(),
+            sequence: msg.sequence(),
+            redundancy: msg.redundancy(),
+            compressed: msg.compression().is_some(),
+            redundant,
+            sample_ts,
+            values,
+        })?;
+    }
+    Ok(())
+}
+
+fn run(mut args: Args) -> Result<(), Box<dyn Error>> {
+    let mut wtr = Writer::from_writer(io::stdout());
+    let mut skipped = 0;
+    let mut invalid = 0;
+    let mut buf = Vec::new();
+    for datagram in PcapReader::open(&args.path)? {
+        let datagram = datagram?;
+        if let Some(port) = args.port {
+            if datagram.src.port() != port && datagram.dst.port() != port {
+                continue;
+            }
+        }
+        if Packet::is_handshake(&datagram.payload) {
+            skipped += 1;
+            continue;
+        }
+        // Opening may decrypt in place.
+        buf.clear();
+        buf.extend_from_slice(&datagram.payload);
+        let bs = match args.integrity.open(&mut buf) {
+            Ok(bs) => bs,
+            Err(_) => {
+                invalid += 1;
+                continue;
+            }
+        };
+        if !Packet::is_data(bs) {
+            skipped += 1;
+            continue;
+        }
+        let msg = match MessageRef::new(bs) {
+            Ok(msg) => msg,
+            Err(_) => {
+                invalid += 1;
+                continue;
+            }
+        };
+        let written = match (args.dof, msg.payload_type()) {
+            (3, PayloadType::Master) => write_samples::<PayloadM2S, _>(&mut wtr, &datagram, &msg),
+            (3, PayloadType::Slave) => write_samples::<PayloadS2M, _>(&mut wtr, &datagram, &msg),
+            (6, PayloadType::Master) => {
+                write_samples::<Payload6DoFM2S, _>(&mut wtr, &datagram, &msg)
+            }
+            (6, PayloadType::Slave) => {
+                write_samples::<Payload6DoFS2M, _>(&mut wtr, &datagram, &msg)
+            }
+            (_, PayloadType::Master) => {
+                write_samples::<Payload7DoFM2S, _>(&mut wtr, &datagram, &msg)
+            }
+            (_, PayloadType::Slave) => {
+                write_samples::<Payload7DoFS2M, _>(&mut wtr, &datagram, &msg)
+            }
+        };
+        if written.is_err() {
+            invalid += 1;
+        }
+    }
+    wtr.flush()?;
+    eprintln!(
+        "skipped {} non-data datagrams, {} datagrams could not be decoded",
+        skipped, invalid
+    );
+    Ok(())
+}
+
+fn main() {
+    let args = match parse_args() {
+        Ok(args) => args,
+        Err(e) => {
+            eprintln!("{}", e);
+            eprintln!(
+                "usage: pcap_to_csv <capture.pcap> [--dof 3|6|7] [--port <port>] \
+                 [--crc32 | --hmac <key>]"
+            );
+            process::exit(2);
+        }
+    };
+    if let Err(e) = run(args) {
+        eprintln!("{}", e);
+        process::exit(1);
+    }
+}
@@ -6,7 +6,9 @@ use network_emulator::{
     congestion_detection::{self, CongestionDetector},
     hoip::{PayloadM2S, PayloadS2M, PayloadType, Serializable},
     k_policy::{KPolicy, KPolicySDMI, KPolicySDMIExponentialBackoff, KPolicySDSI},
-    now, setup_network_emulator, NetworkModule,
+    now,
+    sampling::Sample,
+    setup_network_emulator, NetworkModule, NetworkModuleConfig,
 };
 use serde::Serialize;
 use std::{
@@ -58,8 +60,8 @@ fn write_simulation_results(i: usize, rx_record: Receiver<Record>) {
 }
 
 pub fn run_network<
-    A: 'static + Send + Clone + Serializable,
-    B: 'static + Send + Serializable,
+    A: 'static + Send + Serializable + Sample,
+    B: 'static + Send + Serializable + Sample,
     CD: 'static + Send + CongestionDetector,
     KP: 'static + Send + KPolicy,
 >(
@@ -72,7 +74,8 @@ pub fn run_network<
     thread::spawn(move || {
         let mut i = 0;
         while running.load(Ordering::SeqCst) {
-            if let Some((ts, _)) = network_module.try_recv() {
+            let received = network_module.try_recv().expect("failed to receive");
+            if let Some((ts, _)) = received {
                 let now = now();
                 tx_record
                     .send(Record {
@@ -83,7 +86,9 @@ pub fn run_network<
                     .expect("failed to send record from slave");
             }
             if i % 4 == 0 {
-                network_module.send(sample_packet.clone());
+                network_module
+                    .send(sample_packet.clone())
+                    .expect("failed to send");
             }
             i += 1;
             thread::sleep(std::time::Duration::from_micros(250));
@@ -106,26 +111,34 @@ fn run_simulation<
     w: f64,
     cooloff: usize,
 ) {
-    let master = NetworkModule::<PayloadM2S, PayloadS2M, CDM, KPM>::new(
-        "127.0.0.1:13380",
-        "127.0.0.1:13370",
+    let config = NetworkModuleConfig {
+        rate: 2000.0,
+        w,
+        cooloff,
+        ..NetworkModuleConfig::default()
+    };
+    let master = NetworkModule::<PayloadM2S, PayloadS2M, CDM, KPM>::from_config(
+        NetworkModuleConfig {
+            dest_addr: "127.0.0.1:13380".to_string(),
+            src_addr: "127.0.0.1:13370".to_string(),
+            op: PayloadType::Master,
+            ..config.clone()
+        },
         congestion_detector_mater,
         k_policy_master,
-        w,
-        10,
-        PayloadType::Master,
-        2000.0,
-    );
-    let slave = NetworkModule::<PayloadS2M, PayloadM2S, CDS, KPS>::new(
-        "127.0.0.1:13370",
-        "127.0.0.1:13380",
+    )
+    .unwrap();
+    let slave = NetworkModule::<PayloadS2M, PayloadM2S, CDS, KPS>::from_config(
+        NetworkModuleConfig {
+            dest_addr: "127.0.0.1:13370".to_string(),
+            src_addr: "127.0.0.1:13380".to_string(),
+            op: PayloadType::Slave,
+            ..config
+        },
         congestion_detector_slave,
         k_policy_slave,
-        w,
-        10,
-        PayloadType::Slave,
-        2000.0,
-    );
+    )
+    .unwrap();
 
     let simulation_running = Arc::new(AtomicBool::new(true));
 
@@ -184,8 +197,8 @@ fn main() -> Result<(), Box<dyn Error>> {
         let congestion_detector_mater = congestion_detection::Window::new(5);
         let congestion_detector_slave = congestion_detection::Window::new(5);
 
-        let k_policy_master = KPolicySDMI {};
-        let k_policy_slave = KPolicySDMI {};
+        let k_policy_master = KPolicySDMI::new();
+        let k_policy_slave = KPolicySDMI::new();
 
         /*
         let k_policy_master = KPolicySDSI {};
@@ -0,0 +1,228 @@
+//! `#[derive(Serializable)]` for `hoip` payloads.
+//!
+//! The derive supports `#[repr(C)]` structs whose fields are integer or float
+//! scalars or (nested) arrays of them. Fields are encoded big-endian in
+//! declaration order without padding.
+//!
+//! The generated code refers to `::network_emulator`. A different path to the
+//! crate can be given with `#[serializable(crate = "path")]`.
+
+extern crate proc_macro;
+
+use proc_macro::TokenStream;
+use proc_macro2::TokenStream as TokenStream2;
+use quote::{quote, quote_spanned};
+use syn::spanned::Spanned;
+use syn::{
+    parse_macro_input, Data, DeriveInput, Error, Fields, Index, Lit, Meta, NestedMeta, Path, Type,
+};
+
+const SCALARS: &[&str] = &[
+    "u8", "i8", "u16", "i16", "u32", "i32", "u64", "i64", "u128", "i128", "f32", "f64",
+];
+
+#[proc_macro_derive(Serializable, attributes(serializable))]
+pub fn derive_serializable(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+    match expand(&input) {
+        Ok(tokens) => tokens.into(),
+        Err(e) => e.to_compile_error().into(),
+    }
+}
+
+fn expand(input: &DeriveInput) -> Result<TokenStream2, Error> {
+    if !is_repr_c(input) {
+        return Err(Error::new(
+            input.ident.span(),
+            "Serializable can only be derived for #[repr(C)] structs",
+        ));
+    }
+    let data = match &input.data {
+        Data::Struct(data) => data,
+        _ => {
+            return Err(Error::new(
+                input.ident.span(),
+                "Serializable can only be derived for structs",
+            ))
+        }
+    };
+
+    let krate = crate_path(input)?;
+
+    let mut lens = Vec::new();
+    let mut writes = Vec::new();
+    let mut reads = Vec::new();
+    for (i, field) in data.fields.iter().enumerate() {
+        check_type(&field.ty)?;
+        let ty = &field.ty;
+        let member = match &field.ident {
+            Some(ident) => quote!(#ident),
+            None => {
+                let index = Index::from(i);
+                quote!(#index)
+            }
+        };
+        lens.push(quote!(::std::mem::size_of::<#ty>()));
+        writes.push(write(ty, quote!(self.#member)));
+        reads.push((member, read(ty)));
+    }
+
+    let construct = match &data.fields {
+        Fields::Named(_) => {
+            let fields = reads.iter().map(|(member, read)| quote!(#member: #read));
+            quote!(Self { #(#fields),* })
+        }
+        Fields::Unnamed(_) => {
+            let fields = reads.iter().map(|(_, read)| read);
+            quote!(Self(#(#fields),*))
+        }
+        Fields::Unit => quote!(Self),
+    };
+
+    let name = &input.ident;
+    let (impl_generics, ty_generics, where_clause) = input.generics.split_for_impl();
+    Ok(quote! {
+        impl #impl_generics #krate::hoip::Serializable for #name #ty_generics #where_clause {
+            const LEN: usize = 0 #(+ #lens)*;
+
+            #[allow(unused_variables, unused_assignments)]
+            fn encode(&self, bs: &mut [u8]) {
+                let mut pos = 0;
+                #(#writes)*
+            }
+
+            #[allow(unused_mut, unused_variables, unused_assignments)]
+            fn decode(bs: &[u8]) -> ::std::result::Result<Self, #krate::hoip::HoipError> {
+                let expected = <Self as #krate::hoip::Serializable>::LEN;
+                if bs.len() < expected {
+                    return Err(#krate::hoip::HoipError::SampleTooShort {
+                        len: bs.len(),
+                        expected,
+                    });
+                }
+                let mut pos = 0;
+                Ok(#construct)
+            }
+        }
+    })
+}
+
+/// Returns the path to the `network_emulator` crate.
+fn crate_path(input: &DeriveInput) -> Result<Path, Error> {
+    let attr = match input
+        .attrs
+        .iter()
+        .find(|attr| attr.path.is_ident("serializable"))
+    {
+        Some(attr) => attr,
+        None => return Ok(syn::parse_quote!(::network_emulator)),
+    };
+    if let Meta::List(list) = attr.parse_meta()? {
+        for nested in list.nested.iter() {
+            if let NestedMeta::Meta(Meta::NameValue(nv)) = nested {
+                if let (true, Lit::Str(path)) = (nv.path.is_ident("crate"), &nv.lit) {
+                    return path.parse();
+                }
+            }
+        }
+    }
+    Err(Error::new(
+        attr.span(),
+        "expected #[serializable(crate = \"path\")]",
+    ))
+}
+
+fn is_repr_c(input: &DeriveInput) -> bool {
+    input
+        .attrs
+        .iter()
+        .filter(|attr| attr.path.is_ident("repr"))
+        .filter_map(|attr| attr.parse_meta().ok())
+        .any(|meta| match meta {
+            Meta::List(list) => list.nested.iter().any(|nested| match nested {
+                NestedMeta::Meta(meta) => meta.path().is_ident("C"),
+                _ => false,
+            }),
+            _ => false,
+        })
+}
+
+fn is_scalar(ty: &Type) -> bool {
+    match ty {
+        Type::Path(path) if path.qself.is_none() => SCALARS.iter().any(|s| path.path.is_ident(s)),
+        _ => false,
+    }
+}
+
+fn check_type(ty: &Type) -> Result<(), Error> {
+    match ty {
+        Type::Array(array) => check_type(&array.elem),
+        ty if is_scalar(ty) => Ok(()),
+        ty => Err(Error::new(
+            ty.span(),
+            "Serializable fields must be integer or float scalars or arrays of them",
+        )),
+    }
+}
+
+/// Writes the big-endian encoding of `value` of type `ty` to `bs` at `pos`
+/// and advances `pos`.
+fn write(ty: &Type, value: TokenStream2) -> TokenStream2 {
+    match ty {
+        Type::Array(array) => {
+            let elem = write(&array.elem, quote!((*x)));
+            quote_spanned! {ty.span()=>
+                for x in #value.iter() {
+                    #elem
+                }
+            }
+        }
+        _ => quote_spanned! {ty.span()=>
+            {
+                const LEN: usize = ::std::mem::size_of::<#ty>();
+                bs[pos..pos + LEN].copy_from_slice(&#value.to_be_bytes());
+                pos += LEN;
+            }
+        },
+    }
+}
+
+/// Reads a value of type `ty` from `bs` at `pos` and advances `pos`.
+fn read(ty: &Type) -> TokenStream2 {
+    match ty {
+        Type::Array(array) => {
+            let zero = zero(ty);
+            let elem = read(&array.elem);
+            quote_spanned! {ty.span()=>
+                {
+                    let mut array = #zero;
+                    for x in array.iter_mut() {
+                        *x = #elem;
+                    }
+                    array
+                }
+            }
+        }
+        _ => quote_spanned! {ty.span()=>
+            {
+                const LEN: usize = ::std::mem::size_of::<#ty>();
+                let mut be = [0u8; LEN];
+                be.copy_from_slice(&bs[pos..pos + LEN]);
+                pos += LEN;
+                <#ty>::from_be_bytes(be)
+            }
+        },
+    }
+}
+
+/// Returns an expression creating a zeroed value of type `ty`.
+fn zero(ty: &Type) -> TokenStream2 {
+    match ty {
+        Type::Array(array) => {
+            let elem = zero(&array.elem);
+            let len = &array.len;
+            quote!([#elem; #len])
+        }
+        _ => quote!((0 as #ty)),
+    }
+}
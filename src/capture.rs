@@ -0,0 +1,431 @@
+//! Capture of the exchanged datagrams to pcap files.
+//!
+//! Datagrams are written with synthetic IP and UDP headers and nanosecond
+//! timestamps, so the files can be opened with the usual tools. The reader
+//! also accepts captures taken with tcpdump, e.g. on `lo`.
+
+use byteorder::{BigEndian, ByteOrder, LittleEndian, ReadBytesExt, WriteBytesExt};
+use std::fs::File;
+use std::io::{self, BufWriter, Read, Write};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::path::Path;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// The link type of packets starting with an IPv4 or IPv6 header.
+pub const LINKTYPE_RAW: u32 = 101;
+
+const LINKTYPE_NULL: u32 = 0;
+const LINKTYPE_ETHERNET: u32 = 1;
+const LINKTYPE_LINUX_SLL: u32 = 113;
+const LINKTYPE_IPV4: u32 = 228;
+const LINKTYPE_IPV6: u32 = 229;
+const LINKTYPE_LINUX_SLL2: u32 = 276;
+
+const MAGIC_MICROS: u32 = 0xA1B2_C3D4;
+const MAGIC_NANOS: u32 = 0xA1B2_3C4D;
+const SNAPLEN: u32 = 65535;
+
+const IPV4_HEADER_LEN: usize = 20;
+const IPV6_HEADER_LEN: usize = 40;
+const UDP_HEADER_LEN: usize = 8;
+const PROTO_UDP: u8 = 17;
+const ETHERTYPE_IPV4: u16 = 0x0800;
+const ETHERTYPE_IPV6: u16 = 0x86DD;
+
+/// Writes datagrams to a pcap file.
+pub struct PcapWriter<W: Write> {
+    wtr: W,
+}
+
+impl PcapWriter<BufWriter<File>> {
+    /// Creates the pcap file at `path`, replacing an existing one.
+    pub fn create<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        Self::new(BufWriter::new(File::create(path)?))
+    }
+}
+
+impl<W: Write> PcapWriter<W> {
+    /// Writes the file header to `wtr`.
+    pub fn new(mut wtr: W) -> io::Result<Self> {
+        wtr.write_u32::<LittleEndian>(MAGIC_NANOS)?;
+        wtr.write_u16::<LittleEndian>(2)?;
+        wtr.write_u16::<LittleEndian>(4)?;
+        // The timezone offset and the accuracy of the timestamps.
+        wtr.write_i32::<LittleEndian>(0)?;
+        wtr.write_u32::<LittleEndian>(0)?;
+        wtr.write_u32::<LittleEndian>(SNAPLEN)?;
+        wtr.write_u32::<LittleEndian>(LINKTYPE_RAW)?;
+        Ok(Self { wtr })
+    }
+
+    /// Writes the datagram `bs` sent from `src` to `dst` at `ts`. Both
+    /// addresses have to be of the same IP version.
+    pub fn write(
+        &mut self,
+        ts: SystemTime,
+        src: SocketAddr,
+        dst: SocketAddr,
+        bs: &[u8],
+    ) -> io::Result<()> {
+        let mut header = [0; IPV6_HEADER_LEN + UDP_HEADER_LEN];
+        let header = ip_udp_header(&mut header, src, dst, bs)?;
+        let len = (header.len() + bs.len()) as u32;
+        let ts = ts.duration_since(UNIX_EPOCH).unwrap_or_default();
+        self.wtr.write_u32::<LittleEndian>(ts.as_secs() as u32)?;
+        self.wtr.write_u32::<LittleEndian>(ts.subsec_nanos())?;
+        self.wtr.write_u32::<LittleEndian>(len)?;
+        self.wtr.write_u32::<LittleEndian>(len)?;
+        self.wtr.write_all(header)?;
+        self.wtr.write_all(bs)
+    }
+
+    pub fn flush(&mut self) -> io::Result<()> {
+        self.wtr.flush()
+    }
+}
+
+/// Writes the IP and UDP header of the datagram `bs` into `buf` and returns
+/// the part of it that was written.
+fn ip_udp_header<'a>(
+    buf: &'a mut [u8],
+    src: SocketAddr,
+    dst: SocketAddr,
+    bs: &[u8],
+) -> io::Result<&'a [u8]> {
+    let udp_len = UDP_HEADER_LEN + bs.len();
+    let (ip_len, pseudo) = match (src.ip(), dst.ip()) {
+        (IpAddr::V4(src_ip), IpAddr::V4(dst_ip)) => {
+            if IPV4_HEADER_LEN + udp_len > usize::from(u16::MAX) {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    "datagram too long",
+                ));
+            }
+            let ip = &mut buf[..IPV4_HEADER_LEN];
+            ip[0] = 0x45;
+            BigEndian::write_u16(&mut ip[2..4], (IPV4_HEADER_LEN + udp_len) as u16);
+            // Don't fragment.
+            ip[6] = 0x40;
+            ip[8] = 64;
+            ip[9] = PROTO_UDP;
+            ip[12..16].copy_from_slice(&src_ip.octets());
+            ip[16..20].copy_from_slice(&dst_ip.octets());
+            let checksum = !fold(sum(ip));
+            BigEndian::write_u16(&mut ip[10..12], checksum);
+            (IPV4_HEADER_LEN, sum(&ip[12..20]))
+        }
+        (IpAddr::V6(src_ip), IpAddr::V6(dst_ip)) => {
+            if udp_len > usize::from(u16::MAX) {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    "datagram too long",
+                ));
+            }
+            let ip = &mut buf[..IPV6_HEADER_LEN];
+            ip[0] = 0x60;
+            BigEndian::write_u16(&mut ip[4..6], udp_len as u16);
+            ip[6] = PROTO_UDP;
+            ip[7] = 64;
+            ip[8..24].copy_from_slice(&src_ip.octets());
+            ip[24..40].copy_from_slice(&dst_ip.octets());
+            (IPV6_HEADER_LEN, sum(&ip[8..40]))
+        }
+        _ => {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "addresses of different IP versions",
+            ))
+        }
+    };
+    let udp = &mut buf[ip_len..ip_len + UDP_HEADER_LEN];
+    BigEndian::write_u16(&mut udp[0..2], src.port());
+    BigEndian::write_u16(&mut udp[2..4], dst.port());
+    BigEndian::write_u16(&mut udp[4..6], udp_len as u16);
+    let checksum = !fold(pseudo + u64::from(PROTO_UDP) + udp_len as u64 + sum(udp) + sum(bs));
+    // A computed checksum of zero is sent as all ones.
+    let checksum = if checksum == 0 { 0xFFFF } else { checksum };
+    BigEndian::write_u16(&mut udp[6..8], checksum);
+    Ok(&buf[..ip_len + UDP_HEADER_LEN])
+}
+
+/// Sums the big-endian 16 bit words of `bs` for the internet checksum.
+fn sum(bs: &[u8]) -> u64 {
+    let mut chunks = bs.chunks_exact(2);
+    let mut sum = chunks
+        .by_ref()
+        .map(|word| u64::from(BigEndian::read_u16(word)))
+        .sum();
+    if let [last] = chunks.remainder() {
+        sum += u64::from(*last) << 8;
+    }
+    sum
+}
+
+fn fold(mut sum: u64) -> u16 {
+    while sum > 0xFFFF {
+        sum = (sum & 0xFFFF) + (sum >> 16);
+    }
+    sum as u16
+}
+
+/// A UDP datagram read from a pcap file.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Datagram {
+    /// The capture time since the UNIX epoch.
+    pub ts: Duration,
+    pub src: SocketAddr,
+    pub dst: SocketAddr,
+    pub payload: Vec<u8>,
+}
+
+/// Reads the UDP datagrams of a pcap file. Other packets are skipped.
+pub struct PcapReader<R: Read> {
+    rdr: R,
+    big_endian: bool,
+    nanos: bool,
+    linktype: u32,
+    buf: Vec<u8>,
+}
+
+impl PcapReader<io::BufReader<File>> {
+    pub fn open<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        Self::new(io::BufReader::new(File::open(path)?))
+    }
+}
+
+impl<R: Read> PcapReader<R> {
+    /// Reads the file header from `rdr`.
+    pub fn new(mut rdr: R) -> io::Result<Self> {
+        let mut header = [0; 24];
+        rdr.read_exact(&mut header)?;
+        let (big_endian, nanos) = match LittleEndian::read_u32(&header[0..4]) {
+            MAGIC_MICROS => (false, false),
+            MAGIC_NANOS => (false, true),
+            magic if magic.swap_bytes() == MAGIC_MICROS => (true, false),
+            magic if magic.swap_bytes() == MAGIC_NANOS => (true, true),
+            _ => return Err(invalid("not a pcap file")),
+        };
+        let linktype = match big_endian {
+            false => LittleEndian::read_u32(&header[20..24]),
+            true => BigEndian::read_u32(&header[20..24]),
+        };
+        // The upper bits may hold the FCS length.
+        let linktype = linktype & 0x0FFF_FFFF;
+        match linktype {
+            LINKTYPE_NULL | LINKTYPE_ETHERNET | LINKTYPE_RAW | LINKTYPE_LINUX_SLL
+            | LINKTYPE_IPV4 | LINKTYPE_IPV6 | LINKTYPE_LINUX_SLL2 => {}
+            _ => return Err(invalid("unsupported link type")),
+        }
+        Ok(Self {
+            rdr,
+            big_endian,
+            nanos,
+            linktype,
+            buf: Vec::new(),
+        })
+    }
+
+    fn read_u32(&mut self) -> io::Result<u32> {
+        match self.big_endian {
+            false => self.rdr.read_u32::<LittleEndian>(),
+            true => self.rdr.read_u32::<BigEndian>(),
+        }
+    }
+
+    /// Reads the next UDP datagram. Returns `None` at the end of the file.
+    pub fn next_datagram(&mut self) -> io::Result<Option<Datagram>> {
+        loop {
+            let secs = match self.read_u32() {
+                Ok(secs) => secs,
+                Err(ref e) if e.kind() == io::ErrorKind::UnexpectedEof => return Ok(None),
+                Err(e) => return Err(e),
+            };
+            let fraction = self.read_u32()?;
+            let captured = self.read_u32()? as usize;
+            let _len = self.read_u32()?;
+            self.buf.resize(captured, 0);
+            self.rdr.read_exact(&mut self.buf)?;
+            let ts = match self.nanos {
+                false => Duration::new(u64::from(secs), fraction * 1000),
+                true => Duration::new(u64::from(secs), fraction),
+            };
+            if let Some(ip) = self.ip_packet() {
+                if let Some((src, dst, payload)) = parse_udp(ip) {
+                    return Ok(Some(Datagram {
+                        ts,
+                        src,
+                        dst,
+                        payload: payload.to_vec(),
+                    }));
+                }
+            }
+        }
+    }
+
+    /// Strips the link layer header of the current packet.
+    fn ip_packet(&self) -> Option<&[u8]> {
+        let bs = &self.buf[..];
+        let (ethertype, start) = match self.linktype {
+            LINKTYPE_RAW | LINKTYPE_IPV4 | LINKTYPE_IPV6 => return Some(bs),
+            // The address family in host byte order. The IP version is
+            // taken from the packet.
+            LINKTYPE_NULL => return bs.get(4..),
+            LINKTYPE_ETHERNET => (bs.get(12..14)?, 14),
+            LINKTYPE_LINUX_SLL => (bs.get(14..16)?, 16),
+            LINKTYPE_LINUX_SLL2 => (bs.get(0..2)?, 20),
+            _ => return None,
+        };
+        match BigEndian::read_u16(ethertype) {
+            ETHERTYPE_IPV4 | ETHERTYPE_IPV6 => bs.get(start..),
+            _ => None,
+        }
+    }
+}
+
+impl<R: Read> Iterator for PcapReader<R> {
+    type Item = io::Result<Datagram>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.next_datagram().transpose()
+    }
+}
+
+/// Returns the addresses and the payload of an unfragmented UDP datagram in
+/// the IP packet `bs`.
+fn parse_udp(bs: &[u8]) -> Option<(SocketAddr, SocketAddr, &[u8])> {
+    let (src, dst, udp) = match bs.first()? >> 4 {
+        4 => {
+            let header_len = usize::from(bs[0] & 0x0F) * 4;
+            let fragmented = BigEndian::read_u16(bs.get(6..8)?) & 0x3FFF != 0;
+            if *bs.get(9)? != PROTO_UDP || fragmented {
+                return None;
+            }
+            let total_len = usize::from(BigEndian::read_u16(&bs[2..4]));
+            let mut src = [0; 4];
+            let mut dst = [0; 4];
+            src.copy_from_slice(bs.get(12..16)?);
+            dst.copy_from_slice(bs.get(16..20)?);
+            (
+                IpAddr::V4(Ipv4Addr::from(src)),
+                IpAddr::V4(Ipv4Addr::from(dst)),
+                bs.get(header_len..total_len)?,
+            )
+        }
+        6 => {
+            // Extension headers are not supported.
+            if *bs.get(6)? != PROTO_UDP {
+                return None;
+            }
+            let payload_len = usize::from(BigEndian::read_u16(&bs[4..6]));
+            let mut src = [0; 16];
+            let mut dst = [0; 16];
+            src.copy_from_slice(bs.get(8..24)?);
+            dst.copy_from_slice(bs.get(24..40)?);
+            (
+                IpAddr::V6(Ipv6Addr::from(src)),
+                IpAddr::V6(Ipv6Addr::from(dst)),
+                bs.get(IPV6_HEADER_LEN..IPV6_HEADER_LEN + payload_len)?,
+            )
+        }
+        _ => return None,
+    };
+    let src_port = BigEndian::read_u16(udp.get(0..2)?);
+    let dst_port = BigEndian::read_u16(udp.get(2..4)?);
+    let udp_len = usize::from(BigEndian::read_u16(udp.get(4..6)?));
+    let payload = udp.get(UDP_HEADER_LEN..udp_len)?;
+    Some((
+        SocketAddr::new(src, src_port),
+        SocketAddr::new(dst, dst_port),
+        payload,
+    ))
+}
+
+fn invalid(msg: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+/// Whether a captured datagram was sent or received.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Direction {
+    Sent,
+    Received,
+}
+
+/// Captures the datagrams exchanged between a local socket and its peer.
+pub struct Capture<W: Write> {
+    wtr: PcapWriter<W>,
+    local: SocketAddr,
+    peer: SocketAddr,
+}
+
+impl<W: Write> Capture<W> {
+    pub fn new(wtr: PcapWriter<W>, local: SocketAddr, peer: SocketAddr) -> Self {
+        Self { wtr, local, peer }
+    }
+
+    /// Writes the datagram `bs` with the current time.
+    pub fn record(&mut self, direction: Direction, bs: &[u8]) -> io::Result<()> {
+        let (src, dst) = match direction {
+            Direction::Sent => (self.local, self.peer),
+            Direction::Received => (self.peer, self.local),
+        };
+        self.wtr.write(SystemTime::now(), src, dst, bs)
+    }
+
+    pub fn flush(&mut self) -> io::Result<()> {
+        self.wtr.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn roundtrip() {
+        let v4 = (
+            "127.0.0.1:13370".parse().unwrap(),
+            "127.0.0.1:13380".parse().unwrap(),
+        );
+        let v6 = (
+            "[::1]:13370".parse().unwrap(),
+            "[::1]:13380".parse().unwrap(),
+        );
+        let ts = UNIX_EPOCH + Duration::new(1_600_000_000, 123_456_789);
+
+        let mut wtr = PcapWriter::new(Vec::new()).unwrap();
+        wtr.write(ts, v4.0, v4.1, b"odd").unwrap();
+        wtr.write(ts, v6.1, v6.0, b"even").unwrap();
+        let bs = wtr.wtr;
+
+        // Valid checksums sum up to all ones.
+        let ip = &bs[24 + 16..24 + 16 + IPV4_HEADER_LEN];
+        assert_eq!(fold(sum(ip)), 0xFFFF);
+        let udp = &bs[24 + 16 + IPV4_HEADER_LEN..24 + 16 + IPV4_HEADER_LEN + 11];
+        let pseudo = sum(&ip[12..20]) + u64::from(PROTO_UDP) + 11;
+        assert_eq!(fold(pseudo + sum(udp)), 0xFFFF);
+
+        let datagrams = PcapReader::new(&bs[..])
+            .unwrap()
+            .collect::<io::Result<Vec<_>>>()
+            .unwrap();
+        let ts = ts.duration_since(UNIX_EPOCH).unwrap();
+        assert_eq!(
+            datagrams,
+            vec![
+                Datagram {
+                    ts,
+                    src: v4.0,
+                    dst: v4.1,
+                    payload: b"odd".to_vec(),
+                },
+                Datagram {
+                    ts,
+                    src: v6.1,
+                    dst: v6.0,
+                    payload: b"even".to_vec(),
+                },
+            ]
+        );
+    }
+}
@@ -1,6 +1,14 @@
-use serde::Deserialize;
+use crate::hoip::{
+    Compression, DelayIndicator, FecConfig, Header, Integrity, PayloadType, Profile,
+    SamplingScheme, Serializable, KEY_LEN,
+};
+use crate::k_policy::{K_MAX, K_MIN};
+use crate::playout::PlayoutConfig;
+use crate::sampling::{Sample, SamplingConfig, SAMPLE_PERIOD};
+use crate::transient::TransientErrorPolicy;
+use serde::{Deserialize, Serialize};
 use serde_yaml;
-use std::{error::Error, fs::File, path::Path};
+use std::{error::Error, fmt, fs::File, net::SocketAddr, path::Path};
 
 #[derive(Debug, Deserialize, Clone)]
 pub struct GilbertElliotConfig {
@@ -26,3 +34,477 @@ pub fn read_channel_configs<P: AsRef<Path>>(path: P) -> Result<Vec<ChannelConfig
     let rdr = File::open(path)?;
     Ok(serde_yaml::from_reader(rdr)?)
 }
+
+/// The smallest receive buffer. It holds the longest header and trailer.
+const MIN_RECV_BUFFER_SIZE: usize = 64;
+
+/// The protection of the messages, see `Integrity`. The keys are written as
+/// hex strings.
+#[derive(Clone, PartialEq, Serialize, Deserialize)]
+pub enum IntegrityConfig {
+    None,
+    Crc32,
+    Hmac {
+        #[serde(with = "hex")]
+        key: Vec<u8>,
+    },
+    ChaCha20Poly1305 {
+        #[serde(with = "hex")]
+        key: [u8; KEY_LEN],
+    },
+}
+
+impl IntegrityConfig {
+    pub fn to_integrity(&self) -> Integrity {
+        match self {
+            IntegrityConfig::None => Integrity::None,
+            IntegrityConfig::Crc32 => Integrity::Crc32,
+            IntegrityConfig::Hmac { key } => Integrity::hmac(key),
+            IntegrityConfig::ChaCha20Poly1305 { key } => Integrity::chacha20poly1305(key),
+        }
+    }
+}
+
+impl fmt::Debug for IntegrityConfig {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        // The keys are not printed.
+        match self {
+            IntegrityConfig::None => f.write_str("None"),
+            IntegrityConfig::Crc32 => f.write_str("Crc32"),
+            IntegrityConfig::Hmac { .. } => f.debug_struct("Hmac").finish_non_exhaustive(),
+            IntegrityConfig::ChaCha20Poly1305 { .. } => {
+                f.debug_struct("ChaCha20Poly1305").finish_non_exhaustive()
+            }
+        }
+    }
+}
+
+// Serializes bytes as a string of hex digits.
+mod hex {
+    use serde::de::Error;
+    use serde::{Deserialize, Deserializer, Serializer};
+    use std::convert::TryFrom;
+    use std::fmt::Write;
+
+    pub fn serialize<S: Serializer, T: AsRef<[u8]>>(
+        bs: &T,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        let bs = bs.as_ref();
+        let mut s = String::with_capacity(2 * bs.len());
+        for b in bs {
+            write!(s, "{:02x}", b).unwrap();
+        }
+        serializer.serialize_str(&s)
+    }
+
+    /// Fails if the bytes don't fit into `T`, e.g. a key of fixed length.
+    pub fn deserialize<'de, D: Deserializer<'de>, T: TryFrom<Vec<u8>>>(
+        deserializer: D,
+    ) -> Result<T, D::Error> {
+        let s = String::deserialize(deserializer)?;
+        if s.len() % 2 != 0 || !s.is_ascii() {
+            return Err(D::Error::custom("expected an even number of hex digits"));
+        }
+        let bs = (0..s.len())
+            .step_by(2)
+            .map(|i| u8::from_str_radix(&s[i..i + 2], 16).map_err(D::Error::custom))
+            .collect::<Result<Vec<u8>, _>>()?;
+        let len = bs.len();
+        T::try_from(bs).map_err(|_| D::Error::invalid_length(len, &"a key of the expected length"))
+    }
+}
+
+/// The configuration of a `NetworkModule`. Fields that are missing when it
+/// is deserialized take their default value.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct NetworkModuleConfig {
+    /// The address of the peer.
+    pub dest_addr: String,
+    /// The address the socket is bound to.
+    pub src_addr: String,
+    /// The payload type of the sent messages.
+    pub op: PayloadType,
+    /// The maximum number of sent messages per second.
+    pub rate: f64,
+    /// The weight of the exponential moving average of the rott.
+    pub w: f64,
+    /// The number of rott updates a congestion state is kept for at least.
+    pub cooloff: usize,
+    /// The maximum number of samples per message, between `K_MIN` and
+    /// `K_MAX`. Larger values need the extended header and can be set with
+    /// `NetworkModule::set_k_max`.
+    pub k_max: i8,
+    pub sampling: SamplingConfig,
+    pub delay_indicator: DelayIndicator,
+    pub compression: Option<Compression>,
+    pub compact_header: bool,
+    pub redundancy: u8,
+    pub fec: FecConfig,
+    pub integrity: IntegrityConfig,
+    /// The interval of the receiver reports in [us], if any.
+    pub report_interval_micros: Option<u64>,
+    /// The size of the buffer datagrams are received into in bytes. Longer
+    /// datagrams are truncated.
+    pub recv_buffer_size: usize,
+    /// The period between two samples in [us]. The peer must use the same,
+    /// which is checked in the handshake.
+    pub sample_period_micros: u64,
+    /// Which transient socket errors are returned.
+    pub transient_errors: TransientErrorPolicy,
+    /// The playout buffer of the received samples, if any.
+    pub playout: Option<PlayoutConfig>,
+}
+
+impl Default for NetworkModuleConfig {
+    fn default() -> Self {
+        Self {
+            dest_addr: "127.0.0.1:13380".to_string(),
+            src_addr: "127.0.0.1:13370".to_string(),
+            op: PayloadType::Master,
+            rate: 1000.0,
+            w: 0.1,
+            cooloff: 10,
+            k_max: K_MAX,
+            sampling: SamplingConfig::Lossless,
+            delay_indicator: DelayIndicator::InHeader,
+            compression: None,
+            compact_header: false,
+            redundancy: 0,
+            fec: FecConfig::None,
+            integrity: IntegrityConfig::None,
+            report_interval_micros: None,
+            recv_buffer_size: 4096,
+            sample_period_micros: SAMPLE_PERIOD,
+            transient_errors: TransientErrorPolicy::Tolerate,
+            playout: None,
+        }
+    }
+}
+
+/// An invalid field of a `NetworkModuleConfig`.
+#[derive(Debug, Clone, PartialEq)]
+pub enum ConfigError {
+    /// An address is not of the form `ip:port`.
+    Address(String),
+    /// The rate is not positive.
+    Rate(f64),
+    /// The weight is not between 0 and 1.
+    Weight(f64),
+    /// The maximum number of samples per message is not between `K_MIN` and
+    /// `K_MAX`.
+    KMax(i8),
+    /// The scale of the compression is not positive and finite.
+    Compression(Compression),
+    /// A forward error correction over no messages.
+    Fec,
+    /// The receive buffer is smaller than the longest header and trailer.
+    RecvBufferSize(usize),
+    /// The longest data message of `len` bytes doesn't fit into the receive
+    /// buffer.
+    MessageLen { len: usize, recv_buffer_size: usize },
+    /// The sample period is zero.
+    SamplePeriod,
+    /// The Weber fraction is negative or the level of level crossing is not
+    /// positive.
+    Sampling(SamplingConfig),
+    /// The playout delay has no valid range.
+    Playout,
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ConfigError::Address(addr) => write!(f, "invalid address {:?}", addr),
+            ConfigError::Rate(rate) => write!(f, "rate {} is not positive", rate),
+            ConfigError::Weight(w) => write!(f, "weight {} is not between 0 and 1", w),
+            ConfigError::KMax(k_max) => {
+                write!(f, "k max {} is not between {} and {}", k_max, K_MIN, K_MAX)
+            }
+            ConfigError::Compression(compression) => {
+                write!(f, "invalid compression {:?}", compression)
+            }
+            ConfigError::Fec => write!(f, "forward error correction over no messages"),
+            ConfigError::RecvBufferSize(size) => write!(
+                f,
+                "receive buffer of {} bytes is smaller than {} bytes",
+                size, MIN_RECV_BUFFER_SIZE
+            ),
+            ConfigError::MessageLen {
+                len,
+                recv_buffer_size,
+            } => write!(
+                f,
+                "message of {} bytes doesn't fit into the receive buffer of {} bytes",
+                len, recv_buffer_size
+            ),
+            ConfigError::SamplePeriod => write!(f, "sample period is zero"),
+            ConfigError::Sampling(sampling) => write!(f, "invalid sampling {:?}", sampling),
+            ConfigError::Playout => write!(f, "invalid playout delay"),
+        }
+    }
+}
+
+impl Error for ConfigError {}
+
+impl NetworkModuleConfig {
+    /// Reads the configuration from a YAML file.
+    pub fn read<P: AsRef<Path>>(path: P) -> Result<Self, Box<dyn Error>> {
+        let rdr = File::open(path)?;
+        let config: Self = serde_yaml::from_reader(rdr)?;
+        config.validate()?;
+        Ok(config)
+    }
+
+    /// Checks that every field has a usable value.
+    pub fn validate(&self) -> Result<(), ConfigError> {
+        for addr in [&self.dest_addr, &self.src_addr].iter() {
+            if addr.parse::<SocketAddr>().is_err() {
+                return Err(ConfigError::Address(addr.to_string()));
+            }
+        }
+        if self.rate.is_nan() || self.rate <= 0.0 {
+            return Err(ConfigError::Rate(self.rate));
+        }
+        if self.w.is_nan() || self.w <= 0.0 || self.w >= 1.0 {
+            return Err(ConfigError::Weight(self.w));
+        }
+        if self.k_max < K_MIN || self.k_max > K_MAX {
+            return Err(ConfigError::KMax(self.k_max));
+        }
+        if let Some(compression) = self.compression {
+            // NaNs fail every comparison.
+            if !(compression.scale.is_finite() && compression.scale > 0.0) {
+                return Err(ConfigError::Compression(compression));
+            }
+        }
+        if self.fec == (FecConfig::Xor { n: 0 }) {
+            return Err(ConfigError::Fec);
+        }
+        if self.recv_buffer_size < MIN_RECV_BUFFER_SIZE {
+            return Err(ConfigError::RecvBufferSize(self.recv_buffer_size));
+        }
+        if self.sample_period_micros == 0 {
+            return Err(ConfigError::SamplePeriod);
+        }
+        let valid = match self.sampling {
+            SamplingConfig::Lossless => true,
+            SamplingConfig::Weber { fraction, .. } => fraction >= 0.0,
+            SamplingConfig::LevelCrossing { level } => level > 0.0,
+        };
+        // NaNs fail every comparison.
+        if !valid {
+            return Err(ConfigError::Sampling(self.sampling));
+        }
+        if let Some(playout) = &self.playout {
+            if playout.jitter_factor.is_nan()
+                || playout.jitter_factor < 0.0
+                || playout.target_latency_micros > playout.max_latency_micros
+            {
+                return Err(ConfigError::Playout);
+            }
+        }
+        Ok(())
+    }
+
+    /// Checks that a data message with `k_max` samples of type `T` and the
+    /// `redundancy` repeated ones fits into the receive buffer. The peer is
+    /// expected to send with the same configuration.
+    pub fn validate_message_len<T: Serializable + Sample>(&self) -> Result<(), ConfigError> {
+        let header = Header {
+            payload_type: self.op,
+            sampling_scheme: SamplingScheme::Lossless,
+            num_samples: self.k_max as u8,
+            delay_indicator: self.delay_indicator,
+            threshold: 0,
+            rott: 0,
+            timestamp: 0,
+            sequence: 0,
+            redundancy: self.redundancy,
+            compression: self.compression,
+            profile: Profile::Standard,
+        };
+        let len = header.encoded_len()
+            + header.payload_len::<T>()
+            + self.integrity.to_integrity().trailer_len();
+        if len > self.recv_buffer_size {
+            return Err(ConfigError::MessageLen {
+                len,
+                recv_buffer_size: self.recv_buffer_size,
+            });
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hoip::{PayloadM2S, Width};
+    use crate::sampling::Deadband;
+
+    #[test]
+    fn network_module_config() {
+        let config: NetworkModuleConfig = serde_yaml::from_str(
+            "
+op: Slave
+rate: 500
+transient_errors:
+  Limit:
+    max: 10
+playout:
+  target_latency_micros: 3000
+fec:
+  Xor:
+    n: 4
+integrity:
+  Hmac:
+    key: 736563726574
+",
+        )
+        .unwrap();
+        assert_eq!(config.op, PayloadType::Slave);
+        assert_eq!(config.rate, 500.0);
+        assert_eq!(config.fec, FecConfig::Xor { n: 4 });
+        assert_eq!(
+            config.integrity,
+            IntegrityConfig::Hmac {
+                key: b"secret".to_vec()
+            }
+        );
+        assert_eq!(
+            config.transient_errors,
+            TransientErrorPolicy::Limit { max: 10 }
+        );
+        assert_eq!(
+            config.playout,
+            Some(PlayoutConfig {
+                target_latency_micros: 3000,
+                ..PlayoutConfig::default()
+            })
+        );
+        assert_eq!(config.w, NetworkModuleConfig::default().w);
+        assert_eq!(config.validate(), Ok(()));
+
+        let yaml = serde_yaml::to_string(&config).unwrap();
+        assert_eq!(
+            serde_yaml::from_str::<NetworkModuleConfig>(&yaml).unwrap(),
+            config
+        );
+
+        let invalid = NetworkModuleConfig {
+            src_addr: "localhost".to_string(),
+            ..config.clone()
+        };
+        assert_eq!(
+            invalid.validate(),
+            Err(ConfigError::Address("localhost".to_string()))
+        );
+        let invalid = NetworkModuleConfig {
+            w: 1.0,
+            ..config.clone()
+        };
+        assert_eq!(invalid.validate(), Err(ConfigError::Weight(1.0)));
+        for k_max in [0, K_MAX + 1].iter() {
+            let invalid = NetworkModuleConfig {
+                k_max: *k_max,
+                ..config.clone()
+            };
+            assert_eq!(invalid.validate(), Err(ConfigError::KMax(*k_max)));
+        }
+        for level in [0.0, -0.5, f32::NAN].iter() {
+            let sampling = SamplingConfig::LevelCrossing { level: *level };
+            let invalid = NetworkModuleConfig {
+                sampling,
+                ..config.clone()
+            };
+            assert!(matches!(invalid.validate(), Err(ConfigError::Sampling(_))));
+        }
+        for fraction in [-0.1, f32::NAN].iter() {
+            let sampling = SamplingConfig::Weber {
+                fraction: *fraction,
+                deadband: Deadband::Norm,
+            };
+            let invalid = NetworkModuleConfig {
+                sampling,
+                ..config.clone()
+            };
+            assert!(matches!(invalid.validate(), Err(ConfigError::Sampling(_))));
+        }
+        assert!(serde_yaml::from_str::<IntegrityConfig>("Hmac:\n  key: 7g").is_err());
+        for scale in [0.0, -0.5, f32::NAN, f32::INFINITY].iter() {
+            let compression = Compression {
+                scale: *scale,
+                width: Width::I16,
+                delta: None,
+            };
+            let invalid = NetworkModuleConfig {
+                compression: Some(compression),
+                ..config.clone()
+            };
+            assert!(matches!(
+                invalid.validate(),
+                Err(ConfigError::Compression(_))
+            ));
+        }
+    }
+
+    #[test]
+    fn message_len() {
+        let config = NetworkModuleConfig {
+            recv_buffer_size: 128,
+            ..NetworkModuleConfig::default()
+        };
+        assert_eq!(config.validate_message_len::<PayloadM2S>(), Ok(()));
+        // 16 bytes of header, an options byte, a redundancy byte and six
+        // samples of 24 bytes.
+        let invalid = NetworkModuleConfig {
+            redundancy: 2,
+            ..config.clone()
+        };
+        assert_eq!(
+            invalid.validate_message_len::<PayloadM2S>(),
+            Err(ConfigError::MessageLen {
+                len: 162,
+                recv_buffer_size: 128
+            })
+        );
+        // Compressed to two bytes per value, the samples take 12 bytes each.
+        let compressed = NetworkModuleConfig {
+            compression: Some(Compression {
+                scale: 0.01,
+                width: Width::I16,
+                delta: None,
+            }),
+            ..invalid
+        };
+        assert_eq!(compressed.validate_message_len::<PayloadM2S>(), Ok(()));
+    }
+
+    #[test]
+    fn integrity_config() {
+        let integrity = IntegrityConfig::ChaCha20Poly1305 {
+            key: [0xab; KEY_LEN],
+        };
+        let yaml = serde_yaml::to_string(&integrity).unwrap();
+        assert!(yaml.contains(&"ab".repeat(KEY_LEN)));
+        assert_eq!(
+            serde_yaml::from_str::<IntegrityConfig>(&yaml).unwrap(),
+            integrity
+        );
+        let short = "ChaCha20Poly1305:\n  key: abab";
+        assert!(serde_yaml::from_str::<IntegrityConfig>(short).is_err());
+
+        // The keys are not printed.
+        let config = NetworkModuleConfig {
+            integrity,
+            ..NetworkModuleConfig::default()
+        };
+        assert!(!format!("{:?}", config).contains("171"));
+        let integrity = IntegrityConfig::Hmac {
+            key: b"secret".to_vec(),
+        };
+        assert_eq!(format!("{:?}", integrity), "Hmac { .. }");
+    }
+}
@@ -1,3 +1,5 @@
+use crate::hoip::ReceiverReport;
+
 mod biaz;
 mod trend;
 mod window;
@@ -8,7 +10,11 @@ pub use trend::Trend;
 pub use window::Window;
 pub use zig_zag::ZigZag;
 
-#[derive(Debug, Clone, Copy)]
+/// The fraction of lost messages in a receiver report above which the
+/// network is considered congested.
+pub const LOSS_THRESHOLD: f64 = 0.05;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub enum CongestionState {
     // The algorithm is not sure.
     NotSure,
@@ -26,4 +32,10 @@ pub trait CongestionDetector {
         std_rott: f64,
         prev_rott: u32,
     ) -> CongestionState;
+
+    /// Informs the detector about a receiver report of the peer on the sent
+    /// messages. A returned state replaces the current one.
+    fn on_report(&mut self, _report: &ReceiverReport) -> Option<CongestionState> {
+        None
+    }
 }
@@ -1,4 +1,5 @@
-use super::{CongestionDetector, CongestionState};
+use super::{CongestionDetector, CongestionState, LOSS_THRESHOLD};
+use crate::hoip::ReceiverReport;
 
 pub struct Window {
     // The number of samples inside of a window.
@@ -92,4 +93,14 @@ impl CongestionDetector for Window {
             return CongestionState::NotSure;
         }
     }
+
+    /// Detects congestion if the peer lost more than `LOSS_THRESHOLD` of the
+    /// messages since its previous report.
+    fn on_report(&mut self, report: &ReceiverReport) -> Option<CongestionState> {
+        if report.loss() > LOSS_THRESHOLD {
+            self.reset();
+            return Some(CongestionState::Congested);
+        }
+        None
+    }
 }
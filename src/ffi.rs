@@ -1,51 +1,123 @@
-use crate::hoip::{PayloadM2S, PayloadS2M, PayloadType};
+use crate::hoip::{
+    Integrity, Payload, Payload6DoFM2S, Payload6DoFS2M, Payload7DoFM2S, Payload7DoFS2M, PayloadM2S,
+    PayloadS2M, PayloadType, Serializable, KEY_LEN,
+};
 use crate::k_policy::KPolicySDMI;
-use crate::{congestion_detection, NetworkModule};
+use crate::sampling::Sample;
+use crate::{congestion_detection, NetworkModule, NetworkModuleConfig};
 
-type MasterNetworkModule =
-    NetworkModule<PayloadM2S, PayloadS2M, congestion_detection::Window, KPolicySDMI>;
-type SlaveNetworkModule =
-    NetworkModule<PayloadS2M, PayloadM2S, congestion_detection::Window, KPolicySDMI>;
+/// The number of values of the generic payloads that are sent in both
+/// directions by the `values_*` functions.
+pub const FFI_NUM_VALUES: usize = 16;
 
-#[no_mangle]
-pub unsafe extern "C" fn master_network_module_new(rate: f64) -> *mut MasterNetworkModule {
-    let w = 0.1;
+type Values = Payload<FFI_NUM_VALUES>;
+
+/// A network module sending `FFI_NUM_VALUES` values in both directions. It
+/// is opaque, as the bindings can't express the generic payload.
+pub struct ValuesNetworkModule(FfiNetworkModule<Values, Values>);
+
+type FfiNetworkModule<S, R> = NetworkModule<S, R, congestion_detection::Window, KPolicySDMI>;
+
+fn new_network_module<S, R>(
+    dest_addr: &str,
+    src_addr: &str,
+    op: PayloadType,
+    rate: f64,
+) -> *mut FfiNetworkModule<S, R>
+where
+    S: Serializable + Sample,
+    R: Serializable + Sample,
+{
+    let config = NetworkModuleConfig {
+        dest_addr: dest_addr.to_string(),
+        src_addr: src_addr.to_string(),
+        op,
+        rate,
+        w: 0.1,
+        cooloff: 10,
+        ..NetworkModuleConfig::default()
+    };
     let congestion_detector = congestion_detection::Window::new(5);
-    let k_policy = KPolicySDMI {};
-    let network_module = NetworkModule::<_, _, _, _>::new(
+    let k_policy = KPolicySDMI::new();
+    match NetworkModule::from_config(config, congestion_detector, k_policy) {
+        Ok(network_module) => Box::into_raw(Box::new(network_module)),
+        Err(_) => std::ptr::null_mut(),
+    }
+}
+
+/// Creates a network module that sends master payloads.
+fn new_master<S, R>(rate: f64) -> *mut FfiNetworkModule<S, R>
+where
+    S: Serializable + Sample,
+    R: Serializable + Sample,
+{
+    new_network_module(
         "127.0.0.1:13380",
         "127.0.0.1:13370",
-        congestion_detector,
-        k_policy,
-        w,
-        10,
         PayloadType::Master,
         rate,
-    );
-    Box::into_raw(Box::new(network_module))
+    )
 }
 
-#[no_mangle]
-pub unsafe extern "C" fn master_network_module_send(
-    network_module: *mut MasterNetworkModule,
-    payload: PayloadM2S,
-) {
-    assert!(!network_module.is_null());
-    let network_module = &mut *network_module;
-    network_module.send(payload)
+/// Creates a network module that sends slave payloads.
+fn new_slave<S, R>(rate: f64) -> *mut FfiNetworkModule<S, R>
+where
+    S: Serializable + Sample,
+    R: Serializable + Sample,
+{
+    new_network_module(
+        "127.0.0.1:13370",
+        "127.0.0.1:13380",
+        PayloadType::Slave,
+        rate,
+    )
 }
 
-#[no_mangle]
-pub unsafe extern "C" fn master_network_module_try_recv(
-    network_module: *mut MasterNetworkModule,
-    payload: *mut PayloadS2M,
-) -> bool {
+/// Creates a network module with `new` that encrypts its packets with the
+/// `KEY_LEN` bytes `key` points to. Returns null if `key` is null or the
+/// network module couldn't be created.
+unsafe fn new_encrypted<S, R>(
+    new: impl FnOnce() -> *mut FfiNetworkModule<S, R>,
+    key: *const u8,
+) -> *mut FfiNetworkModule<S, R>
+where
+    S: Serializable + Sample,
+    R: Serializable + Sample,
+{
+    if key.is_null() {
+        return std::ptr::null_mut();
+    }
+    let network_module = new();
+    if network_module.is_null() {
+        return network_module;
+    }
+    let key = &*(key as *const [u8; KEY_LEN]);
+    (*network_module).set_integrity(Integrity::chacha20poly1305(key));
+    network_module
+}
+
+/// Wraps the network module behind `network_module`, if any.
+unsafe fn wrap_values(
+    network_module: *mut FfiNetworkModule<Values, Values>,
+) -> *mut ValuesNetworkModule {
+    if network_module.is_null() {
+        return std::ptr::null_mut();
+    }
+    let network_module = Box::from_raw(network_module);
+    Box::into_raw(Box::new(ValuesNetworkModule(*network_module)))
+}
+
+unsafe fn try_recv<S, R>(network_module: *mut FfiNetworkModule<S, R>, payload: *mut R) -> bool
+where
+    S: Serializable + Sample,
+    R: Serializable + Sample,
+{
     assert!(!network_module.is_null());
     assert!(!payload.is_null());
     let network_module = &mut *network_module;
     let payload = &mut *payload;
 
-    if let Some((_, received_payload)) = network_module.try_recv() {
+    if let Ok(Some((_, received_payload))) = network_module.try_recv() {
         *payload = received_payload;
         true
     } else {
@@ -53,98 +125,95 @@ pub unsafe extern "C" fn master_network_module_try_recv(
     }
 }
 
+// Defines the extern functions like they are written.
+macro_rules! items {
+    ($($item:item)*) => {
+        $($item)*
+    };
+}
+
+include!("ffi_network_modules.rs");
+
+ffi_network_modules!(items);
+
 #[no_mangle]
-pub unsafe extern "C" fn master_network_module_rate(
-    network_module: *mut MasterNetworkModule,
-) -> f64 {
-    assert!(!network_module.is_null());
-    let network_module = &mut *network_module;
-    network_module.rate()
+pub unsafe extern "C" fn values_master_network_module_new(rate: f64) -> *mut ValuesNetworkModule {
+    wrap_values(new_master(rate))
 }
 
 #[no_mangle]
-pub unsafe extern "C" fn master_network_module_set_rate(
-    network_module: *mut MasterNetworkModule,
+pub unsafe extern "C" fn values_master_network_module_new_encrypted(
     rate: f64,
-) {
-    assert!(!network_module.is_null());
-    let network_module = &mut *network_module;
-    network_module.set_rate(rate);
+    key: *const u8,
+) -> *mut ValuesNetworkModule {
+    wrap_values(new_encrypted(|| new_master(rate), key))
 }
 
 #[no_mangle]
-pub unsafe extern "C" fn master_network_module_free(network_module: *mut MasterNetworkModule) {
-    if !network_module.is_null() {
-        Box::from_raw(network_module);
-    }
+pub unsafe extern "C" fn values_slave_network_module_new(rate: f64) -> *mut ValuesNetworkModule {
+    wrap_values(new_slave(rate))
 }
 
 #[no_mangle]
-pub unsafe extern "C" fn slave_network_module_new(rate: f64) -> *mut SlaveNetworkModule {
-    let w = 0.1;
-    let congestion_detector = congestion_detection::Window::new(5);
-    let k_policy = KPolicySDMI {};
-    let network_module = NetworkModule::<_, _, _, _>::new(
-        "127.0.0.1:13370",
-        "127.0.0.1:13380",
-        congestion_detector,
-        k_policy,
-        w,
-        10,
-        PayloadType::Master,
-        rate,
-    );
-    Box::into_raw(Box::new(network_module))
+pub unsafe extern "C" fn values_slave_network_module_new_encrypted(
+    rate: f64,
+    key: *const u8,
+) -> *mut ValuesNetworkModule {
+    wrap_values(new_encrypted(|| new_slave(rate), key))
 }
 
+/// Sends the `FFI_NUM_VALUES` values `values` points to.
 #[no_mangle]
-pub unsafe extern "C" fn slave_network_module_send(
-    network_module: *mut SlaveNetworkModule,
-    payload: PayloadS2M,
-) {
+pub unsafe extern "C" fn values_network_module_send(
+    network_module: *mut ValuesNetworkModule,
+    values: *const f32,
+) -> bool {
     assert!(!network_module.is_null());
-    let network_module = &mut *network_module;
-    network_module.send(payload);
+    assert!(!values.is_null());
+    let network_module = &mut (*network_module).0;
+    let values = *(values as *const [f32; FFI_NUM_VALUES]);
+    network_module.send(Payload::new(values)).is_ok()
 }
 
+/// Writes the `FFI_NUM_VALUES` values of the next received sample to
+/// `values`.
 #[no_mangle]
-pub unsafe extern "C" fn slave_network_module_try_recv(
-    network_module: *mut SlaveNetworkModule,
-    payload: *mut PayloadM2S,
+pub unsafe extern "C" fn values_network_module_try_recv(
+    network_module: *mut ValuesNetworkModule,
+    values: *mut f32,
 ) -> bool {
     assert!(!network_module.is_null());
-    assert!(!payload.is_null());
-    let network_module = &mut *network_module;
-    let payload = &mut *payload;
-
-    if let Some((_, received_payload)) = network_module.try_recv() {
-        *payload = received_payload;
-        true
-    } else {
-        false
+    assert!(!values.is_null());
+    let mut payload = Values::default();
+    if !try_recv(&mut (*network_module).0, &mut payload) {
+        return false;
     }
+    *(values as *mut [f32; FFI_NUM_VALUES]) = payload.values();
+    true
 }
 
 #[no_mangle]
-pub unsafe extern "C" fn slave_network_module_rate(network_module: *mut SlaveNetworkModule) -> f64 {
+pub unsafe extern "C" fn values_network_module_rate(
+    network_module: *mut ValuesNetworkModule,
+) -> f64 {
     assert!(!network_module.is_null());
-    let network_module = &mut *network_module;
+    let network_module = &mut (*network_module).0;
     network_module.rate()
 }
 
 #[no_mangle]
-pub unsafe extern "C" fn slave_network_module_set_rate(
-    network_module: *mut SlaveNetworkModule,
+pub unsafe extern "C" fn values_network_module_set_rate(
+    network_module: *mut ValuesNetworkModule,
     rate: f64,
 ) {
     assert!(!network_module.is_null());
-    let network_module = &mut *network_module;
+    let network_module = &mut (*network_module).0;
     network_module.set_rate(rate);
 }
 
 #[no_mangle]
-pub unsafe extern "C" fn slave_network_free(network_module: *mut SlaveNetworkModule) {
+pub unsafe extern "C" fn values_network_module_free(network_module: *mut ValuesNetworkModule) {
     if !network_module.is_null() {
-        Box::from_raw(network_module);
+        drop(Box::from_raw(network_module));
     }
 }
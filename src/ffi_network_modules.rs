@@ -0,0 +1,169 @@
+// The extern functions of the network modules of the `#[repr(C)]` payload
+// families. This file is included by `ffi.rs`, which defines the functions,
+// and by the build script, which passes their source to cbindgen as it
+// doesn't expand macros. `$emit` receives the generated items.
+
+/// Defines the extern functions of a network module of the type `$module`,
+/// which is created by `$new_fn` and sends `$send`.
+macro_rules! network_module_fns {
+    (
+        $emit:ident,
+        $module:ident,
+        $new_fn:ident,
+        $send:ty,
+        $recv:ty,
+        new: $new:ident,
+        new_encrypted: $new_encrypted:ident,
+        send: $send_fn:ident,
+        try_recv: $try_recv:ident,
+        rate: $rate:ident,
+        set_rate: $set_rate:ident,
+        free: $free:ident $(,)?
+    ) => {
+        $emit! {
+            #[no_mangle]
+            pub unsafe extern "C" fn $new(rate: f64) -> *mut $module {
+                $new_fn(rate)
+            }
+
+            #[no_mangle]
+            pub unsafe extern "C" fn $new_encrypted(rate: f64, key: *const u8) -> *mut $module {
+                new_encrypted(|| $new(rate), key)
+            }
+
+            #[no_mangle]
+            pub unsafe extern "C" fn $send_fn(
+                network_module: *mut $module,
+                payload: $send,
+            ) -> bool {
+                assert!(!network_module.is_null());
+                let network_module = &mut *network_module;
+                network_module.send(payload).is_ok()
+            }
+
+            #[no_mangle]
+            pub unsafe extern "C" fn $try_recv(
+                network_module: *mut $module,
+                payload: *mut $recv,
+            ) -> bool {
+                try_recv(network_module, payload)
+            }
+
+            #[no_mangle]
+            pub unsafe extern "C" fn $rate(network_module: *mut $module) -> f64 {
+                assert!(!network_module.is_null());
+                let network_module = &mut *network_module;
+                network_module.rate()
+            }
+
+            #[no_mangle]
+            pub unsafe extern "C" fn $set_rate(network_module: *mut $module, rate: f64) {
+                assert!(!network_module.is_null());
+                let network_module = &mut *network_module;
+                network_module.set_rate(rate);
+            }
+
+            #[no_mangle]
+            pub unsafe extern "C" fn $free(network_module: *mut $module) {
+                if !network_module.is_null() {
+                    drop(Box::from_raw(network_module));
+                }
+            }
+        }
+    };
+}
+
+/// Defines the master and the slave network module of a payload family and
+/// their extern functions. The master sends `$m2s` and receives `$s2m`, the
+/// slave the other way round.
+macro_rules! payload_network_modules {
+    (
+        $emit:ident,
+        m2s: $m2s:ty,
+        s2m: $s2m:ty,
+        master: $master:ident { $($master_fns:tt)* },
+        slave: $slave:ident { $($slave_fns:tt)* } $(,)?
+    ) => {
+        $emit! {
+            type $master = NetworkModule<$m2s, $s2m, congestion_detection::Window, KPolicySDMI>;
+            type $slave = NetworkModule<$s2m, $m2s, congestion_detection::Window, KPolicySDMI>;
+        }
+        network_module_fns!($emit, $master, new_master, $m2s, $s2m, $($master_fns)*);
+        network_module_fns!($emit, $slave, new_slave, $s2m, $m2s, $($slave_fns)*);
+    };
+}
+
+/// Defines the network modules of all payload families.
+macro_rules! ffi_network_modules {
+    ($emit:ident) => {
+        payload_network_modules! {
+            $emit,
+            m2s: PayloadM2S,
+            s2m: PayloadS2M,
+            master: MasterNetworkModule {
+                new: master_network_module_new,
+                new_encrypted: master_network_module_new_encrypted,
+                send: master_network_module_send,
+                try_recv: master_network_module_try_recv,
+                rate: master_network_module_rate,
+                set_rate: master_network_module_set_rate,
+                free: master_network_module_free,
+            },
+            slave: SlaveNetworkModule {
+                new: slave_network_module_new,
+                new_encrypted: slave_network_module_new_encrypted,
+                send: slave_network_module_send,
+                try_recv: slave_network_module_try_recv,
+                rate: slave_network_module_rate,
+                set_rate: slave_network_module_set_rate,
+                free: slave_network_free,
+            },
+        }
+        payload_network_modules! {
+            $emit,
+            m2s: Payload7DoFM2S,
+            s2m: Payload7DoFS2M,
+            master: PoseMasterNetworkModule {
+                new: pose_master_network_module_new,
+                new_encrypted: pose_master_network_module_new_encrypted,
+                send: pose_master_network_module_send,
+                try_recv: pose_master_network_module_try_recv,
+                rate: pose_master_network_module_rate,
+                set_rate: pose_master_network_module_set_rate,
+                free: pose_master_network_module_free,
+            },
+            slave: PoseSlaveNetworkModule {
+                new: pose_slave_network_module_new,
+                new_encrypted: pose_slave_network_module_new_encrypted,
+                send: pose_slave_network_module_send,
+                try_recv: pose_slave_network_module_try_recv,
+                rate: pose_slave_network_module_rate,
+                set_rate: pose_slave_network_module_set_rate,
+                free: pose_slave_network_module_free,
+            },
+        }
+        payload_network_modules! {
+            $emit,
+            m2s: Payload6DoFM2S,
+            s2m: Payload6DoFS2M,
+            master: SixDoFMasterNetworkModule {
+                new: six_dof_master_network_module_new,
+                new_encrypted: six_dof_master_network_module_new_encrypted,
+                send: six_dof_master_network_module_send,
+                try_recv: six_dof_master_network_module_try_recv,
+                rate: six_dof_master_network_module_rate,
+                set_rate: six_dof_master_network_module_set_rate,
+                free: six_dof_master_network_module_free,
+            },
+            slave: SixDoFSlaveNetworkModule {
+                new: six_dof_slave_network_module_new,
+                new_encrypted: six_dof_slave_network_module_new_encrypted,
+                send: six_dof_slave_network_module_send,
+                try_recv: six_dof_slave_network_module_try_recv,
+                rate: six_dof_slave_network_module_rate,
+                set_rate: six_dof_slave_network_module_set_rate,
+                free: six_dof_slave_network_module_free,
+            },
+        }
+    };
+}
@@ -0,0 +1,276 @@
+use super::replay::{nonce, Counters, DATA, OTHER};
+use super::{HoipError, MessageRef, Packet, PayloadType, COUNTER_LEN, KEY_LEN};
+use byteorder::{BigEndian, ByteOrder, WriteBytesExt};
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+use std::fmt;
+
+/// The length of the truncated HMAC-SHA256 trailer in bytes.
+pub const HMAC_LEN: usize = 10;
+
+// The nonce class of handshakes, which are not counted.
+const HANDSHAKE: u8 = 2;
+
+/// The authentication of `hoip` packets with a truncated HMAC-SHA256.
+///
+/// The tag covers the direction and the counter of a packet like the nonce
+/// of `Encryption`, so packets can't be reflected to their sender and are
+/// only accepted once per session. Data messages are counted by their
+/// sequence number and other packets carry their counter in front of the
+/// tag. All packets but handshakes are tagged with the key of the session,
+/// see `rekey`. Handshakes are tagged with the pre-shared key and without a
+/// counter, so they can be replayed. A replayed handshake doesn't start a
+/// session though, as it doesn't echo the fresh session number of the
+/// receiver.
+#[derive(Clone)]
+pub struct Authentication {
+    psk: Hmac<Sha256>,
+    mac: Hmac<Sha256>,
+    // The payload type of the packets that are sealed.
+    local: PayloadType,
+    counters: Counters,
+}
+
+impl Authentication {
+    /// Creates the authentication of the packets a master sends with the
+    /// pre-shared `key`.
+    pub fn new(key: &[u8]) -> Self {
+        let psk = Hmac::new_varkey(key).expect("HMAC accepts keys of any length");
+        Self {
+            mac: psk.clone(),
+            psk,
+            local: PayloadType::Master,
+            counters: Counters::default(),
+        }
+    }
+
+    /// Starts the session between the peers that announced the random
+    /// numbers `master` and `slave`. Its key is derived from them, so the
+    /// counters can start over without repeating a tag.
+    pub(crate) fn rekey(&mut self, master: u64, slave: u64) {
+        let key = session_key(&self.psk, master, slave);
+        self.mac = Hmac::new_varkey(&key).expect("HMAC accepts keys of any length");
+        self.counters = Counters::default();
+    }
+
+    /// Sets the payload type of the packets that are sealed. The peer must
+    /// use the other one.
+    pub(crate) fn set_local(&mut self, local: PayloadType) {
+        self.local = local;
+    }
+
+    fn peer(&self) -> PayloadType {
+        match self.local {
+            PayloadType::Master => PayloadType::Slave,
+            PayloadType::Slave => PayloadType::Master,
+        }
+    }
+
+    /// Appends the trailer to the packet in `bs`.
+    ///
+    /// Panics if `bs` holds an invalid data message header.
+    pub fn seal(&mut self, bs: &mut Vec<u8>) {
+        if Packet::is_handshake(bs) {
+            seal_handshake(&self.psk, self.local, bs);
+            return;
+        }
+        let (class, counter) = if Packet::is_data(bs) {
+            let msg = MessageRef::new(bs).expect("sealed data messages are valid");
+            self.counters.next(Some(msg.sequence()))
+        } else {
+            self.counters.next(None)
+        };
+        let code = tag(&self.mac, self.local, class, counter, bs);
+        if class == OTHER {
+            bs.write_u64::<BigEndian>(counter).unwrap();
+        }
+        bs.extend_from_slice(&code);
+    }
+
+    /// Verifies the trailer of the packet in `bs` and returns it without the
+    /// trailer.
+    pub fn open<'a>(&mut self, bs: &'a [u8]) -> Result<&'a [u8], HoipError> {
+        if Packet::is_handshake(bs) {
+            return open_handshake(&self.psk, self.peer(), bs);
+        }
+        let trailer_len = match Packet::is_data(bs) {
+            true => HMAC_LEN,
+            false => COUNTER_LEN + HMAC_LEN,
+        };
+        if bs.len() < trailer_len + 1 {
+            return Err(HoipError::BodyTooShort {
+                len: bs.len(),
+                expected: trailer_len + 1,
+            });
+        }
+        let peer = self.peer();
+        let (msg, trailer) = bs.split_at(bs.len() - trailer_len);
+        let code = &trailer[trailer_len - HMAC_LEN..];
+        let (class, counter) = if Packet::is_data(msg) {
+            let sequence = MessageRef::new(msg)?.sequence();
+            (DATA, self.counters.extend_received(sequence))
+        } else {
+            (OTHER, BigEndian::read_u64(&trailer[..COUNTER_LEN]))
+        };
+        let window = self.counters.window(class);
+        window.check(counter)?;
+        verify(&tag(&self.mac, peer, class, counter, msg), code)?;
+        window.update(counter);
+        Ok(msg)
+    }
+}
+
+impl fmt::Debug for Authentication {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        // The key is not printed.
+        f.debug_struct("Authentication")
+            .field("local", &self.local)
+            .field("counters", &self.counters)
+            .finish()
+    }
+}
+
+/// Appends the tag of the handshake in `bs` sent in `direction`.
+pub(crate) fn seal_handshake(psk: &Hmac<Sha256>, direction: PayloadType, bs: &mut Vec<u8>) {
+    let code = tag(psk, direction, HANDSHAKE, 0, bs);
+    bs.extend_from_slice(&code);
+}
+
+/// Verifies the tag of the handshake in `bs` sent in `direction` and returns
+/// it without the tag.
+pub(crate) fn open_handshake<'a>(
+    psk: &Hmac<Sha256>,
+    direction: PayloadType,
+    bs: &'a [u8],
+) -> Result<&'a [u8], HoipError> {
+    if bs.len() < HMAC_LEN + 1 {
+        return Err(HoipError::BodyTooShort {
+            len: bs.len(),
+            expected: HMAC_LEN + 1,
+        });
+    }
+    let (msg, code) = bs.split_at(bs.len() - HMAC_LEN);
+    verify(&tag(psk, direction, HANDSHAKE, 0, msg), code)?;
+    Ok(msg)
+}
+
+/// Derives the key of the session between the peers that announced the
+/// random numbers `master` and `slave` from the pre-shared key.
+pub(crate) fn session_key(psk: &Hmac<Sha256>, master: u64, slave: u64) -> [u8; KEY_LEN] {
+    let mut mac = psk.clone();
+    mac.input(b"hoip session");
+    mac.input(&master.to_be_bytes());
+    mac.input(&slave.to_be_bytes());
+    let mut key = [0; KEY_LEN];
+    key.copy_from_slice(&mac.result().code());
+    key
+}
+
+/// Returns the truncated HMAC of the nonce of the packet `bs` and `bs`.
+fn tag(
+    mac: &Hmac<Sha256>,
+    direction: PayloadType,
+    class: u8,
+    counter: u64,
+    bs: &[u8],
+) -> [u8; HMAC_LEN] {
+    let mut mac = mac.clone();
+    mac.input(&nonce(direction, class, counter));
+    mac.input(bs);
+    let mut code = [0; HMAC_LEN];
+    code.copy_from_slice(&mac.result().code()[0..HMAC_LEN]);
+    code
+}
+
+/// Compares the tags in constant time to not leak the correct one.
+fn verify(expected: &[u8], code: &[u8]) -> Result<(), HoipError> {
+    let diff = expected
+        .iter()
+        .zip(code)
+        .fold(0, |acc, (a, b)| acc | (a ^ b));
+    match diff {
+        0 => Ok(()),
+        _ => Err(HoipError::AuthenticationFailed),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hoip::{Capabilities, Handshake, Header, Message, PayloadM2S, Profile};
+    use crate::hoip::{ControlAck, DelayIndicator, SamplingScheme, PROTOCOL_VERSION};
+
+    fn data(sequence: u16) -> Vec<u8> {
+        let header = Header {
+            payload_type: PayloadType::Master,
+            sampling_scheme: SamplingScheme::Lossless,
+            num_samples: 1,
+            delay_indicator: DelayIndicator::InHeader,
+            threshold: 0,
+            rott: 0,
+            timestamp: 1,
+            sequence,
+            redundancy: 0,
+            compression: None,
+            profile: Profile::Standard,
+        };
+        let samples = [(1, PayloadM2S::new([1.0; 3], [2.0; 3]))];
+        Message::with_samples(header, &samples).to_bytes()
+    }
+
+    #[test]
+    fn authentication() {
+        let mut master = Authentication::new(b"secret");
+        let mut slave = master.clone();
+        slave.set_local(PayloadType::Slave);
+
+        let plain = data(3);
+        let mut bs = plain.clone();
+        master.seal(&mut bs);
+        assert_eq!(bs.len(), plain.len() + HMAC_LEN);
+        assert_eq!(slave.open(&bs), Ok(&plain[..]));
+        // The same packet is only accepted once.
+        assert_eq!(slave.open(&bs), Err(HoipError::Replayed { sequence: 3 }));
+        // A packet sealed by the slave can't be reflected to it.
+        let mut bs = data(4);
+        slave.seal(&mut bs);
+        assert_eq!(slave.open(&bs), Err(HoipError::AuthenticationFailed));
+        // The sequence number is covered by the tag.
+        let mut bs = data(5);
+        master.seal(&mut bs);
+        let mut forged = data(6);
+        forged.extend_from_slice(&bs[plain.len()..]);
+        assert_eq!(slave.open(&forged), Err(HoipError::AuthenticationFailed));
+        assert!(slave.open(&bs).is_ok());
+
+        let plain = ControlAck { next: 1 }.to_bytes();
+        let mut bs = plain.clone();
+        slave.seal(&mut bs);
+        assert_eq!(bs.len(), plain.len() + COUNTER_LEN + HMAC_LEN);
+        assert_eq!(master.open(&bs), Ok(&plain[..]));
+        assert_eq!(master.open(&bs), Err(HoipError::Replayed { sequence: 1 }));
+
+        let plain = Handshake::Hello(Capabilities {
+            version: PROTOCOL_VERSION,
+            header_len: 16,
+            k_max: 4,
+            sampling_scheme: SamplingScheme::Lossless,
+            send_len: 24,
+            recv_len: 12,
+            integrity: 2,
+            sample_period: 1000,
+            session: 1,
+            echo: 0,
+            time: 0,
+        })
+        .to_bytes();
+        let mut bs = plain.clone();
+        master.seal(&mut bs);
+        assert_eq!(slave.open(&bs), Ok(&plain[..]));
+        assert_eq!(master.open(&bs), Err(HoipError::AuthenticationFailed));
+        assert_eq!(
+            Authentication::new(b"intruder").open(&bs),
+            Err(HoipError::AuthenticationFailed)
+        );
+    }
+}
@@ -0,0 +1,201 @@
+use super::HoipError;
+use crate::sampling::Sample;
+use byteorder::{BigEndian, ByteOrder, WriteBytesExt};
+use serde::{Deserialize, Serialize};
+
+/// The length of the compression parameters in the header in bytes.
+pub const COMPRESSION_LEN: usize = 5;
+
+/// The width of a fixed-point value.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum Width {
+    I8,
+    I16,
+}
+
+impl Width {
+    /// Returns the length of a value in bytes.
+    pub fn bytes(self) -> usize {
+        match self {
+            Width::I8 => 1,
+            Width::I16 => 2,
+        }
+    }
+
+    fn clamp(self, v: i64) -> i64 {
+        match self {
+            Width::I8 => v.max(i64::from(i8::MIN)).min(i64::from(i8::MAX)),
+            Width::I16 => v.max(i64::from(i16::MIN)).min(i64::from(i16::MAX)),
+        }
+    }
+
+    fn write(self, bs: &mut [u8], v: i64) {
+        match self {
+            Width::I8 => bs[0] = v as i8 as u8,
+            Width::I16 => BigEndian::write_i16(bs, v as i16),
+        }
+    }
+
+    fn read(self, bs: &[u8]) -> i64 {
+        match self {
+            Width::I8 => i64::from(bs[0] as i8),
+            Width::I16 => i64::from(BigEndian::read_i16(bs)),
+        }
+    }
+
+    fn to_u8(self) -> u8 {
+        match self {
+            Width::I8 => 1,
+            Width::I16 => 2,
+        }
+    }
+
+    fn from_u8(byte: u8) -> Option<Self> {
+        match byte {
+            1 => Some(Width::I8),
+            2 => Some(Width::I16),
+            _ => None,
+        }
+    }
+}
+
+/// Lossy compression of the channel values of the samples in a message.
+/// Every value is sent as fixed-point multiple of `scale`. Values of a sample
+/// that follows another one in the same message can be sent as difference to
+/// it. Other fields of a sample than its channels are not transmitted.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct Compression {
+    /// The quantisation step of the values.
+    pub scale: f32,
+    /// The width of the values of the first sample.
+    pub width: Width,
+    /// The width of the differences of the following samples to their
+    /// predecessor. Without it every sample is sent like the first one.
+    pub delta: Option<Width>,
+}
+
+impl Compression {
+    /// Appends the parameters to `wtr`.
+    pub(crate) fn encode(&self, wtr: &mut Vec<u8>) {
+        let delta = self.delta.map_or(0, Width::to_u8);
+        wtr.write_u8(self.width.to_u8() | delta << 4).unwrap();
+        wtr.write_f32::<BigEndian>(self.scale).unwrap();
+    }
+
+    /// Decodes the parameters from the first `COMPRESSION_LEN` bytes of `bs`.
+    pub(crate) fn decode(bs: &[u8]) -> Result<Self, HoipError> {
+        let byte = bs[0];
+        let width = Width::from_u8(byte & 0x0F).ok_or(HoipError::ReservedBits { byte })?;
+        let delta = match byte >> 4 {
+            0 => None,
+            delta => Some(Width::from_u8(delta).ok_or(HoipError::ReservedBits { byte })?),
+        };
+        let scale = BigEndian::read_f32(&bs[1..COMPRESSION_LEN]);
+        if !(scale.is_finite() && scale > 0.0) {
+            return Err(HoipError::ReservedBits { byte });
+        }
+        Ok(Self {
+            scale,
+            width,
+            delta,
+        })
+    }
+
+    /// Returns the length of the first and the following samples of
+    /// `num_values` values each, including `delay_len` bytes of delay.
+    pub(crate) fn sample_lens(&self, num_values: usize, delay_len: usize) -> (usize, usize) {
+        let first = delay_len + num_values * self.width.bytes();
+        let next = delay_len + num_values * self.delta.unwrap_or(self.width).bytes();
+        (first, next)
+    }
+
+    /// Writes the values of `samples` into `bs`, which holds the samples
+    /// back to back with `delay_len` bytes of delay in front of each.
+    pub(crate) fn write<'a, T: Sample + 'a>(
+        &self,
+        samples: impl Iterator<Item = &'a T> + Clone,
+        delay_len: usize,
+        bs: &mut [u8],
+    ) {
+        let first = match samples.clone().next() {
+            Some(first) => first,
+            None => return,
+        };
+        let (first_len, next_len) = self.sample_lens(num_values(first), delay_len);
+        let mut j = 0;
+        for c in 0..T::num_channels() {
+            for a in 0..first.channel(c).len() {
+                // The value the receiver reconstructs for the previous sample.
+                let mut prev = 0;
+                for (i, sample) in samples.clone().enumerate() {
+                    let q =
+                        (f64::from(sample.channel(c)[a]) / f64::from(self.scale)).round() as i64;
+                    let (width, start) = self.position(i, j, first_len, next_len, delay_len);
+                    let v = if i == 0 || self.delta.is_none() {
+                        width.clamp(q)
+                    } else {
+                        width.clamp(q.saturating_sub(prev))
+                    };
+                    width.write(&mut bs[start..], v);
+                    prev = if i == 0 || self.delta.is_none() {
+                        v
+                    } else {
+                        prev + v
+                    };
+                }
+                j += 1;
+            }
+        }
+    }
+
+    /// Reads the values of a sample laid out like by `write` from `bs`, which
+    /// starts behind its delay. `prev` is the sample decoded before it in the
+    /// same section of the message, if any.
+    pub(crate) fn read_sample<T: Sample>(&self, bs: &[u8], prev: Option<&T>) -> T {
+        let scale = f64::from(self.scale);
+        let (width, prev) = match (prev, self.delta) {
+            (Some(prev), Some(delta)) => (delta, Some(prev)),
+            _ => (self.width, None),
+        };
+        let mut sample = T::default();
+        let mut j = 0;
+        for c in 0..T::num_channels() {
+            for (a, value) in sample.channel_mut(c).iter_mut().enumerate() {
+                let mut v = width.read(&bs[j * width.bytes()..]);
+                if let Some(prev) = prev {
+                    // Decoded values are multiples of the scale, so the
+                    // fixed-point value of the predecessor is recovered exactly.
+                    v += (f64::from(prev.channel(c)[a]) / scale).round() as i64;
+                }
+                *value = (v as f64 * scale) as f32;
+                j += 1;
+            }
+        }
+        sample
+    }
+
+    /// Returns the width and the offset of the value `j` of the sample `i`.
+    fn position(
+        &self,
+        i: usize,
+        j: usize,
+        first_len: usize,
+        next_len: usize,
+        delay_len: usize,
+    ) -> (Width, usize) {
+        if i == 0 {
+            (self.width, delay_len + j * self.width.bytes())
+        } else {
+            let width = self.delta.unwrap_or(self.width);
+            let start = first_len + (i - 1) * next_len;
+            (width, start + delay_len + j * width.bytes())
+        }
+    }
+}
+
+/// Returns the number of values in all channels of `sample`.
+pub(crate) fn num_values<T: Sample>(sample: &T) -> usize {
+    (0..T::num_channels())
+        .map(|i| sample.channel(i).len())
+        .sum()
+}
@@ -0,0 +1,374 @@
+use super::{HoipError, NON_DATA_CLASS};
+use byteorder::{BigEndian, ByteOrder, WriteBytesExt};
+use std::collections::VecDeque;
+
+/// The length of the header of a control message in bytes.
+pub const CONTROL_HEADER_LEN: usize = 4;
+
+/// The length of a control acknowledgement in bytes.
+pub const CONTROL_ACK_LEN: usize = 3;
+
+const CONTROL: u8 = 4;
+const CONTROL_ACK: u8 = 5;
+
+const SET_PARAMETER: u8 = 0;
+const RATE_CHANGE: u8 = 1;
+const K_HINT: u8 = 2;
+const TEARDOWN: u8 = 3;
+const CUSTOM: u8 = 4;
+
+// The maximum number of unacknowledged control messages. It is also the
+// number of messages the receiver buffers ahead of a missing one.
+const WINDOW: usize = 64;
+
+// The bounds of the retransmission timeout in [us].
+const INITIAL_RTO: u64 = 100_000;
+const MIN_RTO: u64 = 10_000;
+const MAX_RTO: u64 = 1_000_000;
+
+/// The content of a control message.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Control {
+    /// Sets the application defined parameter `id` to `value`.
+    SetParameter { id: u16, value: f64 },
+    /// Asks the peer to send with another rate.
+    RateChange(f64),
+    /// Suggests the number of samples per message to the peer.
+    KHint(i8),
+    /// Announces the end of the session.
+    Teardown,
+    /// Application defined content.
+    Custom(Vec<u8>),
+}
+
+/// A control message and its position in the control channel.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ControlMessage {
+    /// The number of the message, counted per direction.
+    pub id: u16,
+    pub control: Control,
+}
+
+impl ControlMessage {
+    /// Returns true if `kind` from the first byte denotes a control message.
+    pub(crate) fn is_kind(kind: u8) -> bool {
+        kind == CONTROL
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut wtr = Vec::with_capacity(CONTROL_HEADER_LEN + 10);
+        self.encode(&mut wtr);
+        wtr
+    }
+
+    /// Appends the encoded message to `wtr`.
+    pub fn encode(&self, wtr: &mut Vec<u8>) {
+        wtr.write_u8(NON_DATA_CLASS | CONTROL).unwrap();
+        wtr.write_u16::<BigEndian>(self.id).unwrap();
+        match &self.control {
+            Control::SetParameter { id, value } => {
+                wtr.write_u8(SET_PARAMETER).unwrap();
+                wtr.write_u16::<BigEndian>(*id).unwrap();
+                wtr.write_f64::<BigEndian>(*value).unwrap();
+            }
+            Control::RateChange(rate) => {
+                wtr.write_u8(RATE_CHANGE).unwrap();
+                wtr.write_f64::<BigEndian>(*rate).unwrap();
+            }
+            Control::KHint(k) => {
+                wtr.write_u8(K_HINT).unwrap();
+                wtr.write_i8(*k).unwrap();
+            }
+            Control::Teardown => wtr.write_u8(TEARDOWN).unwrap(),
+            Control::Custom(bs) => {
+                wtr.write_u8(CUSTOM).unwrap();
+                wtr.extend_from_slice(bs);
+            }
+        }
+    }
+
+    pub fn from_bytes(bs: &[u8]) -> Result<Self, HoipError> {
+        if bs.len() < CONTROL_HEADER_LEN {
+            return Err(HoipError::BodyTooShort {
+                len: bs.len(),
+                expected: CONTROL_HEADER_LEN,
+            });
+        }
+        let id = BigEndian::read_u16(&bs[1..3]);
+        let kind = bs[3];
+        let body = &bs[CONTROL_HEADER_LEN..];
+        let expected = match kind {
+            SET_PARAMETER => 10,
+            RATE_CHANGE => 8,
+            K_HINT => 1,
+            TEARDOWN => 0,
+            CUSTOM => body.len(),
+            _ => return Err(HoipError::ReservedBits { byte: kind }),
+        };
+        if body.len() != expected {
+            return Err(HoipError::BodyTooShort {
+                len: bs.len(),
+                expected: CONTROL_HEADER_LEN + expected,
+            });
+        }
+        let control = match kind {
+            SET_PARAMETER => Control::SetParameter {
+                id: BigEndian::read_u16(&body[0..2]),
+                value: BigEndian::read_f64(&body[2..10]),
+            },
+            RATE_CHANGE => Control::RateChange(BigEndian::read_f64(body)),
+            K_HINT => Control::KHint(body[0] as i8),
+            TEARDOWN => Control::Teardown,
+            _ => Control::Custom(body.to_vec()),
+        };
+        Ok(Self { id, control })
+    }
+}
+
+/// Acknowledges all control messages before `next`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ControlAck {
+    /// The number of the next control message the receiver waits for.
+    pub next: u16,
+}
+
+impl ControlAck {
+    /// Returns true if `kind` from the first byte denotes an acknowledgement.
+    pub(crate) fn is_kind(kind: u8) -> bool {
+        kind == CONTROL_ACK
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut wtr = Vec::with_capacity(CONTROL_ACK_LEN);
+        self.encode(&mut wtr);
+        wtr
+    }
+
+    /// Appends the encoded acknowledgement to `wtr`.
+    pub fn encode(&self, wtr: &mut Vec<u8>) {
+        wtr.write_u8(NON_DATA_CLASS | CONTROL_ACK).unwrap();
+        wtr.write_u16::<BigEndian>(self.next).unwrap();
+    }
+
+    pub fn from_bytes(bs: &[u8]) -> Result<Self, HoipError> {
+        if bs.len() != CONTROL_ACK_LEN {
+            return Err(HoipError::BodyTooShort {
+                len: bs.len(),
+                expected: CONTROL_ACK_LEN,
+            });
+        }
+        Ok(Self {
+            next: BigEndian::read_u16(&bs[1..3]),
+        })
+    }
+}
+
+struct Pending {
+    msg: ControlMessage,
+    // When the message was last sent and how often it was retransmitted.
+    sent: Option<u64>,
+    retries: u32,
+}
+
+/// Numbers the sent control messages and retransmits them until they are
+/// acknowledged.
+pub struct ControlSender {
+    next: u16,
+    unacked: VecDeque<Pending>,
+    // The smoothed round trip time and its variation in [us].
+    srtt: Option<u64>,
+    rttvar: u64,
+    rto: u64,
+}
+
+impl Default for ControlSender {
+    fn default() -> Self {
+        Self {
+            next: 0,
+            unacked: VecDeque::new(),
+            srtt: None,
+            rttvar: 0,
+            rto: INITIAL_RTO,
+        }
+    }
+}
+
+impl ControlSender {
+    /// Queues `control` for sending. Returns false if too many messages are
+    /// unacknowledged.
+    pub fn push(&mut self, control: Control) -> bool {
+        if self.unacked.len() == WINDOW {
+            return false;
+        }
+        self.unacked.push_back(Pending {
+            msg: ControlMessage {
+                id: self.next,
+                control,
+            },
+            sent: None,
+            retries: 0,
+        });
+        self.next = self.next.wrapping_add(1);
+        true
+    }
+
+    /// Returns the messages that are sent for the first time or whose
+    /// retransmission timeout expired at `now` and marks them as sent.
+    pub fn due(&mut self, now: u64) -> impl Iterator<Item = &ControlMessage> {
+        let rto = self.rto;
+        self.unacked.iter_mut().filter_map(move |pending| {
+            if let Some(sent) = pending.sent {
+                // The timeout doubles with every retransmission.
+                let timeout = std::cmp::min(rto << pending.retries.min(16), MAX_RTO);
+                if now < sent + timeout {
+                    return None;
+                }
+                pending.retries += 1;
+            }
+            pending.sent = Some(now);
+            let pending: &Pending = pending;
+            Some(&pending.msg)
+        })
+    }
+
+    /// Removes the acknowledged messages and updates the retransmission
+    /// timeout from the round trip time of those that weren't retransmitted.
+    pub fn on_ack(&mut self, ack: ControlAck, now: u64) {
+        while let Some(pending) = self.unacked.front() {
+            if ack.next.wrapping_sub(pending.msg.id) as i16 <= 0 {
+                break;
+            }
+            if let (Some(sent), 0) = (pending.sent, pending.retries) {
+                self.on_rtt(now.saturating_sub(sent));
+            }
+            self.unacked.pop_front();
+        }
+    }
+
+    // Estimates the timeout like TCP does.
+    fn on_rtt(&mut self, rtt: u64) {
+        let srtt = match self.srtt {
+            Some(srtt) => {
+                let diff = srtt.max(rtt) - srtt.min(rtt);
+                self.rttvar = (3 * self.rttvar + diff) / 4;
+                (7 * srtt + rtt) / 8
+            }
+            None => {
+                self.rttvar = rtt / 2;
+                rtt
+            }
+        };
+        self.srtt = Some(srtt);
+        self.rto = (srtt + 4 * self.rttvar).clamp(MIN_RTO, MAX_RTO);
+    }
+
+    /// Returns the number of messages that are not acknowledged yet.
+    pub fn unacked(&self) -> usize {
+        self.unacked.len()
+    }
+}
+
+/// Delivers the received control messages in order and exactly once.
+#[derive(Default)]
+pub struct ControlReceiver {
+    next: u16,
+    // The messages received ahead of `next`, indexed by their distance to it.
+    ahead: VecDeque<Option<Control>>,
+    ready: VecDeque<Control>,
+}
+
+impl ControlReceiver {
+    /// Registers a received message and returns the acknowledgement to send.
+    /// Duplicates are acknowledged again.
+    pub fn on_receive(&mut self, msg: ControlMessage) -> ControlAck {
+        let offset = usize::from(msg.id.wrapping_sub(self.next));
+        if offset < WINDOW {
+            if self.ahead.len() <= offset {
+                self.ahead.resize(offset + 1, None);
+            }
+            self.ahead[offset] = Some(msg.control);
+            while let Some(Some(_)) = self.ahead.front() {
+                let control = self.ahead.pop_front().unwrap().unwrap();
+                self.ready.push_back(control);
+                self.next = self.next.wrapping_add(1);
+            }
+        }
+        ControlAck { next: self.next }
+    }
+
+    /// Returns the next control message in order.
+    pub fn pop(&mut self) -> Option<Control> {
+        self.ready.pop_front()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn encoding() {
+        let controls = [
+            Control::SetParameter { id: 3, value: 0.5 },
+            Control::RateChange(2000.0),
+            Control::KHint(-2),
+            Control::Teardown,
+            Control::Custom(b"custom".to_vec()),
+        ];
+        for (id, control) in controls.iter().enumerate() {
+            let msg = ControlMessage {
+                id: id as u16,
+                control: control.clone(),
+            };
+            assert_eq!(ControlMessage::from_bytes(&msg.to_bytes()), Ok(msg));
+        }
+        let ack = ControlAck { next: 7 };
+        assert_eq!(ControlAck::from_bytes(&ack.to_bytes()), Ok(ack));
+
+        let mut bs = ControlMessage {
+            id: 0,
+            control: Control::KHint(1),
+        }
+        .to_bytes();
+        bs.push(0);
+        assert!(ControlMessage::from_bytes(&bs).is_err());
+        bs[3] = 0xFF;
+        assert_eq!(
+            ControlMessage::from_bytes(&bs),
+            Err(HoipError::ReservedBits { byte: 0xFF })
+        );
+    }
+
+    #[test]
+    fn reliable_delivery() {
+        let mut sender = ControlSender::default();
+        let mut receiver = ControlReceiver::default();
+        for k in 0..3 {
+            assert!(sender.push(Control::KHint(k)));
+        }
+        let sent = sender.due(0).cloned().collect::<Vec<_>>();
+        assert_eq!(sent.len(), 3);
+        assert_eq!(sender.due(1).count(), 0);
+
+        // The first message is lost, the others are held back until it is
+        // retransmitted.
+        assert_eq!(receiver.on_receive(sent[2].clone()), ControlAck { next: 0 });
+        assert_eq!(receiver.on_receive(sent[1].clone()), ControlAck { next: 0 });
+        assert_eq!(receiver.pop(), None);
+
+        let resent = sender.due(INITIAL_RTO).cloned().collect::<Vec<_>>();
+        assert_eq!(resent, sent);
+        let ack = receiver.on_receive(resent[0].clone());
+        assert_eq!(ack, ControlAck { next: 3 });
+        // Duplicates are acknowledged but not delivered again.
+        assert_eq!(receiver.on_receive(resent[1].clone()), ack);
+        assert_eq!(
+            std::iter::from_fn(|| receiver.pop()).collect::<Vec<_>>(),
+            vec![Control::KHint(0), Control::KHint(1), Control::KHint(2)]
+        );
+
+        sender.on_ack(ack, INITIAL_RTO + 1);
+        assert_eq!(sender.unacked(), 0);
+        assert_eq!(sender.due(10 * MAX_RTO).count(), 0);
+    }
+}
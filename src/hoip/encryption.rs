@@ -0,0 +1,265 @@
+use super::authentication::{open_handshake, seal_handshake, session_key};
+use super::replay::{nonce, Counters, DATA, OTHER};
+use super::{HoipError, MessageRef, Packet, PayloadType};
+use byteorder::{BigEndian, ByteOrder, WriteBytesExt};
+use chacha20poly1305::aead::{AeadInPlace, NewAead};
+use chacha20poly1305::{ChaCha20Poly1305, Key, Nonce, Tag};
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+use std::fmt;
+
+/// The length of the pre-shared key in bytes.
+pub const KEY_LEN: usize = 32;
+
+/// The length of the authentication tag appended to every packet in bytes.
+pub const TAG_LEN: usize = 16;
+
+/// The length of the counter that non-data packets carry in front of the tag
+/// in bytes.
+pub const COUNTER_LEN: usize = 8;
+
+/// The authenticated encryption of `hoip` packets with ChaCha20-Poly1305.
+///
+/// The header of data messages stays readable and is authenticated together
+/// with the encrypted payload. Their nonce is the sequence number, extended
+/// by its wrap arounds, so no nonce is sent. Other packets only keep their
+/// first byte readable and carry an explicit counter as nonce. The direction
+/// is part of the nonce, so both peers can use the same key.
+///
+/// Every session uses its own key, see `rekey`, so the nonces of a restarted
+/// peer don't repeat those of its previous session. Handshakes are not
+/// encrypted but authenticated with an HMAC of the pre-shared key, like
+/// `Authentication` does.
+#[derive(Clone)]
+pub struct Encryption {
+    psk: Hmac<Sha256>,
+    cipher: ChaCha20Poly1305,
+    // The payload type of the packets that are sealed.
+    local: PayloadType,
+    counters: Counters,
+}
+
+impl Encryption {
+    /// Creates the encryption of the packets a master sends with the
+    /// pre-shared `key`.
+    pub fn new(key: &[u8; KEY_LEN]) -> Self {
+        Self {
+            psk: Hmac::new_varkey(key).expect("HMAC accepts keys of any length"),
+            cipher: ChaCha20Poly1305::new(&Key::from(*key)),
+            local: PayloadType::Master,
+            counters: Counters::default(),
+        }
+    }
+
+    /// Starts the session between the peers that announced the random
+    /// numbers `master` and `slave`. Its key is derived from them, so the
+    /// counters can start over without repeating a nonce.
+    pub(crate) fn rekey(&mut self, master: u64, slave: u64) {
+        let key = session_key(&self.psk, master, slave);
+        self.cipher = ChaCha20Poly1305::new(&Key::from(key));
+        self.counters = Counters::default();
+    }
+
+    /// Sets the payload type of the packets that are sealed. The peer must
+    /// use the other one.
+    pub(crate) fn set_local(&mut self, local: PayloadType) {
+        self.local = local;
+    }
+
+    fn peer(&self) -> PayloadType {
+        match self.local {
+            PayloadType::Master => PayloadType::Slave,
+            PayloadType::Slave => PayloadType::Master,
+        }
+    }
+
+    /// Encrypts the packet in `bs` and appends the trailer.
+    ///
+    /// Panics if `bs` holds an invalid data message header.
+    pub fn seal(&mut self, bs: &mut Vec<u8>) {
+        if Packet::is_handshake(bs) {
+            seal_handshake(&self.psk, self.local, bs);
+            return;
+        }
+        let ((class, counter), readable) = if Packet::is_data(bs) {
+            let msg = MessageRef::new(bs).expect("sealed data messages are valid");
+            (self.counters.next(Some(msg.sequence())), msg.header_len())
+        } else {
+            (self.counters.next(None), std::cmp::min(1, bs.len()))
+        };
+        let nonce = nonce(self.local, class, counter);
+        let (readable, encrypted) = bs.split_at_mut(readable);
+        let tag = self
+            .cipher
+            .encrypt_in_place_detached(&Nonce::from(nonce), readable, encrypted)
+            .expect("packets are shorter than the cipher limit");
+        if class == OTHER {
+            bs.write_u64::<BigEndian>(counter).unwrap();
+        }
+        bs.extend_from_slice(&tag);
+    }
+
+    /// Verifies and decrypts the packet in `bs` in place and returns it
+    /// without the trailer.
+    pub fn open<'a>(&mut self, bs: &'a mut [u8]) -> Result<&'a [u8], HoipError> {
+        if Packet::is_handshake(bs) {
+            return open_handshake(&self.psk, self.peer(), bs);
+        }
+        let data = Packet::is_data(bs);
+        let trailer_len = match data {
+            true => TAG_LEN,
+            false => COUNTER_LEN + TAG_LEN,
+        };
+        if bs.len() < trailer_len + 1 {
+            return Err(HoipError::BodyTooShort {
+                len: bs.len(),
+                expected: trailer_len + 1,
+            });
+        }
+        let peer = self.peer();
+        let (msg, trailer) = bs.split_at_mut(bs.len() - trailer_len);
+        let (class, counter, readable) = if data {
+            let view = MessageRef::new(msg)?;
+            let sequence = self.counters.extend_received(view.sequence());
+            (DATA, sequence, view.header_len())
+        } else {
+            let counter = BigEndian::read_u64(&trailer[..COUNTER_LEN]);
+            (OTHER, counter, 1)
+        };
+        let window = self.counters.window(class);
+        window.check(counter)?;
+        let mut tag = Tag::default();
+        tag.copy_from_slice(&trailer[trailer_len - TAG_LEN..]);
+        let nonce = nonce(peer, class, counter);
+        let (readable, encrypted) = msg.split_at_mut(readable);
+        self.cipher
+            .decrypt_in_place_detached(&Nonce::from(nonce), readable, encrypted, &tag)
+            .map_err(|_| HoipError::AuthenticationFailed)?;
+        window.update(counter);
+        Ok(msg)
+    }
+}
+
+impl fmt::Debug for Encryption {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        // The key is not printed.
+        f.debug_struct("Encryption")
+            .field("local", &self.local)
+            .field("counters", &self.counters)
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hoip::{Control, ControlMessage, Header, Message, PayloadM2S, Profile};
+    use crate::hoip::{DelayIndicator, SamplingScheme};
+
+    fn data(sequence: u16) -> Vec<u8> {
+        let header = Header {
+            payload_type: PayloadType::Master,
+            sampling_scheme: SamplingScheme::Lossless,
+            num_samples: 1,
+            delay_indicator: DelayIndicator::InHeader,
+            threshold: 0,
+            rott: 0,
+            timestamp: 1,
+            sequence,
+            redundancy: 0,
+            compression: None,
+            profile: Profile::Standard,
+        };
+        let samples = [(1, PayloadM2S::new([1.0; 3], [2.0; 3]))];
+        Message::with_samples(header, &samples).to_bytes()
+    }
+
+    fn pair() -> (Encryption, Encryption) {
+        let master = Encryption::new(&[7; KEY_LEN]);
+        let mut slave = master.clone();
+        slave.set_local(PayloadType::Slave);
+        (master, slave)
+    }
+
+    #[test]
+    fn roundtrip() {
+        let (mut master, mut slave) = pair();
+        let plain = data(3);
+        let mut bs = plain.clone();
+        master.seal(&mut bs);
+        assert_eq!(bs.len(), plain.len() + TAG_LEN);
+        let header_len = MessageRef::new(&plain).unwrap().header_len();
+        assert_eq!(bs[..header_len], plain[..header_len]);
+        assert_ne!(bs[header_len..plain.len()], plain[header_len..]);
+        assert_eq!(slave.open(&mut bs.clone()), Ok(&plain[..]));
+
+        // The same packet is only accepted once.
+        assert_eq!(
+            slave.open(&mut bs.clone()),
+            Err(HoipError::Replayed { sequence: 3 })
+        );
+
+        // A packet sealed by the slave can't be reflected to it.
+        let mut bs = data(4);
+        slave.seal(&mut bs);
+        assert_eq!(slave.open(&mut bs), Err(HoipError::AuthenticationFailed));
+
+        let plain = ControlMessage {
+            id: 1,
+            control: Control::Teardown,
+        }
+        .to_bytes();
+        let mut bs = plain.clone();
+        slave.seal(&mut bs);
+        assert_eq!(bs.len(), plain.len() + COUNTER_LEN + TAG_LEN);
+        // Forged packets don't take up their counter.
+        let mut forged = bs.clone();
+        forged[1] ^= 1;
+        assert_eq!(
+            master.open(&mut forged),
+            Err(HoipError::AuthenticationFailed)
+        );
+        assert_eq!(master.open(&mut bs), Ok(&plain[..]));
+    }
+
+    #[test]
+    fn replay_window() {
+        let (mut master, mut slave) = pair();
+        let sealed = (0..=u32::from(u16::MAX) + 10)
+            .map(|i| {
+                let mut bs = data(i as u16);
+                master.seal(&mut bs);
+                bs
+            })
+            .collect::<Vec<_>>();
+
+        // Wrapped sequence numbers use new nonces.
+        assert_ne!(sealed[5], sealed[usize::from(u16::MAX) + 6]);
+        for bs in sealed[65_530..65_540].iter() {
+            assert!(slave.open(&mut bs.clone()).is_ok());
+        }
+        // A packet from before the wrap doesn't match the nonce of its
+        // sequence number after the wrap.
+        assert_eq!(
+            slave.open(&mut sealed[5].clone()),
+            Err(HoipError::AuthenticationFailed)
+        );
+        assert!(slave.open(&mut sealed[65_541].clone()).is_ok());
+
+        // Reordered packets within the window are accepted once.
+        let (mut master, mut slave) = pair();
+        let mut sealed = (0..100)
+            .map(|i| {
+                let mut bs = data(i);
+                master.seal(&mut bs);
+                bs
+            })
+            .collect::<Vec<_>>();
+        assert!(slave.open(&mut sealed[99]).is_ok());
+        assert!(slave.open(&mut sealed[50]).is_ok());
+        assert_eq!(
+            slave.open(&mut sealed[35]),
+            Err(HoipError::Replayed { sequence: 35 })
+        );
+    }
+}
@@ -0,0 +1,221 @@
+use super::{HoipError, NON_DATA_CLASS};
+use byteorder::{BigEndian, ByteOrder, WriteBytesExt};
+use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
+
+/// The length of the header of a parity message in bytes.
+pub const PARITY_HEADER_LEN: usize = 6;
+
+const PARITY: u8 = 3;
+
+// The number of received data messages kept for recovery.
+const WINDOW: usize = 256;
+
+/// The forward error correction used for the data messages that are sent.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum FecConfig {
+    /// No forward error correction.
+    None,
+    /// A XOR parity message is sent after every `n` data messages. It allows
+    /// to recover a single lost message out of those `n`.
+    Xor { n: u8 },
+}
+
+impl FecConfig {
+    /// Returns the number of additionally sent messages per data message.
+    pub fn overhead(&self) -> f64 {
+        match self {
+            FecConfig::None => 0.0,
+            FecConfig::Xor { n } => 1.0 / f64::from(*n),
+        }
+    }
+}
+
+/// A message carrying the XOR parity over consecutive data messages.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Parity {
+    /// The sequence number of the first protected data message.
+    pub base: u16,
+    /// The number of protected data messages.
+    pub count: u8,
+    /// The XOR of the lengths of the protected data messages.
+    pub len: u16,
+    /// The XOR of the protected data messages padded with zeros.
+    pub parity: Vec<u8>,
+}
+
+impl Parity {
+    /// Returns true if `kind` from the first byte denotes a parity message.
+    pub(crate) fn is_kind(kind: u8) -> bool {
+        kind == PARITY
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut wtr = Vec::with_capacity(PARITY_HEADER_LEN + self.parity.len());
+        self.encode(&mut wtr);
+        wtr
+    }
+
+    /// Appends the encoded message to `wtr`.
+    pub fn encode(&self, wtr: &mut Vec<u8>) {
+        wtr.write_u8(NON_DATA_CLASS | PARITY).unwrap();
+        wtr.write_u16::<BigEndian>(self.base).unwrap();
+        wtr.write_u8(self.count).unwrap();
+        wtr.write_u16::<BigEndian>(self.len).unwrap();
+        wtr.extend_from_slice(&self.parity);
+    }
+
+    pub fn from_bytes(bs: &[u8]) -> Result<Self, HoipError> {
+        if bs.len() < PARITY_HEADER_LEN {
+            return Err(HoipError::BodyTooShort {
+                len: bs.len(),
+                expected: PARITY_HEADER_LEN,
+            });
+        }
+        Ok(Self {
+            base: BigEndian::read_u16(&bs[1..3]),
+            count: bs[3],
+            len: BigEndian::read_u16(&bs[4..6]),
+            parity: bs[PARITY_HEADER_LEN..].to_vec(),
+        })
+    }
+}
+
+fn xor_into(parity: &mut Vec<u8>, bs: &[u8]) {
+    if parity.len() < bs.len() {
+        parity.resize(bs.len(), 0);
+    }
+    for (p, b) in parity.iter_mut().zip(bs) {
+        *p ^= b;
+    }
+}
+
+/// Accumulates the parity over the sent data messages.
+pub struct XorEncoder {
+    n: u8,
+    // The parity of the current group. Its buffer is reused for every group.
+    parity: Parity,
+}
+
+impl XorEncoder {
+    pub fn new(n: u8) -> Self {
+        assert!(n > 0, "parity needs at least one message");
+        Self {
+            n,
+            parity: Parity {
+                base: 0,
+                count: 0,
+                len: 0,
+                parity: Vec::new(),
+            },
+        }
+    }
+
+    /// Adds a sent data message and returns the parity message once `n`
+    /// messages were added.
+    pub fn add(&mut self, sequence: u16, bs: &[u8]) -> Option<&Parity> {
+        let parity = &mut self.parity;
+        if parity.count == self.n {
+            // The previous group was completed.
+            parity.count = 0;
+        }
+        if parity.count == 0 {
+            parity.base = sequence;
+            parity.len = 0;
+            parity.parity.clear();
+        }
+        parity.count += 1;
+        parity.len ^= bs.len() as u16;
+        xor_into(&mut parity.parity, bs);
+        if parity.count < self.n {
+            return None;
+        }
+        Some(parity)
+    }
+}
+
+/// Recovers lost data messages from the received ones and their parity.
+#[derive(Default)]
+pub struct XorDecoder {
+    // The last received data messages and their sequence numbers.
+    received: VecDeque<(u16, Vec<u8>)>,
+}
+
+impl XorDecoder {
+    /// Adds a received data message.
+    pub fn add(&mut self, sequence: u16, bs: &[u8]) {
+        // The buffer of the oldest message is reused once the window is full.
+        let mut buf = match self.received.len() {
+            WINDOW => self.received.pop_front().map(|(_, buf)| buf).unwrap(),
+            _ => Vec::new(),
+        };
+        buf.clear();
+        buf.extend_from_slice(bs);
+        self.received.push_back((sequence, buf));
+    }
+
+    fn get(&self, sequence: u16) -> Option<&[u8]> {
+        self.received
+            .iter()
+            .rev()
+            .find(|(s, _)| *s == sequence)
+            .map(|(_, bs)| &bs[..])
+    }
+
+    /// Returns the data message protected by `parity` if it is the only one
+    /// missing.
+    pub fn recover(&mut self, parity: &Parity) -> Option<Vec<u8>> {
+        let mut missing = None;
+        let mut bs = parity.parity.clone();
+        let mut len = parity.len;
+        for i in 0..parity.count {
+            let sequence = parity.base.wrapping_add(u16::from(i));
+            match self.get(sequence) {
+                Some(received) => {
+                    len ^= received.len() as u16;
+                    xor_into(&mut bs, received);
+                }
+                None if missing.is_none() => missing = Some(sequence),
+                None => return None,
+            }
+        }
+        let sequence = missing?;
+        if len as usize > bs.len() {
+            return None;
+        }
+        bs.truncate(len as usize);
+        self.add(sequence, &bs);
+        Some(bs)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn recover() {
+        let msgs = [vec![1, 2, 3], vec![4, 5], vec![6, 7, 8, 9]];
+        let mut encoder = XorEncoder::new(3);
+        assert_eq!(encoder.add(u16::MAX, &msgs[0]), None);
+        assert_eq!(encoder.add(0, &msgs[1]), None);
+        let parity = encoder.add(1, &msgs[2]).unwrap().to_bytes();
+        let parity = Parity::from_bytes(&parity).unwrap();
+
+        for lost in 0..3 {
+            let mut decoder = XorDecoder::default();
+            for (i, msg) in msgs.iter().enumerate() {
+                if i != lost {
+                    decoder.add(u16::MAX.wrapping_add(i as u16), msg);
+                }
+            }
+            assert_eq!(decoder.recover(&parity), Some(msgs[lost].clone()));
+            // Nothing is missing anymore.
+            assert_eq!(decoder.recover(&parity), None);
+        }
+
+        let mut decoder = XorDecoder::default();
+        decoder.add(u16::MAX, &msgs[0]);
+        assert_eq!(decoder.recover(&parity), None);
+    }
+}
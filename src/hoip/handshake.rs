@@ -0,0 +1,323 @@
+use super::{HoipError, SamplingScheme, NON_DATA_CLASS};
+use byteorder::{BigEndian, ByteOrder, WriteBytesExt};
+use std::{error::Error, fmt};
+
+/// The version of the `hoip` protocol implemented by this crate.
+pub const PROTOCOL_VERSION: u8 = 2;
+
+/// The length of an encoded handshake message in bytes.
+pub const HANDSHAKE_LEN: usize = 42;
+
+const HELLO: u8 = 0;
+const ACCEPT: u8 = 1;
+const REJECT: u8 = 2;
+
+/// The parameters both peers have to agree on before haptic data flows.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Capabilities {
+    /// The version of the protocol.
+    pub version: u8,
+    /// The length of the data header in bytes.
+    pub header_len: u8,
+    /// The maximum number of samples per message. Both peers use the smaller
+    /// of their values.
+    pub k_max: u8,
+    /// The sampling scheme used for the samples that are sent.
+    pub sampling_scheme: SamplingScheme,
+    /// The length of a sample that is sent in bytes.
+    pub send_len: u16,
+    /// The length of a sample that is expected to be received in bytes.
+    pub recv_len: u16,
+    /// The integrity protection of data messages, see `Integrity::id`.
+    pub integrity: u8,
+    /// The period between two samples in [us]. The timestamps of the
+    /// samples of a message are derived from it.
+    pub sample_period: u64,
+    /// A random number identifying the session of the peer. The keys of a
+    /// session are derived from those of both peers. It is not negotiated.
+    pub session: u64,
+    /// The session of the peer that is answered, 0 in hellos. A session is
+    /// only started by an answer that echoes the own session, which proves
+    /// that the answer is fresh.
+    pub echo: u64,
+    /// The clock of the peer in [us] when the handshake was sent. It is the
+    /// first reference compact timestamps are unwrapped against, see
+    /// `unwrap_timestamp`. It is not negotiated.
+    pub time: u64,
+}
+
+/// A message exchanged to negotiate the capabilities of the peers.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Handshake {
+    /// Sent until the peer answers, announcing the own capabilities.
+    Hello(Capabilities),
+    /// Answer to a compatible `Hello`, or to an `Accept` that started the
+    /// session, with the own capabilities.
+    Accept(Capabilities),
+    /// Answer to an incompatible `Hello` with the own capabilities.
+    Reject(Capabilities),
+}
+
+/// An error that occurs if the peers can't agree on their capabilities.
+#[derive(Debug, Clone, PartialEq)]
+pub enum HandshakeError {
+    /// The peers implement different protocol versions.
+    Version { local: u8, remote: u8 },
+    /// The peers use different header layouts.
+    HeaderLen { local: u8, remote: u8 },
+    /// The peers use different sampling schemes.
+    SamplingScheme {
+        local: SamplingScheme,
+        remote: SamplingScheme,
+    },
+    /// The sample length expected by one peer differs from the one sent by
+    /// the other.
+    SampleLen { expected: u16, remote: u16 },
+    /// The peers protect data messages differently.
+    Integrity { local: u8, remote: u8 },
+    /// The peers take their samples at different periods.
+    SamplePeriod { local: u64, remote: u64 },
+    /// The peer rejected the capabilities without a mismatch being visible
+    /// locally.
+    Rejected,
+    /// The peer didn't answer in time.
+    Timeout,
+}
+
+impl fmt::Display for HandshakeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            HandshakeError::Version { local, remote } => write!(
+                f,
+                "protocol version mismatch: local {}, remote {}",
+                local, remote
+            ),
+            HandshakeError::HeaderLen { local, remote } => write!(
+                f,
+                "header length mismatch: local {}, remote {}",
+                local, remote
+            ),
+            HandshakeError::SamplingScheme { local, remote } => write!(
+                f,
+                "sampling scheme mismatch: local {:?}, remote {:?}",
+                local, remote
+            ),
+            HandshakeError::SampleLen { expected, remote } => write!(
+                f,
+                "sample length mismatch: expected {} bytes, remote sends {} bytes",
+                expected, remote
+            ),
+            HandshakeError::Integrity { local, remote } => write!(
+                f,
+                "integrity protection mismatch: local {}, remote {}",
+                local, remote
+            ),
+            HandshakeError::SamplePeriod { local, remote } => write!(
+                f,
+                "sample period mismatch: local {} us, remote {} us",
+                local, remote
+            ),
+            HandshakeError::Rejected => write!(f, "rejected by the peer"),
+            HandshakeError::Timeout => write!(f, "the peer didn't answer in time"),
+        }
+    }
+}
+
+impl Error for HandshakeError {}
+
+impl Capabilities {
+    /// Checks if the capabilities of the `remote` peer are compatible.
+    pub fn check(&self, remote: &Capabilities) -> Result<(), HandshakeError> {
+        if self.version != remote.version {
+            return Err(HandshakeError::Version {
+                local: self.version,
+                remote: remote.version,
+            });
+        }
+        if self.header_len != remote.header_len {
+            return Err(HandshakeError::HeaderLen {
+                local: self.header_len,
+                remote: remote.header_len,
+            });
+        }
+        if self.sampling_scheme != remote.sampling_scheme {
+            return Err(HandshakeError::SamplingScheme {
+                local: self.sampling_scheme,
+                remote: remote.sampling_scheme,
+            });
+        }
+        if self.recv_len != remote.send_len {
+            return Err(HandshakeError::SampleLen {
+                expected: self.recv_len,
+                remote: remote.send_len,
+            });
+        }
+        if self.send_len != remote.recv_len {
+            return Err(HandshakeError::SampleLen {
+                expected: remote.recv_len,
+                remote: self.send_len,
+            });
+        }
+        if self.integrity != remote.integrity {
+            return Err(HandshakeError::Integrity {
+                local: self.integrity,
+                remote: remote.integrity,
+            });
+        }
+        if self.sample_period != remote.sample_period {
+            return Err(HandshakeError::SamplePeriod {
+                local: self.sample_period,
+                remote: remote.sample_period,
+            });
+        }
+        Ok(())
+    }
+}
+
+fn sampling_scheme_to_u8(scheme: SamplingScheme) -> u8 {
+    match scheme {
+        SamplingScheme::Lossless => 0,
+        SamplingScheme::Weber => 1,
+        SamplingScheme::LevelCrossing => 2,
+    }
+}
+
+fn sampling_scheme_from_u8(byte: u8) -> Result<SamplingScheme, HoipError> {
+    match byte {
+        0 => Ok(SamplingScheme::Lossless),
+        1 => Ok(SamplingScheme::Weber),
+        2 => Ok(SamplingScheme::LevelCrossing),
+        _ => Err(HoipError::ReservedBits { byte }),
+    }
+}
+
+impl Handshake {
+    /// Returns true if `kind` from the first byte denotes a handshake message.
+    pub(crate) fn is_kind(kind: u8) -> bool {
+        kind <= REJECT
+    }
+
+    pub fn capabilities(&self) -> &Capabilities {
+        match self {
+            Handshake::Hello(caps) | Handshake::Accept(caps) | Handshake::Reject(caps) => caps,
+        }
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let (kind, caps) = match self {
+            Handshake::Hello(caps) => (HELLO, caps),
+            Handshake::Accept(caps) => (ACCEPT, caps),
+            Handshake::Reject(caps) => (REJECT, caps),
+        };
+        let mut wtr = Vec::with_capacity(HANDSHAKE_LEN);
+        wtr.write_u8(NON_DATA_CLASS | kind).unwrap();
+        wtr.write_u8(caps.version).unwrap();
+        wtr.write_u8(caps.header_len).unwrap();
+        wtr.write_u8(caps.k_max).unwrap();
+        wtr.write_u8(sampling_scheme_to_u8(caps.sampling_scheme))
+            .unwrap();
+        wtr.write_u16::<BigEndian>(caps.send_len).unwrap();
+        wtr.write_u16::<BigEndian>(caps.recv_len).unwrap();
+        wtr.write_u8(caps.integrity).unwrap();
+        wtr.write_u64::<BigEndian>(caps.sample_period).unwrap();
+        wtr.write_u64::<BigEndian>(caps.session).unwrap();
+        wtr.write_u64::<BigEndian>(caps.echo).unwrap();
+        wtr.write_u64::<BigEndian>(caps.time).unwrap();
+        wtr
+    }
+
+    pub fn from_bytes(bs: &[u8]) -> Result<Self, HoipError> {
+        if bs.len() < HANDSHAKE_LEN {
+            return Err(HoipError::BodyTooShort {
+                len: bs.len(),
+                expected: HANDSHAKE_LEN,
+            });
+        }
+        let caps = Capabilities {
+            version: bs[1],
+            header_len: bs[2],
+            k_max: bs[3],
+            sampling_scheme: sampling_scheme_from_u8(bs[4])?,
+            send_len: BigEndian::read_u16(&bs[5..7]),
+            recv_len: BigEndian::read_u16(&bs[7..9]),
+            integrity: bs[9],
+            sample_period: BigEndian::read_u64(&bs[10..18]),
+            session: BigEndian::read_u64(&bs[18..26]),
+            echo: BigEndian::read_u64(&bs[26..34]),
+            time: BigEndian::read_u64(&bs[34..42]),
+        };
+        match bs[0] & !NON_DATA_CLASS {
+            HELLO => Ok(Handshake::Hello(caps)),
+            ACCEPT => Ok(Handshake::Accept(caps)),
+            REJECT => Ok(Handshake::Reject(caps)),
+            _ => Err(HoipError::ReservedBits { byte: bs[0] }),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn caps(send_len: u16, recv_len: u16) -> Capabilities {
+        Capabilities {
+            version: PROTOCOL_VERSION,
+            header_len: 16,
+            k_max: 4,
+            sampling_scheme: SamplingScheme::Weber,
+            send_len,
+            recv_len,
+            integrity: 0,
+            sample_period: 1000,
+            session: 0x0123_4567_89ab_cdef,
+            echo: 0xfedc_ba98_7654_3210,
+            time: 0x0000_0012_3456_789a,
+        }
+    }
+
+    #[test]
+    fn basic() {
+        for handshake in vec![
+            Handshake::Hello(caps(24, 12)),
+            Handshake::Accept(caps(12, 24)),
+            Handshake::Reject(caps(1, 2)),
+        ]
+        .into_iter()
+        {
+            assert_eq!(
+                Ok(handshake.clone()),
+                Handshake::from_bytes(&handshake.to_bytes())
+            );
+        }
+    }
+
+    #[test]
+    fn check() {
+        assert_eq!(caps(24, 12).check(&caps(12, 24)), Ok(()));
+        assert_eq!(
+            caps(24, 12).check(&caps(24, 12)),
+            Err(HandshakeError::SampleLen {
+                expected: 12,
+                remote: 24
+            })
+        );
+        let mut remote = caps(12, 24);
+        remote.version += 1;
+        assert_eq!(
+            caps(24, 12).check(&remote),
+            Err(HandshakeError::Version {
+                local: PROTOCOL_VERSION,
+                remote: PROTOCOL_VERSION + 1
+            })
+        );
+        let mut remote = caps(12, 24);
+        remote.sample_period = 500;
+        assert_eq!(
+            caps(24, 12).check(&remote),
+            Err(HandshakeError::SamplePeriod {
+                local: 1000,
+                remote: 500
+            })
+        );
+    }
+}
@@ -0,0 +1,169 @@
+use super::{Authentication, Encryption, HoipError, PayloadType, HMAC_LEN, KEY_LEN, TAG_LEN};
+use byteorder::{BigEndian, ByteOrder, WriteBytesExt};
+
+/// The length of the CRC32 trailer in bytes.
+pub const CRC32_LEN: usize = 4;
+
+/// The protection of `hoip` packets against corruption and forgery. It is
+/// appended as a trailer to every packet but handshakes, which are only
+/// protected by the keyed ones.
+#[derive(Debug, Clone)]
+pub enum Integrity {
+    /// No protection.
+    None,
+    /// A CRC32 checksum detecting corrupted messages.
+    Crc32,
+    /// A truncated HMAC-SHA256 with a pre-shared key authenticating all
+    /// packets and rejecting replayed ones.
+    Hmac(Box<Authentication>),
+    /// ChaCha20-Poly1305 with a pre-shared key encrypting all packets but
+    /// handshakes, authenticating all and rejecting replayed ones.
+    ChaCha20Poly1305(Box<Encryption>),
+}
+
+impl Integrity {
+    /// Creates a HMAC protection using the pre-shared `key`.
+    pub fn hmac(key: &[u8]) -> Self {
+        Integrity::Hmac(Box::new(Authentication::new(key)))
+    }
+
+    /// Creates a ChaCha20-Poly1305 encryption using the pre-shared `key`.
+    pub fn chacha20poly1305(key: &[u8; KEY_LEN]) -> Self {
+        Integrity::ChaCha20Poly1305(Box::new(Encryption::new(key)))
+    }
+
+    /// Sets the payload type of the packets that are sealed. The peer must
+    /// use the other one.
+    pub(crate) fn set_local(&mut self, local: PayloadType) {
+        match self {
+            Integrity::Hmac(authentication) => authentication.set_local(local),
+            Integrity::ChaCha20Poly1305(encryption) => encryption.set_local(local),
+            _ => {}
+        }
+    }
+
+    /// Starts the session between the peers that announced the random
+    /// numbers `master` and `slave` in the handshake. The keyed protections
+    /// derive the key of the session from them and start their counters
+    /// over.
+    pub(crate) fn rekey(&mut self, master: u64, slave: u64) {
+        match self {
+            Integrity::Hmac(authentication) => authentication.rekey(master, slave),
+            Integrity::ChaCha20Poly1305(encryption) => encryption.rekey(master, slave),
+            _ => {}
+        }
+    }
+
+    /// Returns true if handshakes carry a trailer.
+    pub fn protects_handshakes(&self) -> bool {
+        matches!(self, Integrity::Hmac(_) | Integrity::ChaCha20Poly1305(_))
+    }
+
+    /// Returns the identifier of the protection that is negotiated in the
+    /// handshake.
+    pub fn id(&self) -> u8 {
+        match self {
+            Integrity::None => 0,
+            Integrity::Crc32 => 1,
+            Integrity::Hmac(_) => 2,
+            Integrity::ChaCha20Poly1305(_) => 3,
+        }
+    }
+
+    /// Returns the length of the trailer of data messages in bytes.
+    pub fn trailer_len(&self) -> usize {
+        match self {
+            Integrity::None => 0,
+            Integrity::Crc32 => CRC32_LEN,
+            Integrity::Hmac(_) => HMAC_LEN,
+            Integrity::ChaCha20Poly1305(_) => TAG_LEN,
+        }
+    }
+
+    /// Appends the trailer to `bs`.
+    pub fn seal(&mut self, bs: &mut Vec<u8>) {
+        match self {
+            Integrity::None => {}
+            Integrity::Crc32 => {
+                let crc = crc32fast::hash(bs);
+                bs.write_u32::<BigEndian>(crc).unwrap();
+            }
+            Integrity::Hmac(authentication) => authentication.seal(bs),
+            Integrity::ChaCha20Poly1305(encryption) => encryption.seal(bs),
+        }
+    }
+
+    /// Verifies the trailer of `bs` and returns the message without it.
+    /// Encrypted messages are decrypted in place.
+    pub fn open<'a>(&mut self, bs: &'a mut [u8]) -> Result<&'a [u8], HoipError> {
+        match self {
+            Integrity::Hmac(authentication) => authentication.open(bs),
+            Integrity::ChaCha20Poly1305(encryption) => encryption.open(bs),
+            _ => self.verify(bs),
+        }
+    }
+
+    // Verifies the checksum of unauthenticated messages.
+    fn verify<'a>(&self, bs: &'a [u8]) -> Result<&'a [u8], HoipError> {
+        let trailer_len = self.trailer_len();
+        if bs.len() < trailer_len {
+            return Err(HoipError::BodyTooShort {
+                len: bs.len(),
+                expected: trailer_len,
+            });
+        }
+        let (msg, trailer) = bs.split_at(bs.len() - trailer_len);
+        match self {
+            Integrity::None => {}
+            Integrity::Crc32 => {
+                if crc32fast::hash(msg) != BigEndian::read_u32(trailer) {
+                    return Err(HoipError::ChecksumMismatch);
+                }
+            }
+            Integrity::Hmac(_) | Integrity::ChaCha20Poly1305(_) => unreachable!("opened above"),
+        }
+        Ok(msg)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hoip::ControlAck;
+
+    #[test]
+    fn basic() {
+        for mut integrity in vec![Integrity::None, Integrity::Crc32].into_iter() {
+            let mut bs = vec![1, 2, 3, 4];
+            integrity.seal(&mut bs);
+            assert_eq!(bs.len(), 4 + integrity.trailer_len());
+            assert_eq!(integrity.open(&mut bs), Ok(&[1, 2, 3, 4][..]));
+        }
+
+        let mut master = Integrity::hmac(b"secret");
+        let mut slave = master.clone();
+        slave.set_local(PayloadType::Slave);
+        let plain = ControlAck { next: 1 }.to_bytes();
+        let mut bs = plain.clone();
+        master.seal(&mut bs);
+        assert_eq!(slave.open(&mut bs), Ok(&plain[..]));
+    }
+
+    #[test]
+    fn corrupted() {
+        let mut bs = vec![1, 2, 3, 4];
+        Integrity::Crc32.seal(&mut bs);
+        bs[0] ^= 0b100;
+        assert_eq!(
+            Integrity::Crc32.open(&mut bs),
+            Err(HoipError::ChecksumMismatch)
+        );
+
+        let mut bs = ControlAck { next: 1 }.to_bytes();
+        Integrity::hmac(b"master").seal(&mut bs);
+        assert_eq!(
+            Integrity::hmac(b"intruder").open(&mut bs),
+            Err(HoipError::AuthenticationFailed)
+        );
+    }
+}
@@ -0,0 +1,510 @@
+use super::compression::num_values;
+use super::{
+    unwrap_timestamp, Compression, DelayIndicator, Header, HoipError, Message, PayloadType,
+    Profile, SamplingScheme, Serializable, COMPACT_HEADER_LEN, COMPRESSION_LEN, HEADER_LEN,
+    KNOWN_OPTIONS, OPTIONS_BIT, OPTION_COMPACT, OPTION_COMPRESSION, OPTION_REDUNDANCY, OPTION_ROTT,
+};
+use crate::sampling::{Sample, SAMPLE_PERIOD};
+use bitvec::prelude::*;
+use byteorder::{BigEndian, ByteOrder};
+
+/// A `hoip` message borrowed from the buffer it was received into. The header
+/// fields are read from the buffer when they are accessed and the samples are
+/// decoded while iterating over them, so nothing is copied or allocated.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct MessageRef<'a> {
+    bs: &'a [u8],
+    // The position of the options byte, if there is one.
+    options: Option<usize>,
+    // The position of the threshold, the first of the fixed header fields.
+    fixed: usize,
+    header_len: usize,
+    // The timestamp that compact timestamps are unwrapped against.
+    reference: Option<u64>,
+    // The period between the samples in [us] if their delays are not sent.
+    sample_period: u64,
+}
+
+impl<'a> MessageRef<'a> {
+    /// Checks that `bs` holds a valid header and borrows the message.
+    pub fn new(bs: &'a [u8]) -> Result<Self, HoipError> {
+        // Only messages with an options byte can use the compact profile.
+        let min_len = match bs.first() {
+            Some(byte) if byte.bits::<bitvec::cursor::BigEndian>()[OPTIONS_BIT] => {
+                COMPACT_HEADER_LEN
+            }
+            _ => HEADER_LEN,
+        };
+        if bs.len() < min_len {
+            return Err(HoipError::HeaderTooShort {
+                len: bs.len(),
+                expected: min_len,
+            });
+        }
+        let byte = bs[0];
+        let bits = byte.bits::<bitvec::cursor::BigEndian>();
+        let extended = bits[1];
+        // The optional header fields follow the first byte. The minimum
+        // header length guarantees that they can be read.
+        let mut pos = 1;
+        if extended {
+            pos += 1;
+        }
+        let mut options = None;
+        let mut fixed_len = HEADER_LEN - 1;
+        if bits[OPTIONS_BIT] {
+            let byte = bs[pos];
+            if byte == 0 || byte & !KNOWN_OPTIONS != 0 {
+                return Err(HoipError::ReservedBits { byte });
+            }
+            if byte & OPTION_ROTT != 0 && byte & OPTION_COMPACT == 0 {
+                return Err(HoipError::ReservedBits { byte });
+            }
+            if byte & OPTION_COMPACT != 0 {
+                fixed_len = COMPACT_HEADER_LEN - 2;
+                if byte & OPTION_ROTT != 0 {
+                    fixed_len += 3;
+                }
+            }
+            options = Some(pos);
+            pos += 1;
+            if byte & OPTION_REDUNDANCY != 0 {
+                pos += 1;
+            }
+            if byte & OPTION_COMPRESSION != 0 {
+                Compression::decode(&bs[pos..])?;
+                pos += COMPRESSION_LEN;
+            }
+        }
+        let header_len = pos + fixed_len;
+        if bs.len() < header_len {
+            return Err(HoipError::HeaderTooShort {
+                len: bs.len(),
+                expected: header_len,
+            });
+        }
+        if bits[3] && bits[4] {
+            return Err(HoipError::ReservedBits { byte });
+        }
+        if extended && (bits[5] || bits[6]) {
+            return Err(HoipError::ReservedBits { byte });
+        }
+        Ok(Self {
+            bs,
+            options,
+            fixed: pos,
+            header_len,
+            reference: None,
+            sample_period: SAMPLE_PERIOD,
+        })
+    }
+
+    /// Sets the timestamp that the timestamp of a compact message is
+    /// unwrapped against, see `unwrap_timestamp`. Without a reference only
+    /// its lower 32 bits are returned.
+    pub fn with_reference(mut self, reference: u64) -> Self {
+        self.reference = Some(reference);
+        self
+    }
+
+    /// Sets the period in [us] between the samples for
+    /// `DelayIndicator::InHeader`. It defaults to one millisecond.
+    pub fn with_sample_period(mut self, sample_period: u64) -> Self {
+        self.sample_period = sample_period;
+        self
+    }
+
+    fn bit(&self, i: usize) -> bool {
+        self.bs[0].bits::<bitvec::cursor::BigEndian>()[i]
+    }
+
+    fn options(&self) -> u8 {
+        self.options.map_or(0, |pos| self.bs[pos])
+    }
+
+    // The fixed header fields behind the optional ones.
+    fn fixed(&self) -> &'a [u8] {
+        &self.bs[self.fixed..self.header_len]
+    }
+
+    /// Returns the layout of the fixed header fields.
+    pub fn profile(&self) -> Profile {
+        match self.options() & OPTION_COMPACT {
+            0 => Profile::Standard,
+            _ => Profile::Compact {
+                rott: self.options() & OPTION_ROTT != 0,
+            },
+        }
+    }
+
+    pub fn payload_type(&self) -> PayloadType {
+        match self.bit(0) {
+            false => PayloadType::Master,
+            true => PayloadType::Slave,
+        }
+    }
+
+    pub fn sampling_scheme(&self) -> SamplingScheme {
+        match (self.bit(4), self.bit(3)) {
+            (false, false) => SamplingScheme::Lossless,
+            (false, true) => SamplingScheme::Weber,
+            (true, false) => SamplingScheme::LevelCrossing,
+            (true, true) => unreachable!("reserved sampling scheme"),
+        }
+    }
+
+    pub fn num_samples(&self) -> u8 {
+        match (self.is_extended(), self.bit(6), self.bit(5)) {
+            (true, _, _) => self.bs[1],
+            (false, false, false) => 1,
+            (false, false, true) => 2,
+            (false, true, false) => 3,
+            (false, true, true) => 4,
+        }
+    }
+
+    pub fn delay_indicator(&self) -> DelayIndicator {
+        match self.bit(7) {
+            false => DelayIndicator::InHeader,
+            true => DelayIndicator::InPayload,
+        }
+    }
+
+    pub fn threshold(&self) -> u16 {
+        BigEndian::read_u16(&self.fixed()[0..2])
+    }
+
+    /// Returns the rott, or 0 if a compact message doesn't carry it.
+    pub fn rott(&self) -> u32 {
+        match self.profile() {
+            Profile::Compact { rott: false } => 0,
+            _ => BigEndian::read_u24(&self.fixed()[2..5]),
+        }
+    }
+
+    pub fn timestamp(&self) -> u64 {
+        let timestamp = match self.profile() {
+            Profile::Standard => return BigEndian::read_u64(&self.fixed()[5..13]),
+            Profile::Compact { rott: false } => BigEndian::read_u32(&self.fixed()[2..6]),
+            Profile::Compact { rott: true } => BigEndian::read_u32(&self.fixed()[5..9]),
+        };
+        match self.reference {
+            Some(reference) => unwrap_timestamp(reference, timestamp),
+            None => u64::from(timestamp),
+        }
+    }
+
+    pub fn sequence(&self) -> u16 {
+        BigEndian::read_u16(&self.bs[self.header_len - 2..self.header_len])
+    }
+
+    /// Returns the number of repeated samples of the previous message.
+    pub fn redundancy(&self) -> u8 {
+        match self.options() & OPTION_REDUNDANCY {
+            0 => 0,
+            _ => self.bs[self.options.unwrap() + 1],
+        }
+    }
+
+    pub fn compression(&self) -> Option<Compression> {
+        if self.options() & OPTION_COMPRESSION == 0 {
+            return None;
+        }
+        let mut pos = self.options.unwrap() + 1;
+        if self.options() & OPTION_REDUNDANCY != 0 {
+            pos += 1;
+        }
+        Compression::decode(&self.bs[pos..]).ok()
+    }
+
+    /// Returns true if the message uses the extended header.
+    pub fn is_extended(&self) -> bool {
+        self.bit(1)
+    }
+
+    /// Returns the length of the header of this message in bytes.
+    pub fn header_len(&self) -> usize {
+        self.header_len
+    }
+
+    /// Reads all header fields.
+    pub fn header(&self) -> Header {
+        Header {
+            payload_type: self.payload_type(),
+            sampling_scheme: self.sampling_scheme(),
+            num_samples: self.num_samples(),
+            delay_indicator: self.delay_indicator(),
+            threshold: self.threshold(),
+            rott: self.rott(),
+            timestamp: self.timestamp(),
+            sequence: self.sequence(),
+            redundancy: self.redundancy(),
+            compression: self.compression(),
+            profile: self.profile(),
+        }
+    }
+
+    pub fn payload(&self) -> &'a [u8] {
+        &self.bs[self.header_len..]
+    }
+
+    /// Copies the message into an owned one.
+    pub fn to_message(&self) -> Message {
+        Message {
+            header: self.header(),
+            payload: self.payload().to_vec(),
+        }
+    }
+
+    /// Checks the payload against the header and returns an iterator that
+    /// decodes the samples together with the time they were taken at, like
+    /// `Message::timed_samples`.
+    pub fn samples<T: Serializable + Sample>(&self) -> Result<Samples<'a, T>, HoipError> {
+        Samples::new(&self.header(), self.payload(), false, self.sample_period)
+    }
+
+    /// Checks the payload against the header and returns an iterator that
+    /// decodes the repeated samples of the previous message, like
+    /// `Message::redundant_samples`.
+    pub fn redundant_samples<T: Serializable + Sample>(&self) -> Result<Samples<'a, T>, HoipError> {
+        Samples::new(&self.header(), self.payload(), true, self.sample_period)
+    }
+}
+
+/// An iterator decoding the samples of a message payload on demand.
+#[derive(Debug, Clone)]
+pub struct Samples<'a, T> {
+    bs: &'a [u8],
+    i: usize,
+    n: usize,
+    first_len: usize,
+    next_len: usize,
+    delay_len: usize,
+    timestamp: u64,
+    sample_period: u64,
+    delay_indicator: DelayIndicator,
+    redundant: bool,
+    compression: Option<Compression>,
+    // The previously decoded sample that compressed samples are coded against.
+    prev: Option<T>,
+}
+
+impl<'a, T: Serializable + Sample> Samples<'a, T> {
+    /// Checks `payload` against `header` and selects the samples or the
+    /// redundant samples.
+    pub(crate) fn new(
+        header: &Header,
+        payload: &'a [u8],
+        redundant: bool,
+        sample_period: u64,
+    ) -> Result<Self, HoipError> {
+        let delay_len = header.delay_len();
+        let (first_len, next_len) = match &header.compression {
+            Some(compression) => compression.sample_lens(num_values(&T::default()), delay_len),
+            None => (delay_len + T::LEN, delay_len + T::LEN),
+        };
+        let offset = |i: usize| match i {
+            0 => 0,
+            i => first_len + (i - 1) * next_len,
+        };
+        let num_samples = usize::from(header.num_samples);
+        let redundancy = usize::from(header.redundancy);
+        if first_len == 0 {
+            return Err(HoipError::EmptySample);
+        }
+        if header.compression.is_none() && payload.len() % first_len != 0 {
+            return Err(HoipError::PayloadLength {
+                len: payload.len(),
+                sample_len: first_len,
+            });
+        }
+        if header.compression.is_none() && payload.len() / first_len != num_samples + redundancy {
+            return Err(HoipError::NumSamplesMismatch {
+                num_samples: header.num_samples,
+                actual: (payload.len() / first_len).saturating_sub(redundancy),
+            });
+        }
+        if offset(num_samples) + offset(redundancy) != payload.len() {
+            return Err(HoipError::PayloadLength {
+                len: payload.len(),
+                sample_len: first_len,
+            });
+        }
+        let (bs, n) = if redundant {
+            (&payload[offset(num_samples)..], redundancy)
+        } else {
+            (&payload[..offset(num_samples)], num_samples)
+        };
+        Ok(Self {
+            bs,
+            i: 0,
+            n,
+            first_len,
+            next_len,
+            delay_len,
+            timestamp: header.timestamp,
+            sample_period,
+            delay_indicator: header.delay_indicator,
+            redundant,
+            compression: header.compression,
+            prev: None,
+        })
+    }
+}
+
+impl<'a, T: Serializable + Sample> Iterator for Samples<'a, T> {
+    type Item = Result<(u64, T), HoipError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.i == self.n {
+            return None;
+        }
+        let i = self.i;
+        let bs = match i {
+            0 => self.bs,
+            i => &self.bs[self.first_len + (i - 1) * self.next_len..],
+        };
+        let ts = match self.delay_indicator {
+            DelayIndicator::InHeader if self.redundant => self
+                .timestamp
+                .saturating_sub((self.n - i) as u64 * self.sample_period),
+            DelayIndicator::InHeader => {
+                self.timestamp.saturating_add(i as u64 * self.sample_period)
+            }
+            DelayIndicator::InPayload => self
+                .timestamp
+                .saturating_sub(u64::from(BigEndian::read_u16(bs))),
+        };
+        let bs = &bs[self.delay_len..];
+        let sample = match &self.compression {
+            Some(compression) => {
+                let sample = compression.read_sample(bs, self.prev.as_ref());
+                self.prev = Some(sample.clone());
+                sample
+            }
+            None => match T::decode(bs) {
+                Ok(sample) => sample,
+                Err(e) => {
+                    self.i = self.n;
+                    return Some(Err(e));
+                }
+            },
+        };
+        self.i += 1;
+        Some(Ok((ts, sample)))
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        let len = self.n - self.i;
+        (len, Some(len))
+    }
+}
+
+impl<'a, T: Serializable + Sample> ExactSizeIterator for Samples<'a, T> {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hoip::{Payload, PayloadM2S, Width, DELAY_LEN};
+
+    #[test]
+    fn borrowed() {
+        let header = Header {
+            payload_type: PayloadType::Slave,
+            sampling_scheme: SamplingScheme::Weber,
+            num_samples: 5,
+            delay_indicator: DelayIndicator::InPayload,
+            threshold: 10,
+            rott: 20,
+            timestamp: 10_000,
+            sequence: 7,
+            redundancy: 0,
+            compression: Some(Compression {
+                scale: 0.01,
+                width: Width::I16,
+                delta: Some(Width::I8),
+            }),
+            profile: Profile::Standard,
+        };
+        let samples = (0..5)
+            .map(|i| (5_000 + i * 1000, PayloadM2S::new([i as f32; 3], [0.5; 3])))
+            .collect::<Vec<_>>();
+        let redundant = [(4_000, PayloadM2S::new([-1.0; 3], [0.0; 3]))];
+        let msg = Message::with_redundancy(header, &samples, &redundant);
+        let bs = msg.to_bytes();
+
+        let view = MessageRef::new(&bs).unwrap();
+        assert_eq!(view.header(), msg.header);
+        assert_eq!(view.header_len(), msg.header_len());
+        assert_eq!(view.payload(), &msg.payload[..]);
+        assert_eq!(view.to_message(), msg);
+
+        let decoded = view.samples::<PayloadM2S>().unwrap();
+        assert_eq!(decoded.len(), 5);
+        let decoded = decoded.collect::<Result<Vec<_>, _>>().unwrap();
+        assert_eq!(decoded, samples);
+        let decoded = view.redundant_samples::<PayloadM2S>().unwrap();
+        assert_eq!(decoded.map(Result::unwrap).collect::<Vec<_>>(), redundant);
+
+        assert_eq!(
+            MessageRef::new(&bs[..bs.len() - 1])
+                .unwrap()
+                .samples::<PayloadM2S>()
+                .err(),
+            Some(HoipError::PayloadLength {
+                len: msg.payload.len() - 1,
+                sample_len: DELAY_LEN + 6 * 2,
+            })
+        );
+    }
+
+    #[test]
+    fn timestamp_overflow() {
+        let header = Header {
+            payload_type: PayloadType::Master,
+            sampling_scheme: SamplingScheme::Lossless,
+            num_samples: 2,
+            delay_indicator: DelayIndicator::InHeader,
+            threshold: 0,
+            rott: 0,
+            timestamp: u64::MAX,
+            sequence: 0,
+            redundancy: 0,
+            compression: None,
+            profile: Profile::Standard,
+        };
+        let sample = PayloadM2S::new([1.0; 3], [2.0; 3]);
+        let samples = [(u64::MAX, sample.clone()), (u64::MAX, sample)];
+        let bs = Message::with_samples(header, &samples).to_bytes();
+        // The timestamps of the later samples saturate.
+        let decoded = MessageRef::new(&bs)
+            .unwrap()
+            .samples::<PayloadM2S>()
+            .unwrap();
+        let decoded = decoded.collect::<Result<Vec<_>, _>>().unwrap();
+        assert_eq!(decoded, samples);
+    }
+
+    #[test]
+    fn empty_sample() {
+        let header = Header {
+            payload_type: PayloadType::Master,
+            sampling_scheme: SamplingScheme::Lossless,
+            num_samples: 1,
+            delay_indicator: DelayIndicator::InHeader,
+            threshold: 0,
+            rott: 0,
+            timestamp: 1,
+            sequence: 0,
+            redundancy: 0,
+            compression: None,
+            profile: Profile::Standard,
+        };
+        let bs = Message::with_samples(header, &[(1, Payload::<0>::default())]).to_bytes();
+        let view = MessageRef::new(&bs).unwrap();
+        assert_eq!(
+            view.samples::<Payload<0>>().err(),
+            Some(HoipError::EmptySample)
+        );
+    }
+}
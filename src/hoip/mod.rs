@@ -0,0 +1,1002 @@
+use crate::sampling::{Sample, SAMPLE_PERIOD};
+use bitvec::prelude::*;
+use byteorder::{BigEndian, ByteOrder, WriteBytesExt};
+use compression::num_values;
+use serde::{Deserialize, Serialize};
+use std::{error::Error, fmt};
+
+mod authentication;
+mod compression;
+mod control;
+mod encryption;
+mod fec;
+mod handshake;
+mod integrity;
+mod message_ref;
+mod payload;
+mod replay;
+mod report;
+
+pub use authentication::{Authentication, HMAC_LEN};
+pub use compression::{Compression, Width, COMPRESSION_LEN};
+pub use control::{
+    Control, ControlAck, ControlMessage, ControlReceiver, ControlSender, CONTROL_ACK_LEN,
+    CONTROL_HEADER_LEN,
+};
+pub use encryption::{Encryption, COUNTER_LEN, KEY_LEN, TAG_LEN};
+pub use fec::{FecConfig, Parity, XorDecoder, XorEncoder, PARITY_HEADER_LEN};
+pub use handshake::{Capabilities, Handshake, HandshakeError, HANDSHAKE_LEN, PROTOCOL_VERSION};
+pub use integrity::{Integrity, CRC32_LEN};
+pub use message_ref::{MessageRef, Samples};
+pub use network_emulator_derive::Serializable;
+pub use payload::{
+    Payload, Payload6DoFM2S, Payload6DoFS2M, Payload7DoFM2S, Payload7DoFS2M, PayloadM2S, PayloadS2M,
+};
+pub use replay::REPLAY_WINDOW;
+pub use report::{ReceiverReport, REPORT_LEN};
+
+/// The length of the `hoip` header in bytes.
+pub const HEADER_LEN: usize = 16;
+
+/// The length of the extended `hoip` header in bytes. It is used for
+/// messages with more than four samples and carries a full byte sample count.
+pub const EXTENDED_HEADER_LEN: usize = HEADER_LEN + 1;
+
+/// The minimum length of the compact `hoip` header in bytes, i.e. without the
+/// extended sample count, the rott and other optional fields.
+pub const COMPACT_HEADER_LEN: usize = 10;
+
+/// The maximum number of samples a message can carry.
+pub const MAX_SAMPLES: usize = 255;
+
+// Bit two of the first byte announces an options byte after the sample count.
+// Its flags denote optional header fields and payload sections.
+const OPTIONS_BIT: usize = 2;
+const OPTION_REDUNDANCY: u8 = 0b0000_0001;
+const OPTION_COMPRESSION: u8 = 0b0000_0010;
+// The compact profile shortens the timestamp to 32 bits and only carries the
+// rott if it is flagged.
+const OPTION_COMPACT: u8 = 0b0000_0100;
+const OPTION_ROTT: u8 = 0b0000_1000;
+const KNOWN_OPTIONS: u8 = OPTION_REDUNDANCY | OPTION_COMPRESSION | OPTION_COMPACT | OPTION_ROTT;
+
+// Both sampling scheme bits set in the first byte mark a packet that carries
+// no haptic data. The lowest three bits then denote its kind.
+const NON_DATA_CLASS: u8 = 0b0001_1000;
+const KIND_MASK: u8 = 0b0000_0111;
+
+/// The length of the per-sample delay for `DelayIndicator::InPayload` in bytes.
+pub const DELAY_LEN: usize = 2;
+
+/// An error that occurs while decoding a `hoip` message.
+#[derive(Debug, Clone, PartialEq)]
+pub enum HoipError {
+    /// The message is shorter than the header.
+    HeaderTooShort { len: usize, expected: usize },
+    /// A non-data message is shorter than its fixed length.
+    BodyTooShort { len: usize, expected: usize },
+    /// The first header byte contains a reserved bit pattern.
+    ReservedBits { byte: u8 },
+    /// A sample is shorter than the size of its type.
+    SampleTooShort { len: usize, expected: usize },
+    /// The payload length is not a multiple of the sample size.
+    PayloadLength { len: usize, sample_len: usize },
+    /// The samples are encoded in zero bytes, so they can't be counted.
+    EmptySample,
+    /// The number of samples in the header disagrees with the payload.
+    NumSamplesMismatch { num_samples: u8, actual: usize },
+    /// The CRC32 trailer doesn't match the message.
+    ChecksumMismatch,
+    /// The HMAC or AEAD tag doesn't match the message.
+    AuthenticationFailed,
+    /// An encrypted message was already received or is older than the
+    /// replay window.
+    Replayed { sequence: u64 },
+}
+
+impl fmt::Display for HoipError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            HoipError::HeaderTooShort { len, expected } => write!(
+                f,
+                "message of {} bytes is shorter than the {} byte header",
+                len, expected
+            ),
+            HoipError::BodyTooShort { len, expected } => {
+                write!(f, "message of {} bytes, expected {} bytes", len, expected)
+            }
+            HoipError::ReservedBits { byte } => {
+                write!(f, "reserved bit pattern in header byte {:#010b}", byte)
+            }
+            HoipError::SampleTooShort { len, expected } => {
+                write!(f, "sample of {} bytes, expected {} bytes", len, expected)
+            }
+            HoipError::PayloadLength { len, sample_len } => write!(
+                f,
+                "payload of {} bytes is not a multiple of the sample size {}",
+                len, sample_len
+            ),
+            HoipError::EmptySample => write!(f, "samples of zero bytes can't be decoded"),
+            HoipError::NumSamplesMismatch {
+                num_samples,
+                actual,
+            } => write!(
+                f,
+                "header announces {} samples but payload contains {}",
+                num_samples, actual
+            ),
+            HoipError::ChecksumMismatch => write!(f, "checksum mismatch"),
+            HoipError::AuthenticationFailed => write!(f, "authentication failed"),
+            HoipError::Replayed { sequence } => {
+                write!(f, "message {} was replayed or is too old", sequence)
+            }
+        }
+    }
+}
+
+impl Error for HoipError {}
+
+/// A packet governed by the `hoip` protocol.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Packet {
+    /// A message carrying haptic samples.
+    Data(Message),
+    /// A message negotiating the capabilities of the peers.
+    Handshake(Handshake),
+    /// A message carrying the parity over previous data messages.
+    Parity(Parity),
+    /// A reliably delivered control message.
+    Control(ControlMessage),
+    /// An acknowledgement of received control messages.
+    ControlAck(ControlAck),
+    /// Statistics about the data messages received by the peer.
+    Report(ReceiverReport),
+}
+
+impl Packet {
+    /// Returns true if `bs` holds a handshake message. Handshake messages
+    /// carry no integrity trailer.
+    pub fn is_handshake(bs: &[u8]) -> bool {
+        match bs.first() {
+            Some(byte) => {
+                byte & NON_DATA_CLASS == NON_DATA_CLASS && Handshake::is_kind(byte & KIND_MASK)
+            }
+            None => false,
+        }
+    }
+
+    /// Returns true if `bs` holds a data message.
+    pub fn is_data(bs: &[u8]) -> bool {
+        match bs.first() {
+            Some(byte) => byte & NON_DATA_CLASS != NON_DATA_CLASS,
+            None => false,
+        }
+    }
+
+    pub fn from_bytes(bs: &[u8]) -> Result<Self, HoipError> {
+        let byte = match bs.first() {
+            Some(byte) => *byte,
+            None => {
+                return Err(HoipError::HeaderTooShort {
+                    len: 0,
+                    expected: HEADER_LEN,
+                })
+            }
+        };
+        if byte & NON_DATA_CLASS != NON_DATA_CLASS {
+            return Message::from_bytes(bs).map(Packet::Data);
+        }
+        let kind = byte & KIND_MASK;
+        if Handshake::is_kind(kind) {
+            Handshake::from_bytes(bs).map(Packet::Handshake)
+        } else if Parity::is_kind(kind) {
+            Parity::from_bytes(bs).map(Packet::Parity)
+        } else if ControlMessage::is_kind(kind) {
+            ControlMessage::from_bytes(bs).map(Packet::Control)
+        } else if ControlAck::is_kind(kind) {
+            ControlAck::from_bytes(bs).map(Packet::ControlAck)
+        } else if ReceiverReport::is_kind(kind) {
+            ReceiverReport::from_bytes(bs).map(Packet::Report)
+        } else {
+            Err(HoipError::ReservedBits { byte })
+        }
+    }
+}
+
+/// The payload type of this message.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum PayloadType {
+    Master,
+    Slave,
+}
+
+/// The sampling method used.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SamplingScheme {
+    /// Lossless sampling.
+    Lossless,
+    /// Sampling based on Weber thresholds.
+    Weber,
+    /// Sampling based on level crossing.
+    LevelCrossing,
+}
+
+/// Where delays are saved to.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum DelayIndicator {
+    /// Delay is saved inside of the header.
+    InHeader,
+    /// Delay is saved inside of the payload. Every sample is preceded by
+    /// the time in [us] between its sampling and the message timestamp.
+    InPayload,
+}
+
+/// The layout of the fixed header fields.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Profile {
+    /// The rott is always sent and the timestamp has 64 bits.
+    Standard,
+    /// The timestamp is cut to its lower 32 bits, which wrap after about 71
+    /// minutes, and the rott is only sent if `rott` is set. Receivers restore
+    /// the full timestamp with `unwrap_timestamp`, starting from the clock
+    /// the peer sent in its handshake.
+    ///
+    /// The profile is flagged in the options byte instead of a version bit.
+    /// All bits of the first byte are taken, and receivers that don't know
+    /// the flag reject the message as it has unknown options, so a compact
+    /// header is never mistaken for a standard one.
+    Compact { rott: bool },
+}
+
+/// Restores a timestamp of the compact profile from its lower 32 bits by
+/// picking the value closest to `reference`, e.g. the previous timestamp
+/// received from the same peer.
+pub fn unwrap_timestamp(reference: u64, timestamp: u32) -> u64 {
+    let diff = timestamp.wrapping_sub(reference as u32) as i32;
+    std::cmp::max(0, reference as i64 + i64::from(diff)) as u64
+}
+
+/// The header of a `hoip` message.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Header {
+    pub payload_type: PayloadType,
+    pub sampling_scheme: SamplingScheme,
+    /// The number of samples that are stored inside of the payload.
+    /// This depends on the compression parameter `k`. More than four samples
+    /// require the extended header.
+    pub num_samples: u8,
+    /// Where the delays are stored.
+    pub delay_indicator: DelayIndicator,
+    /// The threshold currently used (e.g. for Weber/LevelCrossing)
+    pub threshold: u16,
+    /// The latest rott that was measured by the network module.
+    pub rott: u32,
+    /// The timestamp of when this message was sent away.
+    pub timestamp: u64,
+    /// The sequence number of this message, counted per direction.
+    pub sequence: u16,
+    /// The number of samples of the previous message that are repeated after
+    /// the samples of this message to conceal single losses.
+    pub redundancy: u8,
+    /// The lossy compression of the samples, if any.
+    pub compression: Option<Compression>,
+    /// The layout of the threshold, rott, timestamp and sequence.
+    pub profile: Profile,
+}
+
+/// A message governed by the `hoip` protocol.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Message {
+    /// The header of the message describing the content.
+    pub header: Header,
+    /// The content containing the samples.
+    pub payload: Vec<u8>,
+}
+
+/// A sample that can be sent inside of a `hoip` message. It can be derived
+/// for `#[repr(C)]` structs of integer and float scalars and arrays.
+pub trait Serializable: Sized {
+    /// The length of an encoded sample in bytes.
+    const LEN: usize;
+
+    /// Encodes the sample into the first `LEN` bytes of `bs`.
+    ///
+    /// Panics if `bs` is shorter than `LEN`.
+    fn encode(&self, bs: &mut [u8]);
+
+    /// Decodes a sample from the first `LEN` bytes of `bs`.
+    fn decode(bs: &[u8]) -> Result<Self, HoipError>;
+}
+
+impl Header {
+    /// Returns true if the header needs the extended layout.
+    pub fn is_extended(&self) -> bool {
+        self.num_samples == 0 || self.num_samples > 4
+    }
+
+    /// Returns the length of the encoded header in bytes.
+    pub fn encoded_len(&self) -> usize {
+        let mut len = match self.profile {
+            Profile::Standard => HEADER_LEN,
+            Profile::Compact { rott: false } => COMPACT_HEADER_LEN - 1,
+            Profile::Compact { rott: true } => COMPACT_HEADER_LEN + 2,
+        };
+        if self.is_extended() {
+            len += 1;
+        }
+        let options = self.options();
+        if options != 0 {
+            len += 1;
+        }
+        if options & OPTION_REDUNDANCY != 0 {
+            len += 1;
+        }
+        if options & OPTION_COMPRESSION != 0 {
+            len += COMPRESSION_LEN;
+        }
+        len
+    }
+
+    fn options(&self) -> u8 {
+        let mut options = 0;
+        if self.redundancy > 0 {
+            options |= OPTION_REDUNDANCY;
+        }
+        if self.compression.is_some() {
+            options |= OPTION_COMPRESSION;
+        }
+        match self.profile {
+            Profile::Standard => {}
+            Profile::Compact { rott: false } => options |= OPTION_COMPACT,
+            Profile::Compact { rott: true } => options |= OPTION_COMPACT | OPTION_ROTT,
+        }
+        options
+    }
+
+    /// Returns the length of the payload of `num_samples` and `redundancy`
+    /// samples of type `T` in bytes.
+    pub fn payload_len<T: Serializable + Sample>(&self) -> usize {
+        let num_samples = usize::from(self.num_samples) + usize::from(self.redundancy);
+        let delay_len = self.delay_len();
+        match &self.compression {
+            Some(compression) if num_samples > 0 => {
+                let (first_len, next_len) =
+                    compression.sample_lens(num_values(&T::default()), delay_len);
+                first_len + (num_samples - 1) * next_len
+            }
+            Some(_) => 0,
+            None => num_samples * (delay_len + T::LEN),
+        }
+    }
+
+    fn delay_len(&self) -> usize {
+        match self.delay_indicator {
+            DelayIndicator::InHeader => 0,
+            DelayIndicator::InPayload => DELAY_LEN,
+        }
+    }
+
+    /// Appends the encoded header to `wtr`.
+    pub fn encode(&self, wtr: &mut Vec<u8>) {
+        let mut byte = 0;
+        let bits = byte.bits_mut::<bitvec::cursor::BigEndian>();
+        match self.payload_type {
+            PayloadType::Master => *bits.at(0) = false,
+            PayloadType::Slave => *bits.at(0) = true,
+        };
+        match self.sampling_scheme {
+            SamplingScheme::Lossless => {}
+            SamplingScheme::Weber => *bits.at(3) = true,
+            SamplingScheme::LevelCrossing => *bits.at(4) = true,
+        };
+        match self.num_samples {
+            1 => {}
+            2 => *bits.at(5) = true,
+            3 => *bits.at(6) = true,
+            4 => {
+                *bits.at(5) = true;
+                *bits.at(6) = true;
+            }
+            _ => *bits.at(1) = true,
+        }
+        match self.delay_indicator {
+            DelayIndicator::InHeader => {}
+            DelayIndicator::InPayload => *bits.at(7) = true,
+        };
+
+        let options = self.options();
+        if options != 0 {
+            *bits.at(OPTIONS_BIT) = true;
+        }
+
+        wtr.write_u8(byte).unwrap();
+        if self.is_extended() {
+            wtr.write_u8(self.num_samples).unwrap();
+        }
+        if options != 0 {
+            wtr.write_u8(options).unwrap();
+        }
+        if options & OPTION_REDUNDANCY != 0 {
+            wtr.write_u8(self.redundancy).unwrap();
+        }
+        if let Some(compression) = &self.compression {
+            compression.encode(wtr);
+        }
+        wtr.write_u16::<BigEndian>(self.threshold).unwrap();
+        let rott = std::cmp::min(0xFFFFFF, self.rott);
+        match self.profile {
+            Profile::Standard => {
+                wtr.write_u24::<BigEndian>(rott).unwrap();
+                wtr.write_u64::<BigEndian>(self.timestamp).unwrap();
+            }
+            Profile::Compact { rott: with_rott } => {
+                if with_rott {
+                    wtr.write_u24::<BigEndian>(rott).unwrap();
+                }
+                wtr.write_u32::<BigEndian>(self.timestamp as u32).unwrap();
+            }
+        }
+        wtr.write_u16::<BigEndian>(self.sequence).unwrap();
+    }
+}
+
+impl Message {
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut wtr = Vec::with_capacity(self.header_len() + self.payload.len());
+        self.encode(&mut wtr);
+        wtr
+    }
+
+    /// Appends the encoded message to `wtr`.
+    pub fn encode(&self, wtr: &mut Vec<u8>) {
+        self.header.encode(wtr);
+        wtr.extend_from_slice(&self.payload);
+    }
+
+    pub fn from_bytes(bs: &[u8]) -> Result<Self, HoipError> {
+        MessageRef::new(bs).map(|msg| msg.to_message())
+    }
+
+    /// Creates a message from samples and the timestamps they were taken at.
+    /// The payload is laid out according to `header.delay_indicator`.
+    pub fn with_samples<T: Serializable + Sample>(header: Header, samples: &[(u64, T)]) -> Self {
+        Self::with_redundancy(header, samples, &[])
+    }
+
+    /// Creates a message from samples and repeats the `redundant` samples of
+    /// the previous message after them. `header.redundancy` is set to their
+    /// number.
+    pub fn with_redundancy<T: Serializable + Sample>(
+        mut header: Header,
+        samples: &[(u64, T)],
+        redundant: &[(u64, T)],
+    ) -> Self {
+        header.redundancy = redundancy(redundant);
+        let num_samples = samples.len() + redundant.len();
+        let mut payload = Vec::with_capacity(num_samples * (DELAY_LEN + T::LEN));
+        write_samples(&header, samples, redundant, &mut payload);
+        Self { header, payload }
+    }
+
+    /// Appends a message with samples and repeated `redundant` samples to
+    /// `wtr` without creating the message. `header.redundancy` is set to
+    /// their number. Nothing is allocated if `wtr` has enough capacity.
+    pub fn encode_samples<T: Serializable + Sample>(
+        mut header: Header,
+        samples: &[(u64, T)],
+        redundant: &[(u64, T)],
+        wtr: &mut Vec<u8>,
+    ) {
+        header.redundancy = redundancy(redundant);
+        header.encode(wtr);
+        write_samples(&header, samples, redundant, wtr);
+    }
+
+    /// Decodes the samples of the payload and checks them against the header.
+    pub fn samples<T: Serializable + Sample>(&self) -> Result<Vec<T>, HoipError> {
+        Ok(self
+            .timed_samples()?
+            .into_iter()
+            .map(|(_, sample)| sample)
+            .collect())
+    }
+
+    /// Decodes the samples of the payload together with the time they were
+    /// taken at. Without delays in the payload the samples are assumed to be
+    /// `SAMPLE_PERIOD` apart, starting at the message timestamp.
+    pub fn timed_samples<T: Serializable + Sample>(&self) -> Result<Vec<(u64, T)>, HoipError> {
+        self.decode_samples(false)
+    }
+
+    /// Decodes the repeated samples of the previous message together with the
+    /// time they were taken at. Without delays in the payload they are assumed
+    /// to be `SAMPLE_PERIOD` apart, ending before the message timestamp.
+    pub fn redundant_samples<T: Serializable + Sample>(&self) -> Result<Vec<(u64, T)>, HoipError> {
+        self.decode_samples(true)
+    }
+
+    fn decode_samples<T: Serializable + Sample>(
+        &self,
+        redundant: bool,
+    ) -> Result<Vec<(u64, T)>, HoipError> {
+        Samples::new(&self.header, &self.payload, redundant, SAMPLE_PERIOD)?.collect()
+    }
+
+    /// Returns the length of the header of this message in bytes.
+    pub fn header_len(&self) -> usize {
+        self.header.encoded_len()
+    }
+
+    /// Returns true if the message needs the extended header.
+    pub fn is_extended(&self) -> bool {
+        self.header.is_extended()
+    }
+
+    pub fn rott(&self) -> u32 {
+        self.header.rott
+    }
+
+    pub fn timestamp(&self) -> u64 {
+        self.header.timestamp
+    }
+
+    pub fn sequence(&self) -> u16 {
+        self.header.sequence
+    }
+
+    pub fn num_samples(&self) -> u8 {
+        self.header.num_samples
+    }
+}
+
+fn redundancy<T>(redundant: &[(u64, T)]) -> u8 {
+    assert!(redundant.len() <= MAX_SAMPLES, "too many redundant samples");
+    redundant.len() as u8
+}
+
+/// Appends the samples followed by the redundant samples laid out according
+/// to `header.delay_indicator` and `header.compression`.
+fn write_samples<T: Serializable + Sample>(
+    header: &Header,
+    samples: &[(u64, T)],
+    redundant: &[(u64, T)],
+    wtr: &mut Vec<u8>,
+) {
+    if let Some(compression) = &header.compression {
+        write_compressed(header, compression, samples, wtr);
+        write_compressed(header, compression, redundant, wtr);
+        return;
+    }
+    for (ts, sample) in samples.iter().chain(redundant) {
+        if let DelayIndicator::InPayload = header.delay_indicator {
+            wtr.write_u16::<BigEndian>(delay(header, *ts)).unwrap();
+        }
+        let start = wtr.len();
+        wtr.resize(start + T::LEN, 0);
+        sample.encode(&mut wtr[start..]);
+    }
+}
+
+fn write_compressed<T: Sample>(
+    header: &Header,
+    compression: &Compression,
+    samples: &[(u64, T)],
+    wtr: &mut Vec<u8>,
+) {
+    let first = match samples.first() {
+        Some((_, first)) => first,
+        None => return,
+    };
+    let delay_len = header.delay_len();
+    let (first_len, next_len) = compression.sample_lens(num_values(first), delay_len);
+    let start = wtr.len();
+    wtr.resize(start + first_len + (samples.len() - 1) * next_len, 0);
+    let bs = &mut wtr[start..];
+    if delay_len > 0 {
+        for (i, (ts, _)) in samples.iter().enumerate() {
+            let offset = match i {
+                0 => 0,
+                i => first_len + (i - 1) * next_len,
+            };
+            BigEndian::write_u16(&mut bs[offset..], delay(header, *ts));
+        }
+    }
+    compression.write(samples.iter().map(|(_, sample)| sample), delay_len, bs);
+}
+
+/// Returns the time in [us] between the sampling at `ts` and the message
+/// timestamp.
+fn delay(header: &Header, ts: u64) -> u16 {
+    let delay = header.timestamp.saturating_sub(ts);
+    std::cmp::min(delay, u64::from(u16::MAX)) as u16
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn basic() {
+        for payload_type in vec![PayloadType::Master, PayloadType::Slave].into_iter() {
+            for sampling_scheme in vec![
+                SamplingScheme::Weber,
+                SamplingScheme::LevelCrossing,
+                SamplingScheme::Lossless,
+            ]
+            .into_iter()
+            {
+                for num_samples in vec![1, 2, 3, 4, 5, 32, 255].into_iter() {
+                    for delay_indicator in
+                        vec![DelayIndicator::InHeader, DelayIndicator::InPayload].into_iter()
+                    {
+                        let msg = Message {
+                            header: Header {
+                                payload_type,
+                                sampling_scheme,
+                                num_samples,
+                                delay_indicator,
+                                threshold: 10,
+                                rott: 1,
+                                timestamp: u64::MAX,
+                                sequence: u16::MAX,
+                                redundancy: 0,
+                                compression: None,
+                                profile: Profile::Standard,
+                            },
+                            payload: vec![1, 2, 3],
+                        };
+                        assert_eq!(Ok(msg.clone()), Message::from_bytes(&msg.to_bytes()));
+                    }
+                }
+            }
+        }
+    }
+
+    fn header(num_samples: u8) -> Header {
+        Header {
+            payload_type: PayloadType::Master,
+            sampling_scheme: SamplingScheme::Lossless,
+            num_samples,
+            delay_indicator: DelayIndicator::InHeader,
+            threshold: 10,
+            rott: 1,
+            timestamp: 1,
+            sequence: 1,
+            redundancy: 0,
+            compression: None,
+            profile: Profile::Standard,
+        }
+    }
+
+    #[test]
+    fn packet() {
+        let msg = Message {
+            header: header(1),
+            payload: vec![0; 12],
+        };
+        assert_eq!(Packet::from_bytes(&msg.to_bytes()), Ok(Packet::Data(msg)));
+
+        let handshake = Handshake::Hello(Capabilities {
+            version: PROTOCOL_VERSION,
+            header_len: HEADER_LEN as u8,
+            k_max: 4,
+            integrity: 0,
+            sample_period: 1000,
+            session: 1,
+            echo: 0,
+            time: 0,
+            sampling_scheme: SamplingScheme::Lossless,
+            send_len: 24,
+            recv_len: 12,
+        });
+        assert_eq!(
+            Packet::from_bytes(&handshake.to_bytes()),
+            Ok(Packet::Handshake(handshake))
+        );
+    }
+
+    #[test]
+    fn malformed() {
+        assert_eq!(
+            Message::from_bytes(&[0; 15]),
+            Err(HoipError::HeaderTooShort {
+                len: 15,
+                expected: HEADER_LEN
+            })
+        );
+        assert_eq!(
+            Message::from_bytes(&[0b0100_0000; 16]),
+            Err(HoipError::HeaderTooShort {
+                len: 16,
+                expected: EXTENDED_HEADER_LEN
+            })
+        );
+
+        let mut bs = Message {
+            header: header(1),
+            payload: vec![],
+        }
+        .to_bytes();
+        bs[0] |= 0b0001_1000;
+        assert_eq!(
+            Message::from_bytes(&bs),
+            Err(HoipError::ReservedBits { byte: bs[0] })
+        );
+
+        let msg = Message {
+            header: header(2),
+            payload: vec![0; 13],
+        };
+        assert_eq!(
+            msg.samples::<PayloadS2M>(),
+            Err(HoipError::PayloadLength {
+                len: 13,
+                sample_len: 12
+            })
+        );
+
+        let msg = Message {
+            header: header(2),
+            payload: vec![0; 36],
+        };
+        assert_eq!(
+            msg.samples::<PayloadS2M>(),
+            Err(HoipError::NumSamplesMismatch {
+                num_samples: 2,
+                actual: 3
+            })
+        );
+
+        assert_eq!(
+            PayloadM2S::decode(&[0; 23]),
+            Err(HoipError::SampleTooShort {
+                len: 23,
+                expected: 24
+            })
+        );
+    }
+
+    #[test]
+    fn samples() {
+        let payloads = vec![
+            PayloadM2S::new([1.0, 2.0, 3.0], [4.0, 5.0, 6.0]),
+            PayloadM2S::new([7.0, 8.0, 9.0], [10.0, 11.0, 12.0]),
+        ];
+        let mut payload = vec![0; 2 * PayloadM2S::LEN];
+        payloads[0].encode(&mut payload[..PayloadM2S::LEN]);
+        payloads[1].encode(&mut payload[PayloadM2S::LEN..]);
+        let msg = Message {
+            header: header(2),
+            payload,
+        };
+        let msg = Message::from_bytes(&msg.to_bytes()).unwrap();
+        assert_eq!(msg.samples::<PayloadM2S>(), Ok(payloads));
+    }
+
+    #[test]
+    fn extended_header() {
+        let msg = Message {
+            header: header(4),
+            payload: vec![0; 48],
+        };
+        assert_eq!(msg.to_bytes().len(), HEADER_LEN + 48);
+
+        let msg = Message {
+            header: header(32),
+            payload: vec![0; 32 * 12],
+        };
+        let bs = msg.to_bytes();
+        assert_eq!(bs.len(), EXTENDED_HEADER_LEN + 32 * 12);
+        assert_eq!(bs[1], 32);
+        let msg = Message::from_bytes(&bs).unwrap();
+        assert_eq!(msg.samples::<PayloadS2M>().unwrap().len(), 32);
+    }
+
+    #[test]
+    fn delays_in_payload() {
+        let mut header = header(3);
+        header.delay_indicator = DelayIndicator::InPayload;
+        header.timestamp = 10_000;
+        let samples = vec![
+            (7_500, PayloadS2M::new([1.0; 3])),
+            (8_000, PayloadS2M::new([2.0; 3])),
+            (10_000, PayloadS2M::new([3.0; 3])),
+        ];
+        let msg = Message::with_samples(header, &samples);
+        assert_eq!(msg.payload.len(), 3 * (DELAY_LEN + 12));
+        let msg = Message::from_bytes(&msg.to_bytes()).unwrap();
+        assert_eq!(msg.timed_samples::<PayloadS2M>(), Ok(samples));
+    }
+
+    #[test]
+    fn redundancy() {
+        for delay_indicator in vec![DelayIndicator::InHeader, DelayIndicator::InPayload].into_iter()
+        {
+            let mut header = header(2);
+            header.delay_indicator = delay_indicator;
+            header.timestamp = 10_000;
+            let samples = vec![
+                (10_000, PayloadS2M::new([3.0; 3])),
+                (11_000, PayloadS2M::new([4.0; 3])),
+            ];
+            let redundant = vec![
+                (8_000, PayloadS2M::new([1.0; 3])),
+                (9_000, PayloadS2M::new([2.0; 3])),
+            ];
+            let msg = Message::with_redundancy(header, &samples, &redundant);
+            let bs = msg.to_bytes();
+            assert_eq!(msg.header_len(), HEADER_LEN + 2);
+            let msg = Message::from_bytes(&bs).unwrap();
+            assert_eq!(msg.header.redundancy, 2);
+            assert_eq!(msg.redundant_samples::<PayloadS2M>(), Ok(redundant));
+            if delay_indicator == DelayIndicator::InHeader {
+                assert_eq!(msg.timed_samples::<PayloadS2M>(), Ok(samples));
+            }
+        }
+
+        let mut bs = Message::with_samples(header(1), &[(1, PayloadS2M::new([0.0; 3]))]).to_bytes();
+        bs[0] |= 0b0010_0000;
+        bs.insert(1, 0b1000_0000);
+        assert_eq!(
+            Message::from_bytes(&bs),
+            Err(HoipError::ReservedBits { byte: 0b1000_0000 })
+        );
+    }
+
+    #[repr(C)]
+    #[derive(Debug, Clone, PartialEq, Serializable)]
+    #[serializable(crate = "crate")]
+    struct Device {
+        id: u8,
+        gain: i16,
+        pose: [[f64; 2]; 3],
+        clock: u64,
+    }
+
+    #[test]
+    fn derive() {
+        assert_eq!(Device::LEN, 1 + 2 + 6 * 8 + 8);
+        let device = Device {
+            id: 7,
+            gain: -2,
+            pose: [[1.0, 2.0], [3.0, 4.0], [5.0, 6.0]],
+            clock: 0x0102_0304_0506_0708,
+        };
+        let mut bs = [0; Device::LEN];
+        device.encode(&mut bs);
+        assert_eq!(&bs[0..3], &[7, 0xFF, 0xFE]);
+        assert_eq!(&bs[51..], &[1, 2, 3, 4, 5, 6, 7, 8]);
+        assert_eq!(Device::decode(&bs), Ok(device));
+        assert_eq!(
+            Device::decode(&bs[1..]),
+            Err(HoipError::SampleTooShort {
+                len: 58,
+                expected: 59
+            })
+        );
+    }
+
+    #[test]
+    fn compression() {
+        let compression = Compression {
+            scale: 0.001,
+            width: Width::I16,
+            delta: Some(Width::I8),
+        };
+        let mut header = header(4);
+        header.delay_indicator = DelayIndicator::InPayload;
+        header.timestamp = 10_000;
+        header.compression = Some(compression);
+        let samples = (0..4)
+            .map(|i| {
+                let v = 1.0 + i as f32 * 0.01;
+                (
+                    7_000 + i * 1000,
+                    PayloadM2S::new([v, -v, 0.0], [0.0, 0.5, -0.5]),
+                )
+            })
+            .collect::<Vec<_>>();
+        // The last redundant value jumps too far for a single difference.
+        let redundant = vec![
+            (5_000, PayloadM2S::new([0.0; 3], [0.0; 3])),
+            (6_000, PayloadM2S::new([1.0; 3], [0.0; 3])),
+        ];
+        let msg = Message::with_redundancy(header, &samples, &redundant);
+        assert_eq!(
+            msg.payload.len(),
+            (DELAY_LEN + 6 * 2) * 2 + (DELAY_LEN + 6) * 4
+        );
+        let bs = msg.to_bytes();
+        assert_eq!(
+            bs.len(),
+            HEADER_LEN + 2 + COMPRESSION_LEN + msg.payload.len()
+        );
+        let msg = Message::from_bytes(&bs).unwrap();
+        assert_eq!(msg.header.compression, Some(compression));
+
+        let decoded = msg.timed_samples::<PayloadM2S>().unwrap();
+        for ((ts, sample), (decoded_ts, decoded)) in samples.iter().zip(decoded.iter()) {
+            assert_eq!(ts, decoded_ts);
+            for c in 0..PayloadM2S::num_channels() {
+                for (a, b) in sample.channel(c).iter().zip(decoded.channel(c)) {
+                    assert!((a - b).abs() <= 0.0005 + 1e-6);
+                }
+            }
+        }
+        // A saturated difference is only corrected by following samples.
+        let decoded = msg.redundant_samples::<PayloadM2S>().unwrap();
+        assert_eq!(decoded[0].1, redundant[0].1);
+        assert!((decoded[1].1.pos()[0] - 0.127).abs() < 1e-6);
+    }
+
+    #[test]
+    fn compression_overflow() {
+        let mut header = header(2);
+        header.compression = Some(Compression {
+            scale: 0.001,
+            width: Width::I16,
+            delta: Some(Width::I8),
+        });
+        let samples = vec![
+            (0, PayloadM2S::new([-1.0; 3], [0.0; 3])),
+            (0, PayloadM2S::new([f32::INFINITY; 3], [0.0; 3])),
+        ];
+        let msg = Message::with_samples(header, &samples);
+        // The difference of an infinite value to a negative one saturates.
+        let decoded = msg.timed_samples::<PayloadM2S>().unwrap();
+        assert_eq!(decoded[0].1.pos()[0], -1.0);
+        assert!((decoded[1].1.pos()[0] + 0.873).abs() < 1e-6);
+    }
+
+    #[test]
+    fn compact() {
+        let mut header = header(2);
+        header.timestamp = (5 << 32) + 2_000;
+        header.rott = 300;
+        header.profile = Profile::Compact { rott: true };
+        let samples = vec![(0, PayloadM2S::default()); 2];
+        let msg = Message::with_samples(header.clone(), &samples);
+        let bs = msg.to_bytes();
+        assert_eq!(bs.len(), COMPACT_HEADER_LEN + 3 + msg.payload.len());
+
+        // The timestamp is restored next to the reference, across a wrap.
+        let view = MessageRef::new(&bs).unwrap();
+        assert_eq!(view.timestamp(), 2_000);
+        assert_eq!(view.rott(), 300);
+        let view = view.with_reference((5 << 32) - 1_000);
+        assert_eq!(view.header(), header);
+        assert_eq!(
+            view.samples::<PayloadM2S>().unwrap().next(),
+            Some(Ok(((5 << 32) + 2_000, PayloadM2S::default())))
+        );
+
+        header.profile = Profile::Compact { rott: false };
+        let bs = Message::with_samples(header.clone(), &samples).to_bytes();
+        assert_eq!(bs.len(), COMPACT_HEADER_LEN + msg.payload.len());
+        let view = MessageRef::new(&bs).unwrap().with_reference(5 << 32);
+        assert_eq!(view.rott(), 0);
+        assert_eq!(view.timestamp(), header.timestamp);
+        assert_eq!(view.sequence(), header.sequence);
+        assert_eq!(
+            MessageRef::new(&bs[..COMPACT_HEADER_LEN - 1]).err(),
+            Some(HoipError::HeaderTooShort {
+                len: COMPACT_HEADER_LEN - 1,
+                expected: COMPACT_HEADER_LEN
+            })
+        );
+
+        let mut bs = bs;
+        bs[1] = OPTION_ROTT;
+        assert_eq!(
+            MessageRef::new(&bs).err(),
+            Some(HoipError::ReservedBits { byte: OPTION_ROTT })
+        );
+
+        assert_eq!(unwrap_timestamp(u64::from(u32::MAX), 5), (1 << 32) + 5);
+        assert_eq!(
+            unwrap_timestamp((1 << 32) + 5, u32::MAX),
+            u64::from(u32::MAX)
+        );
+        assert_eq!(unwrap_timestamp(0, u32::MAX), 0);
+    }
+}
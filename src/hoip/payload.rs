@@ -0,0 +1,415 @@
+use super::Serializable;
+use crate::sampling::Sample;
+use std::slice;
+
+#[repr(C)]
+#[derive(Debug, Clone, Default, PartialEq, Serializable)]
+#[serializable(crate = "crate")]
+pub struct PayloadS2M {
+    force: [f32; 3],
+}
+
+impl PayloadS2M {
+    pub fn new(force: [f32; 3]) -> Self {
+        Self { force }
+    }
+
+    pub fn force(&self) -> [f32; 3] {
+        self.force
+    }
+}
+
+#[repr(C)]
+#[derive(Debug, Clone, Default, PartialEq, Serializable)]
+#[serializable(crate = "crate")]
+pub struct PayloadM2S {
+    pos: [f32; 3],
+    vel: [f32; 3],
+}
+
+impl PayloadM2S {
+    pub fn new(pos: [f32; 3], vel: [f32; 3]) -> Self {
+        Self { pos, vel }
+    }
+
+    pub fn pos(&self) -> [f32; 3] {
+        self.pos
+    }
+
+    pub fn vel(&self) -> [f32; 3] {
+        self.vel
+    }
+}
+
+/// The 6-DoF pose and twist of the master device.
+#[repr(C)]
+#[derive(Debug, Clone, Default, PartialEq, Serializable)]
+#[serializable(crate = "crate")]
+pub struct Payload6DoFM2S {
+    pos: [f32; 3],
+    /// Unit quaternion in the order w, x, y, z.
+    orientation: [f32; 4],
+    vel: [f32; 3],
+    angular_vel: [f32; 3],
+}
+
+impl Payload6DoFM2S {
+    pub fn new(pos: [f32; 3], orientation: [f32; 4], vel: [f32; 3], angular_vel: [f32; 3]) -> Self {
+        Self {
+            pos,
+            orientation,
+            vel,
+            angular_vel,
+        }
+    }
+
+    pub fn pos(&self) -> [f32; 3] {
+        self.pos
+    }
+
+    /// Returns the orientation as unit quaternion in the order w, x, y, z.
+    pub fn orientation(&self) -> [f32; 4] {
+        self.orientation
+    }
+
+    pub fn vel(&self) -> [f32; 3] {
+        self.vel
+    }
+
+    pub fn angular_vel(&self) -> [f32; 3] {
+        self.angular_vel
+    }
+}
+
+/// The 6-DoF force and torque feedback of the slave device.
+#[repr(C)]
+#[derive(Debug, Clone, Default, PartialEq, Serializable)]
+#[serializable(crate = "crate")]
+pub struct Payload6DoFS2M {
+    force: [f32; 3],
+    torque: [f32; 3],
+}
+
+impl Payload6DoFS2M {
+    pub fn new(force: [f32; 3], torque: [f32; 3]) -> Self {
+        Self { force, torque }
+    }
+
+    pub fn force(&self) -> [f32; 3] {
+        self.force
+    }
+
+    pub fn torque(&self) -> [f32; 3] {
+        self.torque
+    }
+}
+
+/// The 6-DoF pose and twist of the master device and the commanded gripper
+/// opening.
+#[repr(C)]
+#[derive(Debug, Clone, Default, PartialEq, Serializable)]
+#[serializable(crate = "crate")]
+pub struct Payload7DoFM2S {
+    pos: [f32; 3],
+    /// Unit quaternion in the order w, x, y, z.
+    orientation: [f32; 4],
+    vel: [f32; 3],
+    angular_vel: [f32; 3],
+    gripper: f32,
+}
+
+impl Payload7DoFM2S {
+    pub fn new(
+        pos: [f32; 3],
+        orientation: [f32; 4],
+        vel: [f32; 3],
+        angular_vel: [f32; 3],
+        gripper: f32,
+    ) -> Self {
+        Self {
+            pos,
+            orientation,
+            vel,
+            angular_vel,
+            gripper,
+        }
+    }
+
+    pub fn pos(&self) -> [f32; 3] {
+        self.pos
+    }
+
+    /// Returns the orientation as unit quaternion in the order w, x, y, z.
+    pub fn orientation(&self) -> [f32; 4] {
+        self.orientation
+    }
+
+    pub fn vel(&self) -> [f32; 3] {
+        self.vel
+    }
+
+    pub fn angular_vel(&self) -> [f32; 3] {
+        self.angular_vel
+    }
+
+    pub fn gripper(&self) -> f32 {
+        self.gripper
+    }
+}
+
+/// The 6-DoF force and torque feedback of the slave device and the force of
+/// the gripper.
+#[repr(C)]
+#[derive(Debug, Clone, Default, PartialEq, Serializable)]
+#[serializable(crate = "crate")]
+pub struct Payload7DoFS2M {
+    force: [f32; 3],
+    torque: [f32; 3],
+    gripper: f32,
+}
+
+impl Payload7DoFS2M {
+    pub fn new(force: [f32; 3], torque: [f32; 3], gripper: f32) -> Self {
+        Self {
+            force,
+            torque,
+            gripper,
+        }
+    }
+
+    pub fn force(&self) -> [f32; 3] {
+        self.force
+    }
+
+    pub fn torque(&self) -> [f32; 3] {
+        self.torque
+    }
+
+    pub fn gripper(&self) -> f32 {
+        self.gripper
+    }
+}
+
+/// A sample of `N` values for devices without a standard payload. All values
+/// form a single channel for sampling.
+#[repr(C)]
+#[derive(Debug, Clone, PartialEq, Serializable)]
+#[serializable(crate = "crate")]
+pub struct Payload<const N: usize> {
+    values: [f32; N],
+}
+
+impl<const N: usize> Payload<N> {
+    pub fn new(values: [f32; N]) -> Self {
+        Self { values }
+    }
+
+    pub fn values(&self) -> [f32; N] {
+        self.values
+    }
+}
+
+impl<const N: usize> Default for Payload<N> {
+    fn default() -> Self {
+        Self { values: [0.0; N] }
+    }
+}
+
+impl Sample for PayloadM2S {
+    fn num_channels() -> usize {
+        2
+    }
+
+    fn channel(&self, i: usize) -> &[f32] {
+        match i {
+            0 => &self.pos,
+            1 => &self.vel,
+            _ => panic!("channel {} out of range", i),
+        }
+    }
+
+    fn channel_mut(&mut self, i: usize) -> &mut [f32] {
+        match i {
+            0 => &mut self.pos,
+            1 => &mut self.vel,
+            _ => panic!("channel {} out of range", i),
+        }
+    }
+}
+
+impl Sample for PayloadS2M {
+    fn num_channels() -> usize {
+        1
+    }
+
+    fn channel(&self, i: usize) -> &[f32] {
+        match i {
+            0 => &self.force,
+            _ => panic!("channel {} out of range", i),
+        }
+    }
+
+    fn channel_mut(&mut self, i: usize) -> &mut [f32] {
+        match i {
+            0 => &mut self.force,
+            _ => panic!("channel {} out of range", i),
+        }
+    }
+}
+
+impl Sample for Payload6DoFM2S {
+    fn num_channels() -> usize {
+        4
+    }
+
+    fn channel(&self, i: usize) -> &[f32] {
+        match i {
+            0 => &self.pos,
+            1 => &self.orientation,
+            2 => &self.vel,
+            3 => &self.angular_vel,
+            _ => panic!("channel {} out of range", i),
+        }
+    }
+
+    fn channel_mut(&mut self, i: usize) -> &mut [f32] {
+        match i {
+            0 => &mut self.pos,
+            1 => &mut self.orientation,
+            2 => &mut self.vel,
+            3 => &mut self.angular_vel,
+            _ => panic!("channel {} out of range", i),
+        }
+    }
+}
+
+impl Sample for Payload6DoFS2M {
+    fn num_channels() -> usize {
+        2
+    }
+
+    fn channel(&self, i: usize) -> &[f32] {
+        match i {
+            0 => &self.force,
+            1 => &self.torque,
+            _ => panic!("channel {} out of range", i),
+        }
+    }
+
+    fn channel_mut(&mut self, i: usize) -> &mut [f32] {
+        match i {
+            0 => &mut self.force,
+            1 => &mut self.torque,
+            _ => panic!("channel {} out of range", i),
+        }
+    }
+}
+
+impl Sample for Payload7DoFM2S {
+    fn num_channels() -> usize {
+        5
+    }
+
+    fn channel(&self, i: usize) -> &[f32] {
+        match i {
+            0 => &self.pos,
+            1 => &self.orientation,
+            2 => &self.vel,
+            3 => &self.angular_vel,
+            4 => slice::from_ref(&self.gripper),
+            _ => panic!("channel {} out of range", i),
+        }
+    }
+
+    fn channel_mut(&mut self, i: usize) -> &mut [f32] {
+        match i {
+            0 => &mut self.pos,
+            1 => &mut self.orientation,
+            2 => &mut self.vel,
+            3 => &mut self.angular_vel,
+            4 => slice::from_mut(&mut self.gripper),
+            _ => panic!("channel {} out of range", i),
+        }
+    }
+}
+
+impl Sample for Payload7DoFS2M {
+    fn num_channels() -> usize {
+        3
+    }
+
+    fn channel(&self, i: usize) -> &[f32] {
+        match i {
+            0 => &self.force,
+            1 => &self.torque,
+            2 => slice::from_ref(&self.gripper),
+            _ => panic!("channel {} out of range", i),
+        }
+    }
+
+    fn channel_mut(&mut self, i: usize) -> &mut [f32] {
+        match i {
+            0 => &mut self.force,
+            1 => &mut self.torque,
+            2 => slice::from_mut(&mut self.gripper),
+            _ => panic!("channel {} out of range", i),
+        }
+    }
+}
+
+impl<const N: usize> Sample for Payload<N> {
+    fn num_channels() -> usize {
+        1
+    }
+
+    fn channel(&self, i: usize) -> &[f32] {
+        match i {
+            0 => &self.values,
+            _ => panic!("channel {} out of range", i),
+        }
+    }
+
+    fn channel_mut(&mut self, i: usize) -> &mut [f32] {
+        match i {
+            0 => &mut self.values,
+            _ => panic!("channel {} out of range", i),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn roundtrip<T: Serializable + Sample + PartialEq + std::fmt::Debug>(sample: T, len: usize) {
+        assert_eq!(T::LEN, len);
+        let num_values: usize = (0..T::num_channels())
+            .map(|i| sample.channel(i).len())
+            .sum();
+        assert_eq!(num_values * 4, len);
+        let mut bs = vec![0; T::LEN];
+        sample.encode(&mut bs);
+        assert_eq!(T::decode(&bs), Ok(sample));
+    }
+
+    #[test]
+    fn basic() {
+        let pos = [1.0, 2.0, 3.0];
+        let orientation = [1.0, 0.0, 0.0, 0.0];
+        let vel = [4.0, 5.0, 6.0];
+        let angular_vel = [7.0, 8.0, 9.0];
+        roundtrip(PayloadM2S::new(pos, vel), 24);
+        roundtrip(PayloadS2M::new(pos), 12);
+        roundtrip(
+            Payload6DoFM2S::new(pos, orientation, vel, angular_vel),
+            13 * 4,
+        );
+        roundtrip(Payload6DoFS2M::new(pos, vel), 6 * 4);
+        roundtrip(
+            Payload7DoFM2S::new(pos, orientation, vel, angular_vel, 0.5),
+            14 * 4,
+        );
+        roundtrip(Payload7DoFS2M::new(pos, vel, 0.5), 7 * 4);
+        roundtrip(Payload::new([1.0; 9]), 9 * 4);
+    }
+}
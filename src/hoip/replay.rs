@@ -0,0 +1,124 @@
+use super::{HoipError, PayloadType};
+use byteorder::{BigEndian, ByteOrder};
+
+/// The number of sequence numbers below the highest one that are still
+/// accepted once.
+pub const REPLAY_WINDOW: u64 = 64;
+
+/// The length of a nonce in bytes.
+pub(crate) const NONCE_LEN: usize = 12;
+
+// The nonce classes keep the counters of data and other packets apart.
+pub(crate) const DATA: u8 = 0;
+pub(crate) const OTHER: u8 = 1;
+
+/// Returns the nonce of the packet with `counter` of the `class` sent in
+/// `direction`.
+pub(crate) fn nonce(direction: PayloadType, class: u8, counter: u64) -> [u8; NONCE_LEN] {
+    let mut nonce = [0; NONCE_LEN];
+    nonce[0] = match direction {
+        PayloadType::Master => 0,
+        PayloadType::Slave => 1,
+    };
+    nonce[1] = class;
+    BigEndian::write_u64(&mut nonce[4..12], counter);
+    nonce
+}
+
+/// Remembers the recently opened sequence numbers to reject replayed packets.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct ReplayWindow {
+    highest: Option<u64>,
+    // Bit `i` is set if `highest - i` was opened.
+    opened: u64,
+}
+
+impl ReplayWindow {
+    pub fn check(&self, sequence: u64) -> Result<(), HoipError> {
+        let highest = match self.highest {
+            Some(highest) if highest >= sequence => highest,
+            _ => return Ok(()),
+        };
+        let age = highest - sequence;
+        if age >= REPLAY_WINDOW || self.opened & (1 << age) != 0 {
+            return Err(HoipError::Replayed { sequence });
+        }
+        Ok(())
+    }
+
+    pub fn update(&mut self, sequence: u64) {
+        match self.highest {
+            Some(highest) if highest >= sequence => self.opened |= 1 << (highest - sequence),
+            Some(highest) => {
+                let shift = sequence - highest;
+                self.opened = if shift >= REPLAY_WINDOW {
+                    1
+                } else {
+                    self.opened << shift | 1
+                };
+                self.highest = Some(sequence);
+            }
+            None => {
+                self.opened = 1;
+                self.highest = Some(sequence);
+            }
+        }
+    }
+}
+
+/// Extends a 16 bit sequence number by picking the value closest to
+/// `reference`.
+fn extend(reference: Option<u64>, sequence: u16) -> u64 {
+    let reference = match reference {
+        Some(reference) => reference,
+        None => return u64::from(sequence),
+    };
+    let diff = sequence.wrapping_sub(reference as u16) as i16;
+    std::cmp::max(0, reference as i64 + i64::from(diff)) as u64
+}
+
+/// The counters of the sealed and the opened packets of one direction each.
+///
+/// Data messages are counted by their sequence number, extended by its wrap
+/// arounds, so their counter is not sent. Other packets carry an explicit
+/// counter.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct Counters {
+    // The extended sequence number of the last sealed data message.
+    sealed: Option<u64>,
+    // The number of sealed non-data packets.
+    counter: u64,
+    data_window: ReplayWindow,
+    other_window: ReplayWindow,
+}
+
+impl Counters {
+    /// Returns the class and the counter of the next sealed data message with
+    /// `sequence` or, if `None`, of the next other packet.
+    pub fn next(&mut self, sequence: Option<u16>) -> (u8, u64) {
+        match sequence {
+            Some(sequence) => {
+                let sequence = extend(self.sealed, sequence);
+                self.sealed = Some(sequence);
+                (DATA, sequence)
+            }
+            None => {
+                self.counter += 1;
+                (OTHER, self.counter)
+            }
+        }
+    }
+
+    /// Extends the sequence number of a received data message.
+    pub fn extend_received(&self, sequence: u16) -> u64 {
+        extend(self.data_window.highest, sequence)
+    }
+
+    /// Returns the replay window of the received packets of `class`.
+    pub fn window(&mut self, class: u8) -> &mut ReplayWindow {
+        match class {
+            DATA => &mut self.data_window,
+            _ => &mut self.other_window,
+        }
+    }
+}
@@ -0,0 +1,107 @@
+use super::{HoipError, NON_DATA_CLASS};
+use crate::congestion_detection::CongestionState;
+use byteorder::{BigEndian, ByteOrder, WriteBytesExt};
+
+/// The length of an encoded receiver report in bytes.
+pub const REPORT_LEN: usize = 15;
+
+const REPORT: u8 = 6;
+
+/// Statistics about the data messages a peer received, sent back to their
+/// sender periodically.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ReceiverReport {
+    /// The fraction of messages lost since the previous report in 1/256.
+    pub fraction_lost: u8,
+    /// The number of messages lost since the start.
+    pub cumulative_lost: u32,
+    /// The highest sequence number received, extended by the number of its
+    /// wrap arounds in the upper 16 bits.
+    pub highest_sequence: u32,
+    /// The estimated interarrival jitter in [us].
+    pub jitter: u32,
+    /// The congestion state the receiver detects for its own direction.
+    pub congestion_state: CongestionState,
+}
+
+impl ReceiverReport {
+    /// Returns true if `kind` from the first byte denotes a receiver report.
+    pub(crate) fn is_kind(kind: u8) -> bool {
+        kind == REPORT
+    }
+
+    /// Returns the fraction of messages lost since the previous report.
+    pub fn loss(&self) -> f64 {
+        f64::from(self.fraction_lost) / 256.0
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut wtr = Vec::with_capacity(REPORT_LEN);
+        self.encode(&mut wtr);
+        wtr
+    }
+
+    /// Appends the encoded report to `wtr`.
+    pub fn encode(&self, wtr: &mut Vec<u8>) {
+        wtr.write_u8(NON_DATA_CLASS | REPORT).unwrap();
+        wtr.write_u8(self.fraction_lost).unwrap();
+        wtr.write_u32::<BigEndian>(self.cumulative_lost).unwrap();
+        wtr.write_u32::<BigEndian>(self.highest_sequence).unwrap();
+        wtr.write_u32::<BigEndian>(self.jitter).unwrap();
+        let state = match self.congestion_state {
+            CongestionState::NotSure => 0,
+            CongestionState::Congested => 1,
+            CongestionState::NotCongested => 2,
+        };
+        wtr.write_u8(state).unwrap();
+    }
+
+    pub fn from_bytes(bs: &[u8]) -> Result<Self, HoipError> {
+        if bs.len() != REPORT_LEN {
+            return Err(HoipError::BodyTooShort {
+                len: bs.len(),
+                expected: REPORT_LEN,
+            });
+        }
+        let congestion_state = match bs[14] {
+            0 => CongestionState::NotSure,
+            1 => CongestionState::Congested,
+            2 => CongestionState::NotCongested,
+            byte => return Err(HoipError::ReservedBits { byte }),
+        };
+        Ok(Self {
+            fraction_lost: bs[1],
+            cumulative_lost: BigEndian::read_u32(&bs[2..6]),
+            highest_sequence: BigEndian::read_u32(&bs[6..10]),
+            jitter: BigEndian::read_u32(&bs[10..14]),
+            congestion_state,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn encoding() {
+        let report = ReceiverReport {
+            fraction_lost: 64,
+            cumulative_lost: 100,
+            highest_sequence: 0x0001_0002,
+            jitter: 250,
+            congestion_state: CongestionState::Congested,
+        };
+        let bs = report.to_bytes();
+        assert_eq!(bs.len(), REPORT_LEN);
+        assert_eq!(ReceiverReport::from_bytes(&bs), Ok(report));
+        assert_eq!(report.loss(), 0.25);
+
+        let mut bs = bs;
+        bs[14] = 3;
+        assert_eq!(
+            ReceiverReport::from_bytes(&bs),
+            Err(HoipError::ReservedBits { byte: 3 })
+        );
+    }
+}
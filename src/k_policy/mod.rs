@@ -1,9 +1,11 @@
 use super::congestion_detection::CongestionState;
+use crate::hoip::ReceiverReport;
 
 mod sdmi;
 mod sdsi;
 mod sdsi_exponential_backoff;
 
+/// The default maximum of `k`, which fits into the standard `hoip` header.
 pub const K_MAX: i8 = 4;
 pub const K_MIN: i8 = 1;
 
@@ -12,5 +14,19 @@ pub use sdsi::KPolicySDSI;
 pub use sdsi_exponential_backoff::KPolicySDMIExponentialBackoff;
 
 pub trait KPolicy {
-    fn select_k(&mut self, congestion_state: CongestionState, current_k: i8) -> Option<i8>;
+    /// Selects the next `k` between `K_MIN` and `k_max`.
+    fn select_k(
+        &mut self,
+        congestion_state: CongestionState,
+        current_k: i8,
+        k_max: i8,
+    ) -> Option<i8>;
+
+    /// Informs the policy about the number of additionally sent messages per
+    /// data message, e.g. for forward error correction.
+    fn set_overhead(&mut self, _overhead: f64) {}
+
+    /// Informs the policy about a receiver report of the peer on the sent
+    /// messages.
+    fn on_report(&mut self, _report: &ReceiverReport) {}
 }
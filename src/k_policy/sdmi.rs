@@ -1,13 +1,90 @@
-use super::{CongestionState, KPolicy, K_MAX, K_MIN};
-use std::cmp::max;
+use super::{CongestionState, KPolicy, K_MIN};
+use crate::congestion_detection::LOSS_THRESHOLD;
+use crate::hoip::ReceiverReport;
+use std::cmp::{max, min};
+
+/// Sets `k` to its maximum on congestion and decreases it by one otherwise.
+/// `k` is not decreased below the smallest value at which the data and the
+/// additional messages together are sent at most once per sample. A receiver
+/// report with more than `LOSS_THRESHOLD` loss is handled like congestion.
+#[derive(Debug, Default)]
+pub struct KPolicySDMI {
+    // The number of additionally sent messages per data message.
+    overhead: f64,
+    // Set if a report with high loss arrived since the last selection.
+    lossy: bool,
+}
+
+impl KPolicySDMI {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn k_min(&self, k_max: i8) -> i8 {
+        let k_min = (1.0 + self.overhead).ceil() as i8;
+        min(k_max, max(K_MIN, k_min))
+    }
+}
 
-pub struct KPolicySDMI;
 impl KPolicy for KPolicySDMI {
-    fn select_k(&mut self, congestion_state: CongestionState, current_k: i8) -> Option<i8> {
+    fn select_k(
+        &mut self,
+        congestion_state: CongestionState,
+        current_k: i8,
+        k_max: i8,
+    ) -> Option<i8> {
+        if std::mem::take(&mut self.lossy) {
+            return Some(k_max);
+        }
         match congestion_state {
             CongestionState::NotSure => None,
-            CongestionState::Congested => Some(K_MAX),
-            CongestionState::NotCongested => Some(max(K_MIN, current_k - 1)),
+            CongestionState::Congested => Some(k_max),
+            CongestionState::NotCongested => Some(max(self.k_min(k_max), current_k - 1)),
         }
     }
+
+    fn set_overhead(&mut self, overhead: f64) {
+        self.overhead = overhead;
+    }
+
+    fn on_report(&mut self, report: &ReceiverReport) {
+        self.lossy |= report.loss() > LOSS_THRESHOLD;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn overhead() {
+        let mut policy = KPolicySDMI::new();
+        let not_congested = CongestionState::NotCongested;
+        assert_eq!(policy.select_k(not_congested, 2, 4), Some(1));
+        // A parity message every 4 data messages needs at least 2 samples
+        // per message.
+        policy.set_overhead(0.25);
+        assert_eq!(policy.select_k(not_congested, 2, 4), Some(2));
+        assert_eq!(policy.select_k(not_congested, 4, 4), Some(3));
+        assert_eq!(policy.select_k(not_congested, 1, 1), Some(1));
+        assert_eq!(policy.select_k(CongestionState::Congested, 2, 4), Some(4));
+    }
+
+    #[test]
+    fn report() {
+        let mut policy = KPolicySDMI::new();
+        let mut report = ReceiverReport {
+            fraction_lost: 0,
+            cumulative_lost: 0,
+            highest_sequence: 100,
+            jitter: 0,
+            congestion_state: CongestionState::NotSure,
+        };
+        policy.on_report(&report);
+        assert_eq!(policy.select_k(CongestionState::NotSure, 1, 4), None);
+        report.fraction_lost = 64;
+        policy.on_report(&report);
+        assert_eq!(policy.select_k(CongestionState::NotSure, 1, 4), Some(4));
+        assert_eq!(policy.select_k(CongestionState::NotSure, 4, 4), None);
+    }
 }
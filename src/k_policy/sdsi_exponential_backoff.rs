@@ -30,7 +30,12 @@ impl KPolicySDMIExponentialBackoff {
 }
 
 impl KPolicy for KPolicySDMIExponentialBackoff {
-    fn select_k(&mut self, congestion_state: CongestionState, current_k: i8) -> Option<i8> {
+    fn select_k(
+        &mut self,
+        congestion_state: CongestionState,
+        current_k: i8,
+        k_max: i8,
+    ) -> Option<i8> {
         self.counter += 1;
         //let cool_off = 40;
         //if self.counter< cool_off {
@@ -43,7 +48,7 @@ impl KPolicy for KPolicySDMIExponentialBackoff {
                 if self.congested_in_a_row > 0 {
                     self.congested_in_a_row = 0;
                     self.counter = 0;
-                    Some(K_MAX)
+                    Some(k_max)
                 } else {
                     None
                 }
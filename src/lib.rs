@@ -4,13 +4,24 @@ mod ffi;
 mod network_analyzer;
 mod network_emulator;
 mod network_module;
+mod playout;
 mod rate_limiter;
+mod reception;
+mod sequence;
+mod transient;
 
+pub mod capture;
 pub mod hoip;
 
 pub mod congestion_detection;
 pub mod k_policy;
+pub mod sampling;
+pub mod transport;
 
 pub use common::now;
+pub use config::{ConfigError, IntegrityConfig, NetworkModuleConfig};
 pub use network_emulator::setup_network_emulator;
-pub use network_module::NetworkModule;
+pub use network_module::{ConnectionState, NetworkModule, NetworkModuleError};
+pub use playout::{PlayoutConfig, PlayoutStats};
+pub use sequence::SequenceStats;
+pub use transient::TransientErrorPolicy;
@@ -1,4 +1,5 @@
 use crate::congestion_detection::{CongestionDetector, CongestionState};
+use crate::hoip::ReceiverReport;
 
 pub struct NetworkAnalyzer<CD> {
     // The estimated average delay.
@@ -14,12 +15,14 @@ pub struct NetworkAnalyzer<CD> {
     w: f64,
     // The counter for counting ticks between state changes.
     counter: usize,
+    // The number of updates a new state is kept for at least.
+    cooloff: usize,
     // The current congestion state.
     state: CongestionState,
 }
 
 impl<CD: CongestionDetector> NetworkAnalyzer<CD> {
-    pub fn new(congestion_detector: CD, w: f64) -> Self {
+    pub fn new(congestion_detector: CD, w: f64, cooloff: usize) -> Self {
         Self {
             avg_rott: 0.0,
             std_rott: 0.0,
@@ -27,6 +30,7 @@ impl<CD: CongestionDetector> NetworkAnalyzer<CD> {
             congestion_detector,
             w,
             counter: 0,
+            cooloff,
             state: CongestionState::NotSure,
         }
     }
@@ -40,16 +44,106 @@ impl<CD: CongestionDetector> NetworkAnalyzer<CD> {
     pub fn update_state(&mut self, rott: u32) {
         let (avg_rott, std_rott) = self.calc_avg_and_std_rott(rott);
 
-        self.state =
-            self.congestion_detector
-                .is_congested(rott, avg_rott, std_rott, self.prev_rott);
+        let state = self
+            .congestion_detector
+            .is_congested(rott, avg_rott, std_rott, self.prev_rott);
+        self.transition(state);
 
         self.avg_rott = avg_rott;
         self.std_rott = std_rott;
         self.prev_rott = rott;
     }
 
+    /// Passes a receiver report of the peer to the congestion detector. A
+    /// state it returns is taken even during the cooloff, as the detector
+    /// may have reset its history for it.
+    pub fn on_report(&mut self, report: &ReceiverReport) {
+        if let Some(state) = self.congestion_detector.on_report(report) {
+            if state != self.state {
+                self.state = state;
+                self.counter = self.cooloff;
+            }
+        }
+    }
+
+    // Changes the state unless the previous change is less than `cooloff`
+    // updates ago.
+    fn transition(&mut self, state: CongestionState) {
+        if self.counter > 0 {
+            self.counter -= 1;
+            return;
+        }
+        if state != self.state {
+            self.state = state;
+            self.counter = self.cooloff;
+        }
+    }
+
     pub fn state(&self) -> CongestionState {
         self.state
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::congestion_detection::Window;
+
+    // Detects the state it is set to.
+    struct Fixed(CongestionState);
+
+    impl CongestionDetector for Fixed {
+        fn is_congested(&mut self, _: u32, _: f64, _: f64, _: u32) -> CongestionState {
+            self.0
+        }
+    }
+
+    #[test]
+    fn cooloff() {
+        let mut analyzer = NetworkAnalyzer::new(Fixed(CongestionState::Congested), 0.1, 2);
+        analyzer.update_state(1000);
+        assert_eq!(analyzer.state(), CongestionState::Congested);
+        // The state is kept for the next two updates.
+        analyzer.congestion_detector.0 = CongestionState::NotCongested;
+        analyzer.update_state(1000);
+        analyzer.update_state(1000);
+        assert_eq!(analyzer.state(), CongestionState::Congested);
+        analyzer.update_state(1000);
+        assert_eq!(analyzer.state(), CongestionState::NotCongested);
+        // The cooloff starts again after every change.
+        analyzer.congestion_detector.0 = CongestionState::NotSure;
+        analyzer.update_state(1000);
+        analyzer.update_state(1000);
+        assert_eq!(analyzer.state(), CongestionState::NotCongested);
+        analyzer.update_state(1000);
+        assert_eq!(analyzer.state(), CongestionState::NotSure);
+    }
+
+    #[test]
+    fn lossy_report() {
+        let mut analyzer = NetworkAnalyzer::new(Window::new(5), 0.1, 0);
+        let mut report = ReceiverReport {
+            fraction_lost: 5,
+            cumulative_lost: 5,
+            highest_sequence: 1000,
+            jitter: 100,
+            congestion_state: CongestionState::NotSure,
+        };
+        analyzer.on_report(&report);
+        assert_eq!(analyzer.state(), CongestionState::NotSure);
+        report.fraction_lost = 64;
+        analyzer.on_report(&report);
+        assert_eq!(analyzer.state(), CongestionState::Congested);
+
+        // A lossy report is taken during the cooloff of another state.
+        let mut analyzer = NetworkAnalyzer::new(Window::new(5), 0.1, 10);
+        analyzer.transition(CongestionState::NotCongested);
+        analyzer.transition(CongestionState::NotSure);
+        assert_eq!(analyzer.state(), CongestionState::NotCongested);
+        assert_eq!(analyzer.counter, 9);
+        analyzer.on_report(&report);
+        assert_eq!(analyzer.state(), CongestionState::Congested);
+        // The cooloff starts again.
+        assert_eq!(analyzer.counter, 10);
+    }
+}
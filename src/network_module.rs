@@ -1,151 +1,1091 @@
+use crate::capture::{Capture, Direction, PcapWriter};
 use crate::common::now;
+use crate::config::{ConfigError, NetworkModuleConfig};
 use crate::congestion_detection::CongestionDetector;
-use crate::hoip::{DelayIndicator, Header, Message, PayloadType, SamplingScheme, Serializable};
-use crate::k_policy::{KPolicy, K_MAX};
+use crate::hoip::{
+    Capabilities, Compression, Control, ControlAck, ControlReceiver, ControlSender, DelayIndicator,
+    FecConfig, Handshake, HandshakeError, Header, HoipError, Integrity, Message, MessageRef,
+    Packet, PayloadType, Profile, ReceiverReport, SamplingScheme, Serializable, XorDecoder,
+    XorEncoder, HEADER_LEN, PROTOCOL_VERSION,
+};
+use crate::k_policy::{KPolicy, K_MAX, K_MIN};
 use crate::network_analyzer::NetworkAnalyzer;
+use crate::playout::{PlayoutBuffer, PlayoutConfig, PlayoutStats};
 use crate::rate_limiter::RateLimiter;
-use std::io;
+use crate::reception::ReceptionStats;
+use crate::sampling::{Reconstructor, Sample, Sampler, SamplingConfig};
+use crate::sequence::{Arrival, SequenceStats, SequenceTracker};
+use crate::transient::{TransientErrorPolicy, TransientErrors};
+use crate::transport::Transport;
+use std::cmp::min;
+use std::collections::VecDeque;
+use std::error::Error;
+use std::fmt;
+use std::fs::File;
+use std::io::{self, BufWriter};
 use std::net::UdpSocket;
+use std::path::Path;
+use std::thread;
+use std::time::{Duration, Instant};
 
-pub struct NetworkModule<S, R, CD, KP> {
-    sock: UdpSocket,
-    payloads: Vec<S>,
-    msgs: Vec<R>,
-    msgs_offset: u64,
+/// The interval in [us] in which hellos are sent until the peer answers.
+const HELLO_INTERVAL: u64 = 100_000;
+
+/// The state of the negotiation with the peer.
+#[derive(Debug, Clone, PartialEq)]
+pub enum ConnectionState {
+    /// No compatible answer was received from the peer yet.
+    Negotiating,
+    /// Both peers agreed on their capabilities and haptic data flows.
+    Established,
+    /// The capabilities of the peers are incompatible.
+    Rejected(HandshakeError),
+}
+
+/// An error of a `NetworkModule`.
+#[derive(Debug)]
+pub enum NetworkModuleError {
+    /// The configuration is invalid.
+    Config(ConfigError),
+    /// The capabilities couldn't be negotiated with the peer.
+    Handshake(HandshakeError),
+    /// A socket call failed. Transient errors are only returned according to
+    /// the `TransientErrorPolicy`.
+    Io(io::Error),
+}
+
+impl fmt::Display for NetworkModuleError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            NetworkModuleError::Config(e) => write!(f, "invalid configuration: {}", e),
+            NetworkModuleError::Handshake(e) => write!(f, "handshake failed: {:?}", e),
+            NetworkModuleError::Io(e) => write!(f, "socket error: {}", e),
+        }
+    }
+}
+
+impl Error for NetworkModuleError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            NetworkModuleError::Config(e) => Some(e),
+            NetworkModuleError::Handshake(_) => None,
+            NetworkModuleError::Io(e) => Some(e),
+        }
+    }
+}
+
+impl From<ConfigError> for NetworkModuleError {
+    fn from(e: ConfigError) -> Self {
+        NetworkModuleError::Config(e)
+    }
+}
+
+impl From<HandshakeError> for NetworkModuleError {
+    fn from(e: HandshakeError) -> Self {
+        NetworkModuleError::Handshake(e)
+    }
+}
+
+impl From<io::Error> for NetworkModuleError {
+    fn from(e: io::Error) -> Self {
+        NetworkModuleError::Io(e)
+    }
+}
+
+pub struct NetworkModule<S, R, CD, KP, T = UdpSocket> {
+    transport: T,
+    // The transient errors of the socket calls.
+    errors: TransientErrors,
+    // The samples to send and the time they were taken at.
+    payloads: Vec<(u64, S)>,
+    // The received samples and the time they were taken at, oldest first.
+    msgs: VecDeque<(u64, R)>,
     rott: u32,
-    previous_timestamp: u64,
     network_anaylzer: NetworkAnalyzer<CD>,
     k_policy: KP,
     k: i8,
+    // The maximum `k` negotiated with the peer.
+    k_max: i8,
     op: PayloadType,
     rate_limiter: RateLimiter,
+    invalid_packets: usize,
+    integrity: Integrity,
+    integrity_failures: usize,
+    fec: FecConfig,
+    fec_encoder: Option<XorEncoder>,
+    fec_decoder: XorDecoder,
+    fec_recovered: usize,
+    // The number of parity messages that weren't sent due to the rate limit.
+    skipped_parities: usize,
+    // The number of samples of the previous message repeated in every message.
+    redundancy: u8,
+    // The samples of the previously sent message.
+    last_sent: Vec<(u64, S)>,
+    compression: Option<Compression>,
+    compact_header: bool,
+    // The rott that was last sent with the compact header.
+    sent_rott: Option<u32>,
+    // The rott last received from the peer. Compact headers only carry it
+    // if it changed.
+    peer_rott: u32,
+    // The newest timestamp received from the peer that compact timestamps
+    // are unwrapped against. It starts with the clock of the peer in the
+    // answer that started the session.
+    peer_timestamp: Option<u64>,
+    // The buffer outgoing messages are encoded into. It is reused to not
+    // allocate while sending.
+    send_buf: Vec<u8>,
+    // The buffer the received data messages are copied into back to back
+    // until they are processed by `try_recv`.
+    data_buf: Vec<u8>,
+    // The end of every data message in `data_buf`.
+    data_ends: Vec<usize>,
+    // The number of data messages that were read together with a newer one.
+    superseded_packets: usize,
+    // The number of received samples that were cleared before they were read.
+    cleared_samples: usize,
+    // The buffer datagrams are received into.
+    recv_buf: Vec<u8>,
+    // The data messages recovered from parity since the last `try_recv`.
+    recovered: Vec<Vec<u8>>,
+    control_sender: ControlSender,
+    control_receiver: ControlReceiver,
+    reception: ReceptionStats,
+    // The interval in [us] in which receiver reports are sent, if at all.
+    report_interval: Option<u64>,
+    last_report: Option<u64>,
+    // The latest receiver report of the peer.
+    peer_report: Option<ReceiverReport>,
+    capture: Option<Capture<BufWriter<File>>>,
+    // The sequence number of the last lost message that was concealed by
+    // redundant samples and their number.
+    concealed: Option<(u16, usize)>,
+    concealed_samples: usize,
+    sequence: u16,
+    sequence_tracker: SequenceTracker,
+    sampler: Sampler<S>,
+    ticks: i8,
+    received_scheme: SamplingScheme,
+    reconstructor: Reconstructor<R>,
+    // The buffer that releases the received samples at their playout time,
+    // if any.
+    playout: Option<PlayoutBuffer<R>>,
+    // The period between two samples in [us].
+    sample_period: u64,
+    delay_indicator: DelayIndicator,
+    capabilities: Capabilities,
+    state: ConnectionState,
+    last_hello: Option<u64>,
+    // The own session and the one of the peer, once it is started.
+    session: Option<(u64, u64)>,
 }
 
-impl<S: Serializable, R: Serializable, CD: CongestionDetector, KP: KPolicy>
+impl<S: Serializable + Sample, R: Serializable + Sample, CD: CongestionDetector, KP: KPolicy>
     NetworkModule<S, R, CD, KP>
 {
-    pub fn new(
-        dest_addr: &str,
-        src_addr: &str,
+    /// Creates a network module with the validated `config` and binds its
+    /// socket.
+    pub fn from_config(
+        config: NetworkModuleConfig,
+        congestion_detector: CD,
+        k_policy: KP,
+    ) -> Result<Self, NetworkModuleError> {
+        config.validate()?;
+        let sock = UdpSocket::bind(&config.src_addr)?;
+        sock.connect(&config.dest_addr)?;
+        sock.set_nonblocking(true)?;
+        Self::with_transport(config, sock, congestion_detector, k_policy)
+    }
+}
+
+impl<S, R, CD, KP, T> NetworkModule<S, R, CD, KP, T>
+where
+    S: Serializable + Sample,
+    R: Serializable + Sample,
+    CD: CongestionDetector,
+    KP: KPolicy,
+    T: Transport,
+{
+    /// Creates a network module with the validated `config` that sends over
+    /// `transport`. The addresses of the config are not used.
+    pub fn with_transport(
+        config: NetworkModuleConfig,
+        transport: T,
         congestion_detector: CD,
         k_policy: KP,
-        w: f64,
-        cooloff: usize,
-        op: PayloadType,
-        rate: f64,
-    ) -> Self {
-        let sock = UdpSocket::bind(src_addr).unwrap();
-        sock.connect(dest_addr).unwrap();
-        sock.set_nonblocking(true).unwrap();
-
-        let rate_limiter = RateLimiter::new(rate);
-
-        Self {
-            sock,
-            payloads: Vec::with_capacity(K_MAX as _),
+    ) -> Result<Self, NetworkModuleError> {
+        config.validate()?;
+        config.validate_message_len::<S>()?;
+        config.validate_message_len::<R>()?;
+
+        let rate_limiter = RateLimiter::new(config.rate);
+        let op = config.op;
+        let w = config.w;
+        let cooloff = config.cooloff;
+
+        let mut network_module = Self {
+            transport,
+            errors: TransientErrors::new(config.transient_errors),
+            payloads: Vec::with_capacity(config.k_max as _),
+            k_max: K_MAX,
             rott: 0,
-            msgs: Vec::new(),
-            previous_timestamp: 0,
-            msgs_offset: 0,
-            network_anaylzer: NetworkAnalyzer::new(congestion_detector, w),
+            msgs: VecDeque::new(),
+            network_anaylzer: NetworkAnalyzer::new(congestion_detector, w, cooloff),
             k_policy,
             k: K_MAX,
             op,
             rate_limiter,
+            invalid_packets: 0,
+            integrity: Integrity::None,
+            integrity_failures: 0,
+            fec: FecConfig::None,
+            fec_encoder: None,
+            fec_decoder: XorDecoder::default(),
+            fec_recovered: 0,
+            skipped_parities: 0,
+            redundancy: 0,
+            last_sent: Vec::with_capacity(config.k_max as _),
+            send_buf: Vec::new(),
+            data_buf: Vec::new(),
+            data_ends: Vec::new(),
+            superseded_packets: 0,
+            cleared_samples: 0,
+            recv_buf: vec![0; config.recv_buffer_size],
+            recovered: Vec::new(),
+            control_sender: ControlSender::default(),
+            control_receiver: ControlReceiver::default(),
+            reception: ReceptionStats::default(),
+            report_interval: None,
+            last_report: None,
+            peer_report: None,
+            capture: None,
+            compression: None,
+            compact_header: false,
+            sent_rott: None,
+            peer_rott: 0,
+            peer_timestamp: None,
+            concealed: None,
+            concealed_samples: 0,
+            sequence: 0,
+            sequence_tracker: SequenceTracker::new(),
+            sampler: Sampler::new(SamplingConfig::Lossless),
+            ticks: 0,
+            received_scheme: SamplingScheme::Lossless,
+            reconstructor: Reconstructor::new(config.sample_period_micros),
+            playout: None,
+            sample_period: config.sample_period_micros,
+            delay_indicator: DelayIndicator::InHeader,
+            capabilities: Capabilities {
+                version: PROTOCOL_VERSION,
+                header_len: HEADER_LEN as u8,
+                k_max: K_MAX as u8,
+                sampling_scheme: SamplingScheme::Lossless,
+                send_len: S::LEN as u16,
+                recv_len: R::LEN as u16,
+                integrity: Integrity::None.id(),
+                sample_period: config.sample_period_micros,
+                session: random_session()?,
+                echo: 0,
+                time: 0,
+            },
+            session: None,
+            state: ConnectionState::Negotiating,
+            last_hello: None,
+        };
+        network_module.set_sampling(config.sampling);
+        network_module.set_delay_indicator(config.delay_indicator);
+        network_module.set_compression(config.compression);
+        network_module.set_compact_header(config.compact_header);
+        network_module.set_redundancy(config.redundancy);
+        network_module.set_fec(config.fec);
+        network_module.set_integrity(config.integrity.to_integrity());
+        network_module.set_k_max(config.k_max);
+        network_module.set_playout(config.playout);
+        network_module
+            .set_report_interval(config.report_interval_micros.map(Duration::from_micros));
+        Ok(network_module)
+    }
+
+    /// Blocks until the capabilities are negotiated with the peer.
+    pub fn handshake(&mut self, timeout: Duration) -> Result<(), NetworkModuleError> {
+        let start = Instant::now();
+        loop {
+            self.send_hello()?;
+            self.try_recv()?;
+            match &self.state {
+                ConnectionState::Established => return Ok(()),
+                ConnectionState::Rejected(e) => return Err(e.clone().into()),
+                ConnectionState::Negotiating => {}
+            }
+            if start.elapsed() > timeout {
+                return Err(HandshakeError::Timeout.into());
+            }
+            thread::sleep(Duration::from_millis(1));
+        }
+    }
+
+    fn send_hello(&mut self) -> io::Result<()> {
+        if self.state != ConnectionState::Negotiating {
+            return Ok(());
+        }
+        let now = now();
+        if let Some(last_hello) = self.last_hello {
+            if now < last_hello + HELLO_INTERVAL {
+                return Ok(());
+            }
         }
+        self.last_hello = Some(now);
+        let hello = Capabilities {
+            time: now,
+            ..self.capabilities.clone()
+        };
+        // The peer might not be up yet.
+        self.send_handshake(Handshake::Hello(hello))
     }
 
-    pub fn send(&mut self, payload: S) {
+    /// Sends `handshake`, sealed if the integrity protects handshakes.
+    fn send_handshake(&mut self, handshake: Handshake) -> io::Result<()> {
+        let mut bs = handshake.to_bytes();
+        if self.integrity.protects_handshakes() {
+            self.integrity.seal(&mut bs);
+        }
+        record(&mut self.capture, Direction::Sent, &bs);
+        self.errors.check(self.transport.send(&bs))?;
+        Ok(())
+    }
+
+    fn establish(&mut self, remote: &Capabilities) {
+        self.k_max = min(self.capabilities.k_max, remote.k_max) as i8;
+        self.k = min(self.k, self.k_max);
+        self.update_playout_floor();
+        self.state = ConnectionState::Established;
+    }
+
+    /// Starts the session with the peer that answered with `remote`, unless
+    /// it is the current one. Only answers that echo the own session are
+    /// fresh and every own session is only used once, so replayed handshakes
+    /// can't start a session. A restarted peer announces a new session, so
+    /// the state kept about the previous one is reset. The clock of the
+    /// answer is the first reference of compact timestamps, so they are
+    /// unwrapped correctly even if the peer runs for longer than they wrap.
+    /// Returns true if the session was started.
+    fn start_session(&mut self, remote: &Capabilities) -> bool {
+        if self.is_peer(remote) {
+            self.establish(remote);
+            return false;
+        }
+        let own = self.capabilities.session;
+        let used = matches!(self.session, Some((session, _)) if session == own);
+        if remote.echo != own || used {
+            return false;
+        }
+        if self.session.is_some() {
+            self.sequence_tracker = SequenceTracker::new();
+            self.concealed = None;
+            self.sent_rott = None;
+            self.fec_decoder = XorDecoder::default();
+            self.control_sender = ControlSender::default();
+            self.control_receiver = ControlReceiver::default();
+        }
+        self.session = Some((own, remote.session));
+        self.peer_timestamp = Some(remote.time);
+        self.rekey();
+        self.establish(remote);
+        true
+    }
+
+    // Returns true if `remote` is the peer of the started session.
+    fn is_peer(&self, remote: &Capabilities) -> bool {
+        matches!(self.session, Some((_, peer)) if peer == remote.session)
+    }
+
+    /// Returns the capabilities to answer `remote` with. The peer of the
+    /// started session is answered with its session. Any other peer gets a
+    /// new session, as the current one was already used.
+    fn answer(&mut self, remote: &Capabilities) -> io::Result<Capabilities> {
+        match self.session {
+            Some((own, _)) if self.is_peer(remote) => {
+                return Ok(Capabilities {
+                    session: own,
+                    echo: remote.session,
+                    time: now(),
+                    ..self.capabilities.clone()
+                });
+            }
+            Some((own, _)) if own == self.capabilities.session => {
+                self.capabilities.session = random_session()?;
+            }
+            _ => {}
+        }
+        Ok(Capabilities {
+            echo: remote.session,
+            time: now(),
+            ..self.capabilities.clone()
+        })
+    }
+
+    /// Derives the keys of the integrity from the sessions of both peers.
+    fn rekey(&mut self) {
+        let (own, peer) = match self.session {
+            Some(session) => session,
+            None => return,
+        };
+        let (master, slave) = match self.op {
+            PayloadType::Master => (own, peer),
+            PayloadType::Slave => (peer, own),
+        };
+        self.integrity.rekey(master, slave);
+    }
+
+    /// Handles a handshake. Hellos are always answered, but only an answer
+    /// that echoes the own session starts one. Once the session is
+    /// established, incompatible handshakes are answered but don't end it,
+    /// as they may be forged by anyone if the peers don't protect their
+    /// handshakes.
+    fn on_handshake(&mut self, handshake: Handshake) -> io::Result<()> {
+        let established = self.state == ConnectionState::Established;
+        let fresh = handshake.capabilities().echo == self.capabilities.session;
+        match handshake {
+            Handshake::Hello(remote) => {
+                let reply = match self.capabilities.check(&remote) {
+                    Ok(()) => {
+                        if self.is_peer(&remote) {
+                            self.establish(&remote);
+                        }
+                        Handshake::Accept(self.answer(&remote)?)
+                    }
+                    Err(e) => {
+                        if !established {
+                            self.state = ConnectionState::Rejected(e);
+                        }
+                        Handshake::Reject(self.answer(&remote)?)
+                    }
+                };
+                self.send_handshake(reply)?;
+            }
+            Handshake::Accept(remote) => match self.capabilities.check(&remote) {
+                Ok(()) => {
+                    if self.start_session(&remote) {
+                        // Lets the peer start the session as well.
+                        let reply = Handshake::Accept(self.answer(&remote)?);
+                        self.send_handshake(reply)?;
+                    }
+                }
+                Err(e) => {
+                    if !established && fresh {
+                        self.state = ConnectionState::Rejected(e);
+                    }
+                }
+            },
+            Handshake::Reject(remote) => {
+                if !established && fresh {
+                    let e = self
+                        .capabilities
+                        .check(&remote)
+                        .err()
+                        .unwrap_or(HandshakeError::Rejected);
+                    self.state = ConnectionState::Rejected(e);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Sends the samples of the last `k` calls once `k` samples are
+    /// collected. Transient socket errors are handled according to the
+    /// `TransientErrorPolicy`.
+    pub fn send(&mut self, payload: S) -> Result<(), NetworkModuleError> {
+        if self.state != ConnectionState::Established {
+            self.send_hello()?;
+            return Ok(());
+        }
+        self.poll_control()?;
+        self.poll_report()?;
+
         let state = self.network_anaylzer.state();
-        if let Some(new_k) = self.k_policy.select_k(state, self.k) {
+        if let Some(new_k) = self.k_policy.select_k(state, self.k, self.k_max) {
             self.k = new_k;
         }
 
-        self.payloads.push(payload);
-        if self.payloads.len() < self.k as _ {
-            return;
+        self.ticks = self.ticks.saturating_add(1);
+        if let Some(payload) = self.sampler.sample(payload) {
+            self.payloads.push((now(), payload));
+        }
+        if self.ticks < self.k {
+            return Ok(());
         }
 
-        let too_many = self.payloads.len() - self.k as usize;
-        if too_many > 0 {
+        if self.payloads.len() > self.k as usize {
+            let too_many = self.payloads.len() - self.k as usize;
             self.payloads.drain(0..too_many);
         }
 
+        // All samples of the last `k` ticks were filtered by the sampler.
+        if self.payloads.is_empty() {
+            self.ticks = 0;
+            return Ok(());
+        }
+
         if self.rate_limiter.limited() {
-            return;
+            return Ok(());
         }
+        self.ticks = 0;
 
-        let payloads = std::mem::replace(&mut self.payloads, Vec::with_capacity(K_MAX as _));
         let rott = self.rott;
-        let num_samples = payloads.len() as u8;
-        let payload = payloads
-            .into_iter()
-            .map(|m| m.to_bytes())
-            .collect::<Vec<_>>()
-            .concat();
-        let msg = Message {
-            header: Header {
-                payload_type: self.op,
-                sampling_scheme: SamplingScheme::Lossless,
-                num_samples,
-                delay_indicator: DelayIndicator::InHeader,
-                threshold: 10,
-                rott,
-                timestamp: now(),
+        let profile = match self.compact_header {
+            false => Profile::Standard,
+            true => Profile::Compact {
+                rott: self.sent_rott != Some(rott),
             },
-            payload,
+        };
+        if let Profile::Compact { rott: true } = profile {
+            self.sent_rott = Some(rott);
+        }
+        let num_samples = self.payloads.len() as u8;
+        let header = Header {
+            payload_type: self.op,
+            sampling_scheme: self.sampler.config().scheme(),
+            num_samples,
+            delay_indicator: self.delay_indicator,
+            threshold: self.sampler.config().threshold(),
+            rott,
+            timestamp: now(),
+            sequence: self.sequence,
+            redundancy: 0,
+            compression: self.compression,
+            profile,
+        };
+        let start = self
+            .last_sent
+            .len()
+            .saturating_sub(self.redundancy as usize);
+        let msg = &mut self.send_buf;
+        msg.clear();
+        Message::encode_samples(header, &self.payloads, &self.last_sent[start..], msg);
+        // Remember the samples for the redundancy of the next message.
+        self.last_sent.clear();
+        if self.redundancy > 0 {
+            self.last_sent.extend_from_slice(&self.payloads);
+        }
+        self.payloads.clear();
+
+        let parity = match &mut self.fec_encoder {
+            Some(encoder) => encoder.add(self.sequence, msg),
+            None => None,
+        };
+        self.integrity.seal(msg);
+        self.sequence = self.sequence.wrapping_add(1);
+        record(&mut self.capture, Direction::Sent, msg);
+        self.errors.check(self.transport.send(msg))?;
+
+        if let Some(parity) = parity {
+            // Parity messages count against the rate limit.
+            if self.rate_limiter.limited() {
+                self.skipped_parities += 1;
+                return Ok(());
+            }
+            msg.clear();
+            parity.encode(msg);
+            self.integrity.seal(msg);
+            record(&mut self.capture, Direction::Sent, msg);
+            self.errors.check(self.transport.send(msg))?;
         }
-        .to_bytes();
-        self.sock.send(&msg).unwrap();
+        Ok(())
     }
 
     fn try_pop_msg(&mut self) -> Option<(u64, R)> {
-        self.msgs.pop().map(|msgs| {
-            self.msgs_offset -= 1;
-            let ts = self.previous_timestamp + self.msgs_offset * 1000;
-            (ts, msgs)
+        let playout = match &mut self.playout {
+            Some(playout) => playout,
+            None => return self.msgs.pop_front(),
+        };
+        let now = now();
+        let jitter = self.reception.jitter();
+        for (ts, sample) in self.msgs.drain(..) {
+            playout.push(now, ts, sample, jitter);
+        }
+        playout.pop(now)
+    }
+
+    fn next_sample(&mut self) -> Option<(u64, R)> {
+        if let SamplingScheme::Lossless = self.received_scheme {
+            return self.try_pop_msg();
+        }
+        let now = now();
+        match self.try_pop_msg() {
+            Some((ts, sample)) => {
+                self.reconstructor.update(now, ts, sample.clone());
+                Some((ts, sample))
+            }
+            None => self.reconstructor.hold(now),
+        }
+    }
+
+    /// Verifies the integrity of a received datagram and returns its content,
+    /// counting those that fail. Encrypted datagrams are decrypted in place.
+    ///
+    /// Handshakes are only verified if the integrity protects them, so a
+    /// peer with a different integrity is only rejected if it uses the same
+    /// key. Otherwise its handshakes are dropped, as they may be forged.
+    fn open<'a>(&mut self, bs: &'a mut [u8]) -> Option<&'a [u8]> {
+        if Packet::is_handshake(bs) && !self.integrity.protects_handshakes() {
+            return Some(bs);
+        }
+        match self.integrity.open(bs) {
+            Ok(bs) => Some(bs),
+            Err(_) => {
+                self.integrity_failures += 1;
+                None
+            }
+        }
+    }
+
+    /// Borrows a received data message and unwraps its timestamp if it uses
+    /// the compact header.
+    fn message<'a>(&self, bs: &'a [u8]) -> Result<MessageRef<'a>, HoipError> {
+        let msg = MessageRef::new(bs)?.with_sample_period(self.sample_period);
+        Ok(match self.peer_timestamp {
+            Some(reference) => msg.with_reference(reference),
+            None => msg,
         })
     }
 
-    pub fn try_recv(&mut self) -> Option<(u64, R)> {
-        let mut buf = [0; 300];
-        let mut num_bytes = 0;
-        loop {
-            match self.sock.recv(&mut buf) {
+    /// Queues `samples` in front of the queued samples from index `at` on,
+    /// so they are popped before them, and returns their number. Nothing is
+    /// queued if one of them is invalid.
+    fn queue(
+        &mut self,
+        at: usize,
+        samples: impl Iterator<Item = Result<(u64, R), HoipError>>,
+    ) -> Result<usize, HoipError> {
+        let len = self.msgs.len();
+        for sample in samples {
+            match sample {
+                Ok(sample) => self.msgs.push_back(sample),
                 Err(e) => {
-                    if let io::ErrorKind::WouldBlock = e.kind() {
-                        break;
-                    }
-                    panic!("{:}", e);
+                    self.msgs.truncate(len);
+                    return Err(e);
                 }
-                v => num_bytes = v.unwrap(),
+            }
+        }
+        let n = self.msgs.len() - len;
+        self.msgs.make_contiguous()[at..].rotate_right(n);
+        Ok(n)
+    }
+
+    /// Queues the samples of a data message recovered by forward error
+    /// correction from index `at` on and returns their number. They are
+    /// older than the samples that are already queued.
+    fn on_recovered(&mut self, bs: &[u8], at: usize) -> usize {
+        let msg = match self.message(bs) {
+            Ok(msg) => msg,
+            Err(_) => return 0,
+        };
+        let samples = match msg.samples::<R>() {
+            Ok(samples) => samples,
+            Err(_) => return 0,
+        };
+        // Skip the samples that were already concealed by redundant ones.
+        let mut n = samples.len();
+        if let Some((sequence, concealed)) = self.concealed {
+            if sequence == msg.sequence() {
+                n = n.saturating_sub(concealed);
+            }
+        }
+        if !self.sequence_tracker.on_recover(msg.sequence()) {
+            return 0;
+        }
+        match self.queue(at, samples.take(n)) {
+            Ok(n) => {
+                self.fec_recovered += 1;
+                n
+            }
+            Err(_) => 0,
+        }
+    }
+
+    /// Queues the redundant samples of `msg` in front of its samples, which
+    /// are queued from index `at` on, if the previous message was lost.
+    fn conceal(&mut self, msg: &MessageRef, at: usize) {
+        let previous = msg.sequence().wrapping_sub(1);
+        if msg.redundancy() == 0 || self.sequence_tracker.is_received(previous) {
+            return;
+        }
+        let redundant = match msg.redundant_samples::<R>() {
+            Ok(redundant) => redundant,
+            Err(_) => return,
+        };
+        if let Ok(n) = self.queue(at, redundant) {
+            self.concealed = Some((previous, n));
+            self.concealed_samples += n;
+        }
+    }
+
+    /// Returns the next received sample and the time it was taken at. The
+    /// samples of a message are returned oldest first. Transient socket
+    /// errors are handled according to the `TransientErrorPolicy`.
+    pub fn try_recv(&mut self) -> Result<Option<(u64, R)>, NetworkModuleError> {
+        self.read_datagrams()?;
+        self.poll_control()?;
+        self.poll_report()?;
+        let mut data = std::mem::take(&mut self.data_buf);
+        let mut ends = std::mem::take(&mut self.data_ends);
+        let recovered = std::mem::take(&mut self.recovered);
+        let sample = self.receive(&data, &ends, recovered);
+        data.clear();
+        ends.clear();
+        self.data_buf = data;
+        self.data_ends = ends;
+        Ok(sample)
+    }
+
+    /// Queues a control message that is delivered reliably and in order.
+    /// Returns false if too many control messages are unacknowledged.
+    /// Control messages are sent once the connection is established and are
+    /// retransmitted while `send`, `try_recv` or `try_recv_control` are called.
+    pub fn send_control(&mut self, control: Control) -> Result<bool, NetworkModuleError> {
+        if !self.control_sender.push(control) {
+            return Ok(false);
+        }
+        self.poll_control()?;
+        Ok(true)
+    }
+
+    /// Returns the next received control message. The socket is read like
+    /// by `try_recv`, but the received samples are only queued by `try_recv`.
+    pub fn try_recv_control(&mut self) -> Result<Option<Control>, NetworkModuleError> {
+        self.read_datagrams()?;
+        self.poll_control()?;
+        Ok(self.control_receiver.pop())
+    }
+
+    /// Reads all queued datagrams. The data messages are kept for `receive`
+    /// in arrival order. They are copied into a buffer that is kept between
+    /// calls to not allocate per datagram.
+    fn read_datagrams(&mut self) -> io::Result<()> {
+        // The buffer is taken to open datagrams in it while `self` is borrowed.
+        let mut buf = std::mem::take(&mut self.recv_buf);
+        let result = self.read_datagrams_into(&mut buf);
+        self.recv_buf = buf;
+        result
+    }
+
+    fn read_datagrams_into(&mut self, buf: &mut [u8]) -> io::Result<()> {
+        loop {
+            let (num_bytes, arrival) = match self.transport.recv(buf) {
+                Ok(received) => received,
+                Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => return Ok(()),
+                // E.g. a previous message didn't reach the peer.
+                Err(e) => match self.errors.check(Err(e))? {
+                    Some(received) => received,
+                    None => continue,
+                },
+            };
+            record(&mut self.capture, Direction::Received, &buf[0..num_bytes]);
+            let bs = match self.open(&mut buf[0..num_bytes]) {
+                Some(bs) => bs,
+                None => continue,
             };
+            if Packet::is_data(bs) {
+                match self.message(bs) {
+                    Ok(msg) => {
+                        let timestamp = msg.timestamp();
+                        self.reception
+                            .on_arrival(timestamp, arrival.unwrap_or_else(now));
+                        self.peer_timestamp = Some(match self.peer_timestamp {
+                            Some(newest) => std::cmp::max(newest, timestamp),
+                            None => timestamp,
+                        });
+                        self.fec_decoder.add(msg.sequence(), bs);
+                        self.data_buf.extend_from_slice(bs);
+                        self.data_ends.push(self.data_buf.len());
+                    }
+                    Err(_) => self.invalid_packets += 1,
+                }
+                continue;
+            }
+            match Packet::from_bytes(bs) {
+                Ok(Packet::Handshake(handshake)) => self.on_handshake(handshake)?,
+                Ok(Packet::Parity(parity)) => {
+                    if let Some(bs) = self.fec_decoder.recover(&parity) {
+                        self.recovered.push(bs);
+                    }
+                }
+                Ok(Packet::Control(msg)) => {
+                    let ack = self.control_receiver.on_receive(msg);
+                    self.send_control_ack(ack)?;
+                }
+                Ok(Packet::ControlAck(ack)) => self.control_sender.on_ack(ack, now()),
+                Ok(Packet::Report(report)) => {
+                    self.network_anaylzer.on_report(&report);
+                    self.k_policy.on_report(&report);
+                    self.peer_report = Some(report);
+                }
+                Ok(Packet::Data(_)) => {}
+                Err(_) => self.invalid_packets += 1,
+            }
         }
-        if num_bytes == 0 {
-            return self.try_pop_msg();
+    }
+
+    /// Sends the control messages that are due for (re)transmission.
+    fn poll_control(&mut self) -> io::Result<()> {
+        if self.state != ConnectionState::Established {
+            return Ok(());
         }
-        let msg = Message::from_bytes(&buf[0..num_bytes]);
-        self.rott = (now() - msg.timestamp()) as _;
-        if self.previous_timestamp < msg.timestamp() {
-            self.msgs = msg
-                .payload
-                .chunks(R::len())
-                .map(|bs| R::from_bytes(bs))
-                .collect();
-            self.msgs_offset = self.msgs.len() as u64;
+        let wtr = &mut self.send_buf;
+        for msg in self.control_sender.due(now()) {
+            wtr.clear();
+            msg.encode(wtr);
+            self.integrity.seal(wtr);
+            record(&mut self.capture, Direction::Sent, wtr);
+            self.errors.check(self.transport.send(wtr))?;
+        }
+        Ok(())
+    }
+
+    fn send_control_ack(&mut self, ack: ControlAck) -> io::Result<()> {
+        self.send_buf.clear();
+        ack.encode(&mut self.send_buf);
+        self.send_sealed()
+    }
+
+    /// Sends a receiver report if the report interval elapsed.
+    fn poll_report(&mut self) -> io::Result<()> {
+        let interval = match self.report_interval {
+            Some(interval) => interval,
+            None => return Ok(()),
+        };
+        if self.state != ConnectionState::Established {
+            return Ok(());
+        }
+        let now = now();
+        if let Some(last_report) = self.last_report {
+            if now < last_report + interval {
+                return Ok(());
+            }
+        }
+        let state = self.network_anaylzer.state();
+        let report = match self.reception.report(&self.sequence_tracker, state) {
+            Some(report) => report,
+            None => return Ok(()),
+        };
+        self.last_report = Some(now);
+        self.send_buf.clear();
+        report.encode(&mut self.send_buf);
+        self.send_sealed()
+    }
+
+    /// Seals the message in the send buffer and sends it.
+    fn send_sealed(&mut self) -> io::Result<()> {
+        self.integrity.seal(&mut self.send_buf);
+        record(&mut self.capture, Direction::Sent, &self.send_buf);
+        self.errors.check(self.transport.send(&self.send_buf))?;
+        Ok(())
+    }
+
+    /// Queues the samples of the data messages in `data`, which end at
+    /// `ends`, in arrival order and of the messages recovered from parity
+    /// and returns the next sample.
+    fn receive(
+        &mut self,
+        data: &[u8],
+        ends: &[usize],
+        recovered: Vec<Vec<u8>>,
+    ) -> Option<(u64, R)> {
+        if self.state != ConnectionState::Established {
+            return self.next_sample();
+        }
+        let mut start = 0;
+        let mut in_order: usize = 0;
+        for &end in ends {
+            // The samples that are left from previous calls are outdated.
+            if self.on_data(&data[start..end], in_order == 0) {
+                in_order += 1;
+            }
+            start = end;
+        }
+        self.superseded_packets += in_order.saturating_sub(1);
+        let mut at = 0;
+        for bs in recovered.into_iter() {
+            at += self.on_recovered(&bs, at);
+        }
+        self.next_sample()
+    }
+
+    /// Queues the samples of the data message `bs` behind the queued
+    /// samples, after clearing them if `clear` is set. Returns true if the
+    /// message was in order.
+    fn on_data(&mut self, bs: &[u8], clear: bool) -> bool {
+        let msg = match self.message(bs) {
+            Ok(msg) => msg,
+            Err(_) => return false,
+        };
+        let samples = match msg.samples::<R>() {
+            Ok(samples) => samples,
+            Err(_) => {
+                self.invalid_packets += 1;
+                return false;
+            }
+        };
+        // The clock of the peer may be ahead, e.g. if it started earlier.
+        self.rott = now().saturating_sub(msg.timestamp()) as _;
+        if let Arrival::InOrder = self.sequence_tracker.on_receive(msg.sequence()) {
+            if clear {
+                self.cleared_samples += self.msgs.len();
+                self.msgs.clear();
+            }
+            let at = self.msgs.len();
+            let n = match self.queue(at, samples) {
+                Ok(n) => n,
+                Err(_) => {
+                    self.invalid_packets += 1;
+                    return true;
+                }
+            };
 
+            if msg.profile() != (Profile::Compact { rott: false }) {
+                self.peer_rott = msg.rott();
+            }
+            // The oldest sample of the message waited longer than the newest
+            // one by the span of their timestamps.
+            let timestamps = self.msgs.range(at..at + n).map(|(t, _)| *t);
+            let span = timestamps.clone().max().unwrap_or(0) - timestamps.min().unwrap_or(0);
+            let span = min(span, u64::from(u32::MAX)) as u32;
             self.network_anaylzer
-                .update_state(msg.rott() + 1000 * (self.msgs_offset - 1) as u32);
+                .update_state(self.peer_rott.saturating_add(span));
+            self.conceal(&msg, at);
 
-            self.previous_timestamp = msg.timestamp();
+            self.received_scheme = msg.sampling_scheme();
+            return true;
         }
-        self.try_pop_msg()
+        false
+    }
+
+    /// Sets the sampling scheme used for the samples that are sent.
+    /// The sampling scheme is part of the negotiated capabilities and should
+    /// be set before the handshake.
+    pub fn set_sampling(&mut self, config: SamplingConfig) {
+        self.capabilities.sampling_scheme = config.scheme();
+        self.sampler = Sampler::new(config);
+    }
+
+    /// Sets where the delays of the samples that are sent are stored.
+    /// `DelayIndicator::InPayload` lets the receiver rebuild the sampling
+    /// instant of every sample when they are not taken every millisecond.
+    pub fn set_delay_indicator(&mut self, delay_indicator: DelayIndicator) {
+        self.delay_indicator = delay_indicator;
+    }
+
+    /// Sets the lossy compression of the samples that are sent. It is
+    /// signalled in the header and can be changed at any time.
+    pub fn set_compression(&mut self, compression: Option<Compression>) {
+        self.compression = compression;
+    }
+
+    /// Sets whether the data messages that are sent use the compact header,
+    /// which saves up to seven bytes per message by cutting the timestamp to 32
+    /// bits and only sending the rott when it changed. Both headers are
+    /// always understood, so it can be changed at any time.
+    pub fn set_compact_header(&mut self, compact_header: bool) {
+        self.compact_header = compact_header;
+        self.sent_rott = None;
+    }
+
+    /// Sets the protection of the data messages against corruption or
+    /// forgery. It is part of the negotiated capabilities and should be set
+    /// before the handshake. The keyed protections also authenticate the
+    /// handshake messages and use a key per session.
+    pub fn set_integrity(&mut self, mut integrity: Integrity) {
+        integrity.set_local(self.op);
+        self.capabilities.integrity = integrity.id();
+        self.integrity = integrity;
+        self.rekey();
+    }
+
+    /// Sets the forward error correction used for the data messages that
+    /// are sent. Its overhead is reported to the k-policy.
+    pub fn set_fec(&mut self, fec: FecConfig) {
+        self.fec = fec;
+        self.fec_encoder = match fec {
+            FecConfig::None => None,
+            FecConfig::Xor { n } => Some(XorEncoder::new(n)),
+        };
+        self.k_policy.set_overhead(fec.overhead());
+    }
+
+    /// Returns the number of additionally sent messages per data message
+    /// caused by forward error correction.
+    pub fn fec_overhead(&self) -> f64 {
+        self.fec.overhead()
+    }
+
+    /// Returns the number of lost data messages that were recovered by
+    /// forward error correction.
+    pub fn fec_recovered(&self) -> usize {
+        self.fec_recovered
+    }
+
+    /// Returns the number of parity messages that were not sent as the rate
+    /// limit was reached. The messages they cover are not protected.
+    pub fn skipped_parities(&self) -> usize {
+        self.skipped_parities
+    }
+
+    /// Sets the number of samples of the previous message that are repeated
+    /// in every message. They replace the samples of a single lost message at
+    /// the receiver. It can be changed at any time.
+    pub fn set_redundancy(&mut self, redundancy: u8) {
+        self.redundancy = redundancy;
+    }
+
+    pub fn redundancy(&self) -> u8 {
+        self.redundancy
+    }
+
+    /// Returns the number of samples of lost messages that were replaced by
+    /// redundant samples.
+    pub fn concealed_samples(&self) -> usize {
+        self.concealed_samples
+    }
+
+    /// Sets the maximum number of samples per message. Values above four
+    /// need the extended header and are only used if the peer supports them.
+    /// It is part of the negotiated capabilities and should be set before the
+    /// handshake.
+    pub fn set_k_max(&mut self, k_max: i8) {
+        assert!(k_max >= K_MIN, "k max must be at least {}", K_MIN);
+        self.capabilities.k_max = k_max as u8;
+        self.k_max = k_max;
+        self.k = k_max;
+        self.update_playout_floor();
+    }
+
+    /// Returns the state of the negotiation with the peer.
+    pub fn connection_state(&self) -> &ConnectionState {
+        &self.state
     }
 
     pub fn k(&self) -> i8 {
         self.k
     }
 
+    /// Returns the number of received packets that were dropped because they
+    /// could not be decoded.
+    pub fn invalid_packets(&self) -> usize {
+        self.invalid_packets
+    }
+
+    /// Returns the number of received packets that were dropped because their
+    /// checksum or authentication code didn't match or they were replayed.
+    pub fn integrity_failures(&self) -> usize {
+        self.integrity_failures
+    }
+
+    /// Returns the loss, reordering and duplicate statistics of the received
+    /// messages.
+    pub fn sequence_stats(&self) -> SequenceStats {
+        self.sequence_tracker.stats()
+    }
+
     pub fn rate(&self) -> f64 {
         self.rate_limiter.rate()
     }
@@ -153,4 +1093,770 @@ impl<S: Serializable, R: Serializable, CD: CongestionDetector, KP: KPolicy>
     pub fn set_rate(&mut self, rate: f64) {
         self.rate_limiter.set_rate(rate);
     }
+
+    /// Writes every sent and received datagram to the pcap file at `path`
+    /// until the capture is stopped. Received datagrams are written before
+    /// they are verified. The capture stops if writing fails.
+    pub fn start_capture<P: AsRef<Path>>(&mut self, path: P) -> io::Result<()> {
+        let wtr = PcapWriter::create(path)?;
+        let (local, peer) = self.transport.addrs()?;
+        self.capture = Some(Capture::new(wtr, local, peer));
+        Ok(())
+    }
+
+    /// Stops the capture and flushes the pcap file.
+    pub fn stop_capture(&mut self) -> io::Result<()> {
+        match self.capture.take() {
+            Some(mut capture) => capture.flush(),
+            None => Ok(()),
+        }
+    }
+
+    /// Sets the interval in which receiver reports about the received data
+    /// messages are sent to the peer. No reports are sent by default.
+    pub fn set_report_interval(&mut self, interval: Option<Duration>) {
+        self.report_interval = interval.map(|interval| interval.as_micros() as u64);
+    }
+
+    /// Returns the latest receiver report of the peer about the sent data
+    /// messages.
+    pub fn peer_report(&self) -> Option<ReceiverReport> {
+        self.peer_report
+    }
+
+    /// Returns the estimated interarrival jitter of the received data
+    /// messages in [us].
+    pub fn jitter(&self) -> u32 {
+        self.reception.jitter()
+    }
+
+    /// Enables the playout buffer with `config` or disables it with `None`.
+    /// The playout buffer releases each received sample at its sampling time
+    /// plus a playout delay that adapts to the jitter, instead of as soon as
+    /// it is received. The playout delay covers at least the `k_max` samples
+    /// of a message. Samples that are buffered are dropped.
+    pub fn set_playout(&mut self, config: Option<PlayoutConfig>) {
+        self.playout = config.map(PlayoutBuffer::new);
+        self.update_playout_floor();
+    }
+
+    // Lets the playout delay cover the spread of the samples of a message.
+    fn update_playout_floor(&mut self) {
+        let floor = self.k_max as u64 * self.sample_period;
+        if let Some(playout) = &mut self.playout {
+            playout.set_floor(floor);
+        }
+    }
+
+    /// Sets the smallest playout delay in [us] of the playout buffer, if any.
+    pub fn set_target_latency(&mut self, target_latency: u64) {
+        if let Some(playout) = &mut self.playout {
+            playout.set_target_latency(target_latency);
+        }
+    }
+
+    /// Returns the statistics of the playout buffer, if any.
+    pub fn playout_stats(&self) -> Option<PlayoutStats> {
+        self.playout.as_ref().map(PlayoutBuffer::stats)
+    }
+
+    /// Returns the number of sent control messages that are not acknowledged
+    /// yet.
+    pub fn unacked_controls(&self) -> usize {
+        self.control_sender.unacked()
+    }
+
+    /// Returns the number of data messages that were read in one call
+    /// together with a newer one in order. Only the newest one used to be
+    /// processed, so they indicate bursts.
+    pub fn superseded_packets(&self) -> usize {
+        self.superseded_packets
+    }
+
+    /// Returns the number of received samples that were dropped before they
+    /// were read, as a newer data message arrived.
+    pub fn cleared_samples(&self) -> usize {
+        self.cleared_samples
+    }
+
+    /// Sets which transient socket errors are returned.
+    pub fn set_transient_error_policy(&mut self, policy: TransientErrorPolicy) {
+        self.errors.set_policy(policy);
+    }
+
+    /// Returns the number of transient socket errors, e.g. because the peer
+    /// wasn't up, including the tolerated ones.
+    pub fn transient_errors(&self) -> usize {
+        self.errors.count()
+    }
+
+    /// Returns true while datagrams are captured.
+    pub fn is_capturing(&self) -> bool {
+        self.capture.is_some()
+    }
+}
+
+/// Returns a random number identifying a session.
+fn random_session() -> io::Result<u64> {
+    let mut bs = [0; 8];
+    getrandom::getrandom(&mut bs)?;
+    Ok(u64::from_be_bytes(bs))
+}
+
+/// Writes the datagram `bs` to the capture, if any, and stops it if that
+/// fails.
+fn record(capture: &mut Option<Capture<BufWriter<File>>>, direction: Direction, bs: &[u8]) {
+    if let Some(c) = capture {
+        if c.record(direction, bs).is_err() {
+            *capture = None;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::IntegrityConfig;
+    use crate::congestion_detection::Window;
+    use crate::hoip::{PayloadM2S, PayloadS2M, KEY_LEN};
+    use crate::k_policy::KPolicySDMI;
+    use crate::transport::{ChannelConfig, GilbertElliotConfig, LossyTransport, MemoryTransport};
+
+    type Master<T = MemoryTransport> =
+        NetworkModule<PayloadM2S, PayloadS2M, Window, KPolicySDMI, T>;
+    type Slave<T = MemoryTransport> = NetworkModule<PayloadS2M, PayloadM2S, Window, KPolicySDMI, T>;
+    type Lossy = LossyTransport<MemoryTransport>;
+
+    fn config(op: PayloadType) -> NetworkModuleConfig {
+        NetworkModuleConfig {
+            op,
+            rate: 1e9,
+            ..NetworkModuleConfig::default()
+        }
+    }
+
+    fn header(timestamp: u64, sequence: u16) -> Header {
+        Header {
+            payload_type: PayloadType::Master,
+            sampling_scheme: SamplingScheme::Lossless,
+            num_samples: 1,
+            delay_indicator: DelayIndicator::InHeader,
+            threshold: 0,
+            rott: 0,
+            timestamp,
+            sequence,
+            redundancy: 0,
+            compression: None,
+            profile: Profile::Standard,
+        }
+    }
+
+    /// Returns the capabilities of a master of the session `session` that
+    /// is compatible with `slave`.
+    fn master_caps(slave: &Slave, session: u64) -> Capabilities {
+        let mut caps = slave.capabilities.clone();
+        std::mem::swap(&mut caps.send_len, &mut caps.recv_len);
+        caps.session = session;
+        caps.echo = 0;
+        caps
+    }
+
+    /// Starts the session of a master with `caps` over the raw `master`
+    /// transport. Returns the integrity of the session and the sealed
+    /// handshakes that were sent.
+    fn start(
+        master: &mut MemoryTransport,
+        slave: &mut Slave,
+        mut caps: Capabilities,
+        mut integrity: Integrity,
+    ) -> (Integrity, Vec<Vec<u8>>) {
+        let mut hello = Handshake::Hello(caps.clone()).to_bytes();
+        integrity.seal(&mut hello);
+        master.send(&hello).unwrap();
+        slave.try_recv().unwrap();
+        // The slave answers with its session, which the master echoes.
+        let mut buf = [0; 256];
+        caps.echo = loop {
+            let (len, _) = master.recv(&mut buf).unwrap();
+            if let Ok(bs) = integrity.open(&mut buf[..len]) {
+                if let Ok(Packet::Handshake(Handshake::Accept(remote))) = Packet::from_bytes(bs) {
+                    if remote.echo == caps.session {
+                        break remote.session;
+                    }
+                }
+            }
+        };
+        let mut accept = Handshake::Accept(caps.clone()).to_bytes();
+        integrity.seal(&mut accept);
+        master.send(&accept).unwrap();
+        slave.try_recv().unwrap();
+        assert_eq!(slave.connection_state(), &ConnectionState::Established);
+        integrity.rekey(caps.session, caps.echo);
+        (integrity, vec![hello, accept])
+    }
+
+    /// Returns a data message of the master with one sample, sealed by
+    /// `integrity`.
+    fn sealed(integrity: &mut Integrity, sequence: u16) -> Vec<u8> {
+        let sample = PayloadM2S::new([1.0; 3], [2.0; 3]);
+        let msg = Message::with_samples(header(now(), sequence), &[(now(), sample)]);
+        let mut bs = msg.to_bytes();
+        integrity.seal(&mut bs);
+        bs
+    }
+
+    /// Returns a slave that is connected to the returned raw transport.
+    fn raw_master() -> (MemoryTransport, Slave) {
+        let (mut master, transport) = MemoryTransport::pair();
+        let config = config(PayloadType::Slave);
+        let mut slave =
+            Slave::with_transport(config, transport, Window::new(5), KPolicySDMI::new()).unwrap();
+        let caps = master_caps(&slave, 1);
+        start(&mut master, &mut slave, caps, Integrity::None);
+        (master, slave)
+    }
+
+    #[test]
+    fn timestamp_ahead() {
+        let (mut master, mut slave) = raw_master();
+        let sample = PayloadM2S::new([1.0; 3], [2.0; 3]);
+        let msg = Message::with_samples(header(now() + 1_000_000_000, 0), &[(0, sample.clone())]);
+        master.send(&msg.to_bytes()).unwrap();
+        assert_eq!(
+            slave.try_recv().unwrap().map(|(_, s)| s),
+            Some(sample.clone())
+        );
+        assert_eq!(slave.rott, 0);
+
+        // The timestamps of the later samples of a message don't overflow.
+        let header = Header {
+            num_samples: 2,
+            ..header(u64::MAX, 1)
+        };
+        let samples = [(0, sample.clone()), (0, sample.clone())];
+        let msg = Message::with_samples(header, &samples);
+        master.send(&msg.to_bytes()).unwrap();
+        assert_eq!(slave.try_recv().unwrap(), Some((u64::MAX, sample.clone())));
+        assert_eq!(slave.try_recv().unwrap(), Some((u64::MAX, sample)));
+    }
+
+    #[test]
+    fn compact_timestamp() {
+        let (mut master, transport) = MemoryTransport::pair();
+        let config = NetworkModuleConfig {
+            compact_header: true,
+            ..config(PayloadType::Slave)
+        };
+        let mut slave =
+            Slave::with_transport(config, transport, Window::new(5), KPolicySDMI::new()).unwrap();
+        // The clock of the master is beyond the range of compact timestamps.
+        let time = (3 << 32) + 1000;
+        let caps = Capabilities {
+            time,
+            ..master_caps(&slave, 1)
+        };
+        start(&mut master, &mut slave, caps, Integrity::None);
+
+        let sample = PayloadM2S::new([1.0; 3], [2.0; 3]);
+        for (sequence, timestamp) in [time + 4000, time + 5000].iter().enumerate() {
+            let header = Header {
+                profile: Profile::Compact { rott: false },
+                ..header(*timestamp, sequence as u16)
+            };
+            let msg = Message::with_samples(header, &[(*timestamp, sample.clone())]);
+            master.send(&msg.to_bytes()).unwrap();
+            assert_eq!(
+                slave.try_recv().unwrap(),
+                Some((*timestamp, sample.clone()))
+            );
+        }
+    }
+
+    #[test]
+    fn burst() {
+        let (mut master, mut slave) = raw_master();
+        let start = now();
+        for i in 0..3 {
+            let ts = start + i * 2000;
+            let samples: Vec<_> = (0..2)
+                .map(|j| (ts + j * 1000, PayloadM2S::new([ts as f32; 3], [0.0; 3])))
+                .collect();
+            let header = Header {
+                num_samples: 2,
+                ..header(ts, i as u16)
+            };
+            master
+                .send(&Message::with_samples(header, &samples).to_bytes())
+                .unwrap();
+        }
+        // The datagrams are read at once and their samples are returned
+        // oldest first.
+        let mut timestamps = Vec::new();
+        while let Some((ts, _)) = slave.try_recv().unwrap() {
+            timestamps.push(ts - start);
+        }
+        assert_eq!(timestamps, [0, 1000, 2000, 3000, 4000, 5000]);
+        assert_eq!(slave.superseded_packets(), 2);
+        assert_eq!(slave.cleared_samples(), 0);
+
+        // Duplicates don't supersede a message, and the unread samples of a
+        // previous call are cleared by a newer message.
+        let samples = [
+            (start, PayloadM2S::default()),
+            (start, PayloadM2S::default()),
+        ];
+        let header = Header {
+            num_samples: 2,
+            ..header(start, 3)
+        };
+        let bs = Message::with_samples(header.clone(), &samples).to_bytes();
+        master.send(&bs).unwrap();
+        master.send(&bs).unwrap();
+        assert!(slave.try_recv().unwrap().is_some());
+        assert_eq!(slave.superseded_packets(), 2);
+        let header = Header {
+            sequence: 4,
+            ..header
+        };
+        let bs = Message::with_samples(header, &samples).to_bytes();
+        master.send(&bs).unwrap();
+        assert!(slave.try_recv().unwrap().is_some());
+        assert_eq!(slave.cleared_samples(), 1);
+    }
+
+    #[test]
+    fn playout_floor() {
+        let (mut master, transport) = MemoryTransport::pair();
+        let config = NetworkModuleConfig {
+            playout: Some(PlayoutConfig::default()),
+            ..config(PayloadType::Slave)
+        };
+        let mut slave =
+            Slave::with_transport(config, transport, Window::new(5), KPolicySDMI::new()).unwrap();
+        assert_eq!(slave.playout_stats().unwrap().delay, 4_000);
+
+        let caps = Capabilities {
+            k_max: 3,
+            ..master_caps(&slave, 1)
+        };
+        start(&mut master, &mut slave, caps, Integrity::None);
+        let start = now();
+        let samples: Vec<_> = (0..3)
+            .map(|i| (start + i * 1000, PayloadM2S::new([1.0; 3], [0.0; 3])))
+            .collect();
+        let header = Header {
+            num_samples: 3,
+            ..header(start, 0)
+        };
+        master
+            .send(&Message::with_samples(header, &samples).to_bytes())
+            .unwrap();
+        // Every sample of the message is played, one per sample period.
+        while slave.playout_stats().unwrap().played < 3 {
+            slave.try_recv().unwrap();
+        }
+        // The delay follows the negotiated k max.
+        let stats = slave.playout_stats().unwrap();
+        assert_eq!(stats.late, 0);
+        assert_eq!(stats.delay, 3_000);
+    }
+
+    #[test]
+    fn restart() {
+        let (mut master, transport) = MemoryTransport::pair();
+        let config = NetworkModuleConfig {
+            integrity: IntegrityConfig::Hmac {
+                key: b"secret".to_vec(),
+            },
+            ..config(PayloadType::Slave)
+        };
+        let mut slave =
+            Slave::with_transport(config, transport, Window::new(5), KPolicySDMI::new()).unwrap();
+        // Handshakes that aren't authenticated don't end the negotiation.
+        let caps = Capabilities {
+            integrity: Integrity::None.id(),
+            ..master_caps(&slave, 1)
+        };
+        master.send(&Handshake::Reject(caps).to_bytes()).unwrap();
+        slave.try_recv().unwrap();
+        assert_eq!(slave.connection_state(), &ConnectionState::Negotiating);
+        assert_eq!(slave.integrity_failures(), 1);
+
+        let caps = master_caps(&slave, 1);
+        let (mut old, handshakes) =
+            start(&mut master, &mut slave, caps, Integrity::hmac(b"secret"));
+        for sequence in 0..3 {
+            master.send(&sealed(&mut old, sequence)).unwrap();
+        }
+        while slave.try_recv().unwrap().is_some() {}
+        assert_eq!(slave.sequence_stats().received, 3);
+
+        // A restarted master starts over with a new session.
+        let caps = master_caps(&slave, 2);
+        let (mut new, _) = start(&mut master, &mut slave, caps, Integrity::hmac(b"secret"));
+        master.send(&sealed(&mut new, 0)).unwrap();
+        assert!(slave.try_recv().unwrap().is_some());
+        assert_eq!(slave.sequence_stats().received, 1);
+        // The packets of the previous session are not accepted anymore, even
+        // if its handshakes are replayed.
+        for bs in handshakes.iter() {
+            master.send(bs).unwrap();
+        }
+        master.send(&sealed(&mut old, 3)).unwrap();
+        assert_eq!(slave.try_recv().unwrap(), None);
+        assert_eq!(slave.integrity_failures(), 2);
+        assert_eq!(slave.sequence_stats().received, 1);
+
+        // Forged handshakes neither restart nor end the session.
+        let mut caps = slave.capabilities.clone();
+        caps.session = 3;
+        let mut forged = Handshake::Hello(caps.clone()).to_bytes();
+        Integrity::hmac(b"guess").seal(&mut forged);
+        master.send(&forged).unwrap();
+        caps.integrity = Integrity::None.id();
+        master.send(&Handshake::Reject(caps).to_bytes()).unwrap();
+        slave.try_recv().unwrap();
+        assert_eq!(slave.integrity_failures(), 4);
+        assert_eq!(slave.connection_state(), &ConnectionState::Established);
+        master.send(&sealed(&mut new, 1)).unwrap();
+        assert!(slave.try_recv().unwrap().is_some());
+    }
+
+    #[test]
+    fn replayed_handshake() {
+        let (mut master, transport) = MemoryTransport::pair();
+        let config = NetworkModuleConfig {
+            integrity: IntegrityConfig::ChaCha20Poly1305 { key: [3; KEY_LEN] },
+            ..config(PayloadType::Slave)
+        };
+        let mut slave =
+            Slave::with_transport(config, transport, Window::new(5), KPolicySDMI::new()).unwrap();
+        let integrity = || Integrity::chacha20poly1305(&[3; KEY_LEN]);
+        let caps = master_caps(&slave, 1);
+        let (mut old, handshakes) = start(&mut master, &mut slave, caps, integrity());
+        let data = sealed(&mut old, 0);
+        master.send(&data).unwrap();
+        assert!(slave.try_recv().unwrap().is_some());
+
+        // The handshakes of the previous session are replayed after a
+        // restart, but don't bring back its key.
+        let caps = master_caps(&slave, 2);
+        let (mut new, _) = start(&mut master, &mut slave, caps, integrity());
+        for bs in handshakes.iter() {
+            master.send(bs).unwrap();
+        }
+        master.send(&data).unwrap();
+        assert_eq!(slave.try_recv().unwrap(), None);
+        assert_eq!(slave.integrity_failures(), 1);
+        assert_eq!(slave.connection_state(), &ConnectionState::Established);
+        master.send(&sealed(&mut new, 0)).unwrap();
+        assert!(slave.try_recv().unwrap().is_some());
+    }
+
+    /// Returns a master and a slave with the configs that are built from
+    /// `config` over `transports`. They are not connected yet.
+    fn peers<T: Transport>(
+        config: impl Fn(PayloadType) -> NetworkModuleConfig,
+        (a, b): (T, T),
+    ) -> (Master<T>, Slave<T>) {
+        let master = Master::with_transport(
+            config(PayloadType::Master),
+            a,
+            Window::new(5),
+            KPolicySDMI::new(),
+        )
+        .unwrap();
+        let slave = Slave::with_transport(
+            config(PayloadType::Slave),
+            b,
+            Window::new(5),
+            KPolicySDMI::new(),
+        )
+        .unwrap();
+        (master, slave)
+    }
+
+    /// Returns links over which the datagrams of the master are lost with
+    /// the probability `loss`. The datagrams of the slave are not lost.
+    fn lossy_links(loss: f64) -> (Lossy, Lossy) {
+        let link = |err_rate| ChannelConfig {
+            transmission_delay_micros: 1_000,
+            capacity: 1_000_000.0,
+            gilbert_elliot_config: GilbertElliotConfig {
+                prob_good_to_bad: 0.0,
+                prob_bad_to_good: 1.0,
+                err_rate_good: err_rate,
+                err_rate_bad: err_rate,
+            },
+        };
+        let (a, b) = MemoryTransport::pair();
+        (
+            LossyTransport::new(a, link(loss), 7),
+            LossyTransport::new(b, link(0.0), 7),
+        )
+    }
+
+    /// Exchanges handshakes until both peers are connected.
+    fn connect<T: Transport>(master: &mut Master<T>, slave: &mut Slave<T>) {
+        let start = Instant::now();
+        while master.connection_state() != &ConnectionState::Established
+            || slave.connection_state() != &ConnectionState::Established
+        {
+            assert!(start.elapsed() < Duration::from_secs(5));
+            master.send_hello().unwrap();
+            slave.send_hello().unwrap();
+            master.try_recv().unwrap();
+            slave.try_recv().unwrap();
+            thread::sleep(Duration::from_millis(1));
+        }
+    }
+
+    /// Sends `n` samples from the master and returns the first value of
+    /// every sample the slave receives.
+    fn transfer<T: Transport>(master: &mut Master<T>, slave: &mut Slave<T>, n: usize) -> Vec<f32> {
+        let mut received = Vec::new();
+        for i in 0..n {
+            master
+                .send(PayloadM2S::new([i as f32; 3], [0.0; 3]))
+                .unwrap();
+            while let Some((_, sample)) = slave.try_recv().unwrap() {
+                received.push(sample.channel(0)[0]);
+            }
+        }
+        received.extend(settle(master, slave));
+        received
+    }
+
+    /// Lets the datagrams in flight arrive and returns the first value of
+    /// every sample the slave receives meanwhile. Both peers are polled, as
+    /// lossy links only pass datagrams on while they are used.
+    fn settle<T: Transport>(master: &mut Master<T>, slave: &mut Slave<T>) -> Vec<f32> {
+        let mut received = Vec::new();
+        for _ in 0..3 {
+            thread::sleep(Duration::from_millis(2));
+            master.try_recv().unwrap();
+            while let Some((_, sample)) = slave.try_recv().unwrap() {
+                received.push(sample.channel(0)[0]);
+            }
+        }
+        received
+    }
+
+    #[test]
+    fn handshake() {
+        let (mut master, mut slave) = peers(
+            |op| NetworkModuleConfig {
+                k_max: 1,
+                integrity: IntegrityConfig::ChaCha20Poly1305 { key: [3; KEY_LEN] },
+                ..config(op)
+            },
+            MemoryTransport::pair(),
+        );
+        connect(&mut master, &mut slave);
+        let received = transfer(&mut master, &mut slave, 100);
+        assert_eq!(received, (0..100).map(|i| i as f32).collect::<Vec<_>>());
+        assert_eq!(slave.integrity_failures(), 0);
+
+        slave.send(PayloadS2M::new([5.0; 3])).unwrap();
+        assert_eq!(
+            master.try_recv().unwrap().map(|(_, s)| s),
+            Some(PayloadS2M::new([5.0; 3]))
+        );
+
+        // Peers with different sample periods are rejected.
+        let (mut master, mut slave) = peers(
+            |op| NetworkModuleConfig {
+                sample_period_micros: match op {
+                    PayloadType::Master => 1000,
+                    PayloadType::Slave => 500,
+                },
+                ..config(op)
+            },
+            MemoryTransport::pair(),
+        );
+        master.send_hello().unwrap();
+        slave.try_recv().unwrap();
+        master.try_recv().unwrap();
+        assert!(matches!(
+            master.connection_state(),
+            ConnectionState::Rejected(HandshakeError::SamplePeriod { .. })
+        ));
+
+        // Incompatible peers are rejected.
+        let (mut master, mut slave) = peers(
+            |op| NetworkModuleConfig {
+                sampling: match op {
+                    PayloadType::Master => SamplingConfig::Lossless,
+                    PayloadType::Slave => SamplingConfig::LevelCrossing { level: 0.1 },
+                },
+                ..config(op)
+            },
+            MemoryTransport::pair(),
+        );
+        master.send_hello().unwrap();
+        slave.try_recv().unwrap();
+        master.try_recv().unwrap();
+        assert!(matches!(
+            master.connection_state(),
+            ConnectionState::Rejected(HandshakeError::SamplingScheme { .. })
+        ));
+        assert!(matches!(
+            slave.connection_state(),
+            ConnectionState::Rejected(HandshakeError::SamplingScheme { .. })
+        ));
+    }
+
+    #[test]
+    fn fec_recovery() {
+        let (mut master, mut slave) = peers(
+            |op| NetworkModuleConfig {
+                k_max: 1,
+                fec: FecConfig::Xor { n: 2 },
+                ..config(op)
+            },
+            lossy_links(0.1),
+        );
+        connect(&mut master, &mut slave);
+        let received = transfer(&mut master, &mut slave, 500);
+        assert!(slave.fec_recovered() > 0);
+        assert_eq!(
+            slave.sequence_stats().recovered as usize,
+            slave.fec_recovered()
+        );
+        // Only the messages lost together with their parity are missing.
+        let lost = 500 - received.len();
+        assert!(lost < slave.fec_recovered(), "{}", lost);
+    }
+
+    #[test]
+    fn fec_rate_limit() {
+        let (mut master, mut slave) = peers(
+            |op| NetworkModuleConfig {
+                k_max: 1,
+                rate: 1.0,
+                fec: FecConfig::Xor { n: 1 },
+                ..config(op)
+            },
+            MemoryTransport::pair(),
+        );
+        connect(&mut master, &mut slave);
+        // The data message takes the only token, so its parity is skipped.
+        master.send(PayloadM2S::new([1.0; 3], [2.0; 3])).unwrap();
+        assert_eq!(master.skipped_parities(), 1);
+        assert!(slave.try_recv().unwrap().is_some());
+    }
+
+    #[test]
+    fn redundancy() {
+        let (mut master, mut slave) = peers(
+            |op| NetworkModuleConfig {
+                k_max: 1,
+                redundancy: 1,
+                ..config(op)
+            },
+            lossy_links(0.1),
+        );
+        connect(&mut master, &mut slave);
+        let received = transfer(&mut master, &mut slave, 500);
+        assert!(slave.concealed_samples() > 0);
+        assert!(500 - received.len() < slave.concealed_samples());
+    }
+
+    #[test]
+    fn control_retransmission() {
+        let (mut master, mut slave) = peers(config, lossy_links(0.3));
+        connect(&mut master, &mut slave);
+        for i in 0..10 {
+            assert!(master.send_control(Control::Custom(vec![i])).unwrap());
+        }
+        let start = Instant::now();
+        let mut received = Vec::new();
+        while received.len() < 10 || master.unacked_controls() > 0 {
+            assert!(start.elapsed() < Duration::from_secs(5));
+            master.try_recv().unwrap();
+            while let Some(control) = slave.try_recv_control().unwrap() {
+                received.push(control);
+            }
+            thread::sleep(Duration::from_millis(1));
+        }
+        // Every message is delivered once and in order.
+        let expected: Vec<_> = (0..10).map(|i| Control::Custom(vec![i])).collect();
+        assert_eq!(received, expected);
+    }
+
+    #[test]
+    fn reports() {
+        let (mut master, mut slave) = peers(
+            |op| NetworkModuleConfig {
+                k_max: 1,
+                report_interval_micros: Some(1_000),
+                ..config(op)
+            },
+            lossy_links(0.2),
+        );
+        connect(&mut master, &mut slave);
+        transfer(&mut master, &mut slave, 200);
+        settle(&mut master, &mut slave);
+        let report = master.peer_report().unwrap();
+        assert!(report.cumulative_lost > 0);
+        assert!(
+            report.highest_sequence >= 150,
+            "{}",
+            report.highest_sequence
+        );
+    }
+
+    #[test]
+    fn playout() {
+        let (mut master, mut slave) = peers(
+            |op| NetworkModuleConfig {
+                playout: Some(PlayoutConfig::default()),
+                ..config(op)
+            },
+            MemoryTransport::pair(),
+        );
+        connect(&mut master, &mut slave);
+        let start = now();
+        while now() < start + 100_000 {
+            master.send(PayloadM2S::new([1.0; 3], [0.0; 3])).unwrap();
+            slave.try_recv().unwrap();
+            thread::sleep(Duration::from_millis(1));
+        }
+        let stats = slave.playout_stats().unwrap();
+        assert!(stats.played > 0);
+        // The delay covers at least the samples of a message.
+        let floor = K_MAX as u64 * slave.sample_period;
+        assert!(stats.delay >= floor, "{}", stats.delay);
+    }
+
+    #[test]
+    fn error_policy() {
+        let (mut master, mut slave) = peers(
+            |op| NetworkModuleConfig {
+                k_max: 1,
+                ..config(op)
+            },
+            MemoryTransport::pair(),
+        );
+        connect(&mut master, &mut slave);
+        drop(slave);
+        let sample = PayloadM2S::new([1.0; 3], [0.0; 3]);
+        // The peer going away is tolerated by default.
+        master.send(sample.clone()).unwrap();
+        assert_eq!(master.transient_errors(), 1);
+
+        master.set_transient_error_policy(TransientErrorPolicy::Limit { max: 2 });
+        master.send(sample.clone()).unwrap();
+        master.send(sample.clone()).unwrap();
+        assert!(master.send(sample.clone()).is_err());
+
+        master.set_transient_error_policy(TransientErrorPolicy::Fail);
+        match master.send(sample) {
+            Err(NetworkModuleError::Io(e)) => {
+                assert_eq!(e.kind(), io::ErrorKind::ConnectionRefused)
+            }
+            result => panic!("{:?}", result),
+        }
+        assert_eq!(master.transient_errors(), 5);
+    }
 }
@@ -0,0 +1,231 @@
+use serde::{Deserialize, Serialize};
+use std::cmp::{max, min};
+
+/// The number of samples after which the smallest transit time is measured
+/// anew, so the playout follows a drift of the clocks.
+const BASE_WINDOW: usize = 1000;
+
+/// The configuration of the playout buffer.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct PlayoutConfig {
+    /// The smallest playout delay in [us] on top of the smallest transit time.
+    /// The playout delay is never below the time `k_max` samples span, so the
+    /// older samples of a message are not late.
+    pub target_latency_micros: u64,
+    /// The playout delay is at least this multiple of the interarrival jitter.
+    pub jitter_factor: f64,
+    /// The largest playout delay in [us].
+    pub max_latency_micros: u64,
+}
+
+impl Default for PlayoutConfig {
+    fn default() -> Self {
+        Self {
+            target_latency_micros: 2_000,
+            jitter_factor: 4.0,
+            max_latency_micros: 50_000,
+        }
+    }
+}
+
+/// The statistics of a playout buffer.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct PlayoutStats {
+    /// The number of samples that were released.
+    pub played: u64,
+    /// The number of samples that were discarded because their playout time
+    /// had passed.
+    pub late: u64,
+    /// The number of samples that wait for their playout time.
+    pub buffered: usize,
+    /// The current playout delay in [us].
+    pub delay: u64,
+}
+
+/// Releases received samples at their sampling time plus a constant offset,
+/// so bursts of samples are smoothed into a steady stream.
+///
+/// The offset is the smallest transit time, which includes the offset of
+/// the clocks, plus a playout delay that adapts to the jitter.
+#[derive(Debug)]
+pub struct PlayoutBuffer<R> {
+    config: PlayoutConfig,
+    // The samples ordered by their timestamp.
+    samples: Vec<(u64, R)>,
+    // The smallest transit time in [us] and the smallest one in the current
+    // window.
+    base: Option<i64>,
+    window_min: i64,
+    window_len: usize,
+    // The smallest delay that covers the spread of the samples of a message.
+    floor: u64,
+    delay: u64,
+    // The timestamp of the last released sample.
+    last_played: Option<u64>,
+    played: u64,
+    late: u64,
+}
+
+impl<R> PlayoutBuffer<R> {
+    pub fn new(config: PlayoutConfig) -> Self {
+        Self {
+            config,
+            samples: Vec::new(),
+            base: None,
+            window_min: i64::MAX,
+            window_len: 0,
+            floor: 0,
+            delay: config.target_latency_micros,
+            last_played: None,
+            played: 0,
+            late: 0,
+        }
+    }
+
+    /// Sets the smallest playout delay in [us].
+    pub fn set_target_latency(&mut self, target_latency: u64) {
+        self.config.target_latency_micros = target_latency;
+        self.delay = max(self.delay, target_latency);
+    }
+
+    /// Sets the smallest playout delay in [us] regardless of the
+    /// configuration. It is the time between the oldest and the newest sample
+    /// of a message plus a sample period, so all samples of a message are
+    /// played although they arrive together.
+    pub fn set_floor(&mut self, floor: u64) {
+        self.floor = floor;
+        self.delay = max(self.delay, floor);
+    }
+
+    /// Adds a sample taken at `ts` that arrived at `now`, both in [us] of the
+    /// respective clock. `jitter` is the current interarrival jitter in [us].
+    pub fn push(&mut self, now: u64, ts: u64, sample: R, jitter: u32) {
+        self.on_transit(now as i64 - ts as i64);
+        let adaptive = (self.config.jitter_factor * f64::from(jitter)) as u64;
+        let target = max(self.config.target_latency_micros, self.floor);
+        self.delay = max(min(adaptive, self.config.max_latency_micros), target);
+        if let Some(last_played) = self.last_played {
+            if ts <= last_played {
+                self.late += 1;
+                return;
+            }
+        }
+        // Samples that are received twice are only played once.
+        if let Err(i) = self.samples.binary_search_by_key(&ts, |(ts, _)| *ts) {
+            self.samples.insert(i, (ts, sample));
+        }
+    }
+
+    fn on_transit(&mut self, transit: i64) {
+        self.window_min = min(self.window_min, transit);
+        self.window_len += 1;
+        match self.base {
+            Some(base) if base <= transit => {}
+            _ => self.base = Some(transit),
+        }
+        if self.window_len == BASE_WINDOW {
+            self.base = Some(self.window_min);
+            self.window_min = i64::MAX;
+            self.window_len = 0;
+        }
+    }
+
+    fn is_due(&self, ts: u64, now: u64) -> bool {
+        match self.base {
+            Some(base) => ts as i64 + base + self.delay as i64 <= now as i64,
+            None => false,
+        }
+    }
+
+    /// Returns the newest sample whose playout time passed at `now`. Older
+    /// samples that are due as well are discarded as late.
+    pub fn pop(&mut self, now: u64) -> Option<(u64, R)> {
+        let due = self
+            .samples
+            .iter()
+            .take_while(|(ts, _)| self.is_due(*ts, now))
+            .count();
+        if due == 0 {
+            return None;
+        }
+        self.late += (due - 1) as u64;
+        self.samples.drain(..due - 1);
+        let (ts, sample) = self.samples.remove(0);
+        self.last_played = Some(ts);
+        self.played += 1;
+        Some((ts, sample))
+    }
+
+    pub fn stats(&self) -> PlayoutStats {
+        PlayoutStats {
+            played: self.played,
+            late: self.late,
+            buffered: self.samples.len(),
+            delay: self.delay,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn playout() {
+        let config = PlayoutConfig {
+            max_latency_micros: 10_000,
+            ..PlayoutConfig::default()
+        };
+        // Bursts of 4 samples taken 1 ms apart arrive every 4 ms. The clock of
+        // the peer is 100 ms ahead and the transit time is 1 ms.
+        let offset = 100_000;
+        let push_bursts = |buffer: &mut PlayoutBuffer<u64>| {
+            for burst in 0..3u64 {
+                let arrival = burst * 4_000 + 4_000;
+                for i in 0..4 {
+                    let ts = offset + burst * 4_000 + i * 1_000;
+                    buffer.push(arrival, ts, ts, 0);
+                }
+            }
+        };
+
+        // Without the floor the oldest samples of a burst are due on arrival.
+        let mut buffer = PlayoutBuffer::new(config);
+        push_bursts(&mut buffer);
+        assert_eq!(buffer.pop(4_000).map(|(ts, _)| ts - offset), Some(1_000));
+        assert_eq!(buffer.stats().late, 1);
+
+        let mut buffer = PlayoutBuffer::new(config);
+        buffer.set_floor(4 * 1_000);
+        push_bursts(&mut buffer);
+        assert_eq!(buffer.stats().buffered, 12);
+
+        // The delay covers the age of the oldest sample of a burst, so one
+        // sample is played per ms.
+        assert_eq!(buffer.pop(4_999), None);
+        let mut played = Vec::new();
+        for now in (5_000..17_000).step_by(1_000) {
+            played.push(buffer.pop(now).map(|(ts, _)| ts - offset));
+        }
+        let expected = (0..12).map(|i| Some(i * 1_000)).collect::<Vec<_>>();
+        assert_eq!(played, expected);
+
+        // A sample that arrives after its slot is discarded.
+        buffer.push(17_000, offset + 5_000, 0, 0);
+        assert_eq!(buffer.stats().late, 1);
+
+        // Samples that are due together are skipped except the newest.
+        buffer.push(17_000, offset + 13_000, 0, 0);
+        buffer.push(17_000, offset + 14_000, 0, 0);
+        assert_eq!(buffer.pop(20_000).map(|(ts, _)| ts), Some(offset + 14_000));
+        assert_eq!(buffer.stats().late, 2);
+        assert_eq!(buffer.stats().played, 13);
+
+        // The delay adapts to the jitter up to the maximum.
+        buffer.push(21_000, offset + 20_000, 0, 1_500);
+        assert_eq!(buffer.stats().delay, 6_000);
+        buffer.push(21_000, offset + 20_000, 0, 5_000);
+        assert_eq!(buffer.stats().delay, 10_000);
+    }
+}
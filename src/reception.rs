@@ -0,0 +1,96 @@
+use crate::congestion_detection::CongestionState;
+use crate::hoip::ReceiverReport;
+use crate::sequence::SequenceTracker;
+
+/// Collects the statistics of the received data messages that are sent back
+/// to the peer in receiver reports.
+#[derive(Debug, Default)]
+pub struct ReceptionStats {
+    // The estimated interarrival jitter in [us].
+    jitter: f64,
+    // The difference between the arrival time and the timestamp of the
+    // previous message.
+    prev_transit: Option<i64>,
+    // The number of expected and received messages at the previous report.
+    prev_expected: u64,
+    prev_received: u64,
+}
+
+impl ReceptionStats {
+    /// Registers a data message sent at `timestamp` that arrived at `arrival`,
+    /// both in [us] of the respective clock.
+    pub fn on_arrival(&mut self, timestamp: u64, arrival: u64) {
+        let transit = arrival as i64 - timestamp as i64;
+        if let Some(prev_transit) = self.prev_transit {
+            // The offset of the clocks cancels out in the difference.
+            let d = (transit - prev_transit).abs() as f64;
+            self.jitter += (d - self.jitter) / 16.0;
+        }
+        self.prev_transit = Some(transit);
+    }
+
+    /// Returns the estimated interarrival jitter in [us].
+    pub fn jitter(&self) -> u32 {
+        self.jitter as u32
+    }
+
+    /// Creates a report covering the messages since the previous report.
+    /// Returns `None` if no message was received yet.
+    pub fn report(
+        &mut self,
+        tracker: &SequenceTracker,
+        congestion_state: CongestionState,
+    ) -> Option<ReceiverReport> {
+        let highest_sequence = tracker.extended_highest()?;
+        let stats = tracker.stats();
+        // Messages that arrive late are no longer counted as lost, so every
+        // message up to the highest one is either received or lost.
+        let expected = stats.received + stats.lost;
+        let expected_interval = expected.saturating_sub(self.prev_expected);
+        let received_interval = stats.received.saturating_sub(self.prev_received);
+        let lost_interval = expected_interval.saturating_sub(received_interval);
+        self.prev_expected = expected;
+        self.prev_received = stats.received;
+        let fraction_lost = match expected_interval {
+            0 => 0,
+            expected => std::cmp::min(255, (lost_interval << 8) / expected) as u8,
+        };
+        Some(ReceiverReport {
+            fraction_lost,
+            cumulative_lost: std::cmp::min(stats.lost, u64::from(u32::MAX)) as u32,
+            highest_sequence,
+            jitter: self.jitter(),
+            congestion_state,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn report() {
+        let mut tracker = SequenceTracker::new();
+        let mut stats = ReceptionStats::default();
+        assert_eq!(stats.report(&tracker, CongestionState::NotSure), None);
+
+        // Every other message is lost and the delay alternates by 100 us.
+        for i in 0..8u16 {
+            tracker.on_receive(i * 2);
+            let delay = if i % 2 == 0 { 1000 } else { 1100 };
+            stats.on_arrival(u64::from(i) * 2000, u64::from(i) * 2000 + delay);
+        }
+        let report = stats.report(&tracker, CongestionState::Congested).unwrap();
+        assert_eq!(report.highest_sequence, 14);
+        assert_eq!(report.cumulative_lost, 7);
+        assert_eq!(report.fraction_lost, (7 * 256 / 15) as u8);
+        assert_eq!(report.congestion_state, CongestionState::Congested);
+        assert!(report.jitter > 0 && report.jitter < 100);
+
+        tracker.on_receive(15);
+        let report = stats.report(&tracker, CongestionState::NotSure).unwrap();
+        assert_eq!(report.fraction_lost, 0);
+        assert_eq!(report.cumulative_lost, 7);
+    }
+}
@@ -0,0 +1,328 @@
+use crate::hoip::SamplingScheme;
+use serde::{Deserialize, Serialize};
+
+/// The period between two consecutive haptic samples in [us].
+pub(crate) const SAMPLE_PERIOD: u64 = 1000;
+
+/// A haptic sample that consists of one or more vector channels
+/// (e.g. position and velocity). The default sample has the length of every
+/// channel.
+pub trait Sample: Clone + Default {
+    /// Returns the number of channels of the sample.
+    fn num_channels() -> usize;
+
+    /// Returns the values of the channel `i`.
+    fn channel(&self, i: usize) -> &[f32];
+
+    /// Returns the mutable values of the channel `i`.
+    fn channel_mut(&mut self, i: usize) -> &mut [f32];
+}
+
+/// How the Weber deadband compares two samples.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum Deadband {
+    /// Every axis of a channel is compared on its own.
+    PerAxis,
+    /// The euclidean norm of the difference of a channel is compared.
+    Norm,
+}
+
+/// The sampling scheme used by the sender.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum SamplingConfig {
+    /// Every sample is transmitted.
+    Lossless,
+    /// A sample is only transmitted if its relative change to the last
+    /// transmitted sample exceeds the Weber `fraction`.
+    Weber { fraction: f32, deadband: Deadband },
+    /// A sample is only transmitted if one of its axes crosses a multiple of
+    /// the positive `level`. The transmitted sample is quantised to the
+    /// crossed levels.
+    LevelCrossing { level: f32 },
+}
+
+impl SamplingConfig {
+    /// Returns the scheme that is signalled in the header.
+    pub fn scheme(&self) -> SamplingScheme {
+        match self {
+            SamplingConfig::Lossless => SamplingScheme::Lossless,
+            SamplingConfig::Weber { .. } => SamplingScheme::Weber,
+            SamplingConfig::LevelCrossing { .. } => SamplingScheme::LevelCrossing,
+        }
+    }
+
+    /// Returns the threshold that is signalled in the header in thousandths.
+    pub fn threshold(&self) -> u16 {
+        match self {
+            SamplingConfig::Lossless => 0,
+            SamplingConfig::Weber { fraction, .. } => to_thousandths(*fraction),
+            SamplingConfig::LevelCrossing { level } => to_thousandths(*level),
+        }
+    }
+}
+
+fn to_thousandths(v: f32) -> u16 {
+    (v * 1000.0).round().max(0.0).min(f32::from(u16::MAX)) as u16
+}
+
+fn norm<I: Iterator<Item = f32>>(values: I) -> f32 {
+    values.map(|v| v * v).sum::<f32>().sqrt()
+}
+
+/// Decides which samples are transmitted.
+pub struct Sampler<S> {
+    config: SamplingConfig,
+    // The last sample that passed the sampler.
+    last: Option<S>,
+    // The level index of every axis of the last sample for level crossing.
+    levels: Vec<i64>,
+}
+
+impl<S: Sample> Sampler<S> {
+    pub fn new(config: SamplingConfig) -> Self {
+        Self {
+            config,
+            last: None,
+            levels: Vec::new(),
+        }
+    }
+
+    pub fn config(&self) -> SamplingConfig {
+        self.config
+    }
+
+    /// Returns the sample that should be transmitted, if any.
+    pub fn sample(&mut self, sample: S) -> Option<S> {
+        let sample = match self.config {
+            SamplingConfig::Lossless => return Some(sample),
+            SamplingConfig::Weber { fraction, deadband } => self.weber(sample, fraction, deadband),
+            SamplingConfig::LevelCrossing { level } => self.level_crossing(sample, level),
+        };
+        if let Some(sample) = &sample {
+            self.last = Some(sample.clone());
+        }
+        sample
+    }
+
+    fn weber(&self, sample: S, fraction: f32, deadband: Deadband) -> Option<S> {
+        let last = match &self.last {
+            Some(last) => last,
+            None => return Some(sample),
+        };
+        let pass = (0..S::num_channels()).any(|i| {
+            let (new, old) = (sample.channel(i), last.channel(i));
+            match deadband {
+                Deadband::PerAxis => new
+                    .iter()
+                    .zip(old)
+                    .any(|(n, o)| (n - o).abs() > fraction * o.abs()),
+                Deadband::Norm => {
+                    norm(new.iter().zip(old).map(|(n, o)| n - o))
+                        > fraction * norm(old.iter().cloned())
+                }
+            }
+        });
+        if pass {
+            Some(sample)
+        } else {
+            None
+        }
+    }
+
+    fn level_crossing(&mut self, mut sample: S, level: f32) -> Option<S> {
+        let first = self.last.is_none();
+        let mut crossed = first;
+        let mut j = 0;
+        for i in 0..S::num_channels() {
+            for (a, v) in sample.channel_mut(i).iter_mut().enumerate() {
+                let index = (*v / level).floor() as i64;
+                if first {
+                    self.levels.push(index);
+                    *v = index as f32 * level;
+                } else if index > self.levels[j] {
+                    // The signal rose above the level `index`.
+                    self.levels[j] = index;
+                    *v = index as f32 * level;
+                    crossed = true;
+                } else if index < self.levels[j] {
+                    // The signal fell below the level `index + 1`.
+                    self.levels[j] = index;
+                    *v = (index + 1) as f32 * level;
+                    crossed = true;
+                } else if let Some(last) = &self.last {
+                    *v = last.channel(i)[a];
+                }
+                j += 1;
+            }
+        }
+        if crossed {
+            Some(sample)
+        } else {
+            None
+        }
+    }
+}
+
+/// Reconstructs a sample on every tick on the receiver by holding the last
+/// received sample. For level crossing this holds the last crossed level, so
+/// the reconstruction is within one level of the signal. It doesn't
+/// interpolate between crossings, as that needs the next crossing and would
+/// delay every sample until it arrives.
+pub struct Reconstructor<R> {
+    // The last received sample and its timestamp.
+    held: Option<(u64, R)>,
+    // The time the last sample was handed to the application.
+    last_output: u64,
+    // The period between two ticks in [us].
+    sample_period: u64,
+}
+
+impl<R: Sample> Default for Reconstructor<R> {
+    fn default() -> Self {
+        Self::new(SAMPLE_PERIOD)
+    }
+}
+
+impl<R: Sample> Reconstructor<R> {
+    /// Creates a reconstructor for ticks that are `sample_period` [us] apart.
+    pub fn new(sample_period: u64) -> Self {
+        Self {
+            held: None,
+            last_output: 0,
+            sample_period,
+        }
+    }
+
+    /// Registers a received sample that was handed to the application at `now`.
+    pub fn update(&mut self, now: u64, ts: u64, sample: R) {
+        self.held = Some((ts, sample));
+        self.last_output = now;
+    }
+
+    /// Returns the held sample if a tick has passed since the last output.
+    pub fn hold(&mut self, now: u64) -> Option<(u64, R)> {
+        if now < self.last_output + self.sample_period {
+            return None;
+        }
+        self.last_output = now;
+        self.held.clone()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hoip::PayloadS2M;
+
+    #[test]
+    fn weber() {
+        for deadband in vec![Deadband::PerAxis, Deadband::Norm].into_iter() {
+            let mut sampler = Sampler::new(SamplingConfig::Weber {
+                fraction: 0.1,
+                deadband,
+            });
+            assert!(sampler.sample(PayloadS2M::new([1.0, 1.0, 1.0])).is_some());
+            assert!(sampler.sample(PayloadS2M::new([1.05, 1.0, 1.0])).is_none());
+            assert!(sampler.sample(PayloadS2M::new([1.2, 1.2, 1.2])).is_some());
+            assert!(sampler.sample(PayloadS2M::new([1.25, 1.2, 1.2])).is_none());
+        }
+    }
+
+    #[test]
+    fn level_crossing() {
+        let mut sampler = Sampler::new(SamplingConfig::LevelCrossing { level: 0.5 });
+        assert_eq!(
+            sampler.sample(PayloadS2M::new([0.2, 0.7, -0.2])),
+            Some(PayloadS2M::new([0.0, 0.5, -0.5]))
+        );
+        assert_eq!(sampler.sample(PayloadS2M::new([0.4, 0.9, -0.1])), None);
+        assert_eq!(
+            sampler.sample(PayloadS2M::new([1.1, 0.9, -0.1])),
+            Some(PayloadS2M::new([1.0, 0.5, -0.5]))
+        );
+        assert_eq!(
+            sampler.sample(PayloadS2M::new([1.1, 0.3, -0.1])),
+            Some(PayloadS2M::new([1.0, 0.5, -0.5]))
+        );
+        assert_eq!(sampler.sample(PayloadS2M::new([1.2, 0.4, -0.4])), None);
+    }
+
+    #[test]
+    fn reconstructor() {
+        let mut reconstructor = Reconstructor::new(1000);
+        assert_eq!(reconstructor.hold(1000), None);
+        reconstructor.update(1500, 1200, PayloadS2M::new([1.0, 2.0, 3.0]));
+        // No tick has passed since the sample was handed out.
+        assert_eq!(reconstructor.hold(2000), None);
+        assert_eq!(
+            reconstructor.hold(2500),
+            Some((1200, PayloadS2M::new([1.0, 2.0, 3.0])))
+        );
+        assert_eq!(reconstructor.hold(3000), None);
+        assert!(reconstructor.hold(3500).is_some());
+    }
+
+    #[test]
+    fn weber_reconstruction() {
+        let fraction = 0.1;
+        for deadband in vec![Deadband::PerAxis, Deadband::Norm].into_iter() {
+            let mut sampler = Sampler::new(SamplingConfig::Weber { fraction, deadband });
+            let mut reconstructor = Reconstructor::new(SAMPLE_PERIOD);
+            let mut transmitted = 0;
+            for i in 0..1000 {
+                let t = i as f32 / 1000.0;
+                let v = 1.0 + (t * 10.0).sin();
+                let original = PayloadS2M::new([v, 2.0 * v, 1.5]);
+                let now = i * SAMPLE_PERIOD;
+                let output = match sampler.sample(original.clone()) {
+                    Some(sample) => {
+                        transmitted += 1;
+                        reconstructor.update(now, now, sample.clone());
+                        sample
+                    }
+                    None => reconstructor.hold(now).unwrap().1,
+                };
+                // The held sample differs from the original by at most the
+                // Weber fraction.
+                let (o, h) = (original.channel(0), output.channel(0));
+                match deadband {
+                    Deadband::PerAxis => assert!(o
+                        .iter()
+                        .zip(h)
+                        .all(|(o, h)| (o - h).abs() <= fraction * h.abs())),
+                    Deadband::Norm => assert!(
+                        norm(o.iter().zip(h).map(|(o, h)| o - h))
+                            <= fraction * norm(h.iter().cloned())
+                    ),
+                }
+            }
+            assert!(transmitted < 500, "{}", transmitted);
+        }
+    }
+
+    #[test]
+    fn level_crossing_reconstruction() {
+        let level = 0.1;
+        let mut sampler = Sampler::new(SamplingConfig::LevelCrossing { level });
+        let mut reconstructor = Reconstructor::new(SAMPLE_PERIOD);
+        let mut transmitted = 0;
+        for i in 0..1000 {
+            let t = i as f32 / 1000.0;
+            let v = 1.0 + (t * 10.0).sin();
+            let original = PayloadS2M::new([v, -2.0 * v, 1.5]);
+            let now = i * SAMPLE_PERIOD;
+            let output = match sampler.sample(original.clone()) {
+                Some(sample) => {
+                    transmitted += 1;
+                    reconstructor.update(now, now, sample.clone());
+                    sample
+                }
+                None => reconstructor.hold(now).unwrap().1,
+            };
+            // The held level differs from the original by at most a level.
+            let (o, h) = (original.channel(0), output.channel(0));
+            assert!(o.iter().zip(h).all(|(o, h)| (o - h).abs() <= level));
+        }
+        assert!(transmitted < 500, "{}", transmitted);
+    }
+}
@@ -0,0 +1,202 @@
+/// The number of sequence numbers behind the highest one that are remembered
+/// for duplicate detection.
+const WINDOW: u16 = 64;
+
+/// How a received message relates to the previously received ones.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Arrival {
+    /// The message is newer than every message received so far.
+    InOrder,
+    /// The message is older than the newest message received so far.
+    Reordered,
+    /// The message was already received.
+    Duplicate,
+}
+
+/// Statistics about the sequence numbers of the received messages.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct SequenceStats {
+    /// The number of distinct messages received.
+    pub received: u64,
+    /// The number of messages that are missing.
+    pub lost: u64,
+    /// The number of messages that arrived after a newer message.
+    pub reordered: u64,
+    /// The number of messages that arrived more than once.
+    pub duplicates: u64,
+    /// The number of lost messages that were recovered.
+    pub recovered: u64,
+}
+
+/// Tracks the sequence numbers of received messages.
+#[derive(Debug, Default)]
+pub struct SequenceTracker {
+    // The highest sequence number received so far.
+    highest: Option<u16>,
+    // Bit `i` is set if `highest - i` was received.
+    window: u64,
+    // The number of times the highest sequence number wrapped around.
+    cycles: u16,
+    stats: SequenceStats,
+}
+
+impl SequenceTracker {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Registers the sequence number of a received message.
+    pub fn on_receive(&mut self, sequence: u16) -> Arrival {
+        self.register(sequence, false)
+    }
+
+    /// Registers the sequence number of a lost message that was recovered,
+    /// e.g. by forward error correction. It stays counted as lost. Returns
+    /// false if the message was already received or recovered.
+    pub fn on_recover(&mut self, sequence: u16) -> bool {
+        self.register(sequence, true) != Arrival::Duplicate
+    }
+
+    fn register(&mut self, sequence: u16, recovered: bool) -> Arrival {
+        let highest = match self.highest {
+            Some(highest) => highest,
+            None => {
+                self.highest = Some(sequence);
+                self.window = 1;
+                self.count(recovered);
+                return Arrival::InOrder;
+            }
+        };
+
+        let diff = sequence.wrapping_sub(highest) as i16;
+        if diff > 0 {
+            let diff = diff as u16;
+            if sequence < highest {
+                self.cycles = self.cycles.wrapping_add(1);
+            }
+            self.stats.lost += u64::from(diff - 1);
+            self.window = if diff < WINDOW {
+                (self.window << diff) | 1
+            } else {
+                1
+            };
+            self.highest = Some(sequence);
+            self.count(recovered);
+            return Arrival::InOrder;
+        }
+
+        let age = highest.wrapping_sub(sequence);
+        if age < WINDOW {
+            let bit = 1 << age;
+            if self.window & bit != 0 {
+                if !recovered {
+                    self.stats.duplicates += 1;
+                }
+                return Arrival::Duplicate;
+            }
+            self.window |= bit;
+            if recovered {
+                self.stats.recovered += 1;
+                return Arrival::Reordered;
+            }
+            self.stats.lost = self.stats.lost.saturating_sub(1);
+        } else if recovered {
+            // The message is already counted as lost.
+            self.stats.recovered += 1;
+            return Arrival::Reordered;
+        }
+        // Messages older than the window can't be told apart from duplicates
+        // and are counted as reordered.
+        self.stats.received += 1;
+        self.stats.reordered += 1;
+        Arrival::Reordered
+    }
+
+    // Counts a message that is newer than every message so far.
+    fn count(&mut self, recovered: bool) {
+        if recovered {
+            self.stats.lost += 1;
+            self.stats.recovered += 1;
+        } else {
+            self.stats.received += 1;
+        }
+    }
+
+    /// Returns true if the message with `sequence` was received. Messages
+    /// older than the window are reported as not received.
+    pub fn is_received(&self, sequence: u16) -> bool {
+        let highest = match self.highest {
+            Some(highest) => highest,
+            None => return false,
+        };
+        let age = highest.wrapping_sub(sequence);
+        age < WINDOW && self.window & (1 << age) != 0
+    }
+
+    /// Returns the highest sequence number received, extended by the number
+    /// of its wrap arounds in the upper 16 bits.
+    pub fn extended_highest(&self) -> Option<u32> {
+        self.highest
+            .map(|highest| u32::from(self.cycles) << 16 | u32::from(highest))
+    }
+
+    pub fn stats(&self) -> SequenceStats {
+        self.stats
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn loss_reordering_and_duplicates() {
+        let mut tracker = SequenceTracker::new();
+        assert_eq!(tracker.on_receive(u16::MAX - 1), Arrival::InOrder);
+        assert_eq!(tracker.on_receive(1), Arrival::InOrder);
+        assert_eq!(tracker.stats().lost, 2);
+        assert!(tracker.is_received(u16::MAX - 1));
+        assert!(!tracker.is_received(u16::MAX));
+        assert_eq!(tracker.extended_highest(), Some(0x0001_0001));
+
+        assert_eq!(tracker.on_receive(0), Arrival::Reordered);
+        assert_eq!(tracker.on_receive(0), Arrival::Duplicate);
+        assert_eq!(tracker.on_receive(1), Arrival::Duplicate);
+
+        assert_eq!(
+            tracker.stats(),
+            SequenceStats {
+                received: 3,
+                lost: 1,
+                reordered: 1,
+                duplicates: 2,
+                recovered: 0,
+            }
+        );
+    }
+
+    #[test]
+    fn recovery() {
+        let mut tracker = SequenceTracker::new();
+        tracker.on_receive(0);
+        tracker.on_receive(3);
+        assert!(tracker.on_recover(1));
+        assert!(!tracker.on_recover(1));
+        assert!(tracker.is_received(1));
+        // The original message arrives after it was recovered.
+        assert_eq!(tracker.on_receive(1), Arrival::Duplicate);
+        // A recovered message may be newer than every received one.
+        assert!(tracker.on_recover(4));
+        assert_eq!(tracker.extended_highest(), Some(4));
+        assert_eq!(
+            tracker.stats(),
+            SequenceStats {
+                received: 2,
+                lost: 3,
+                reordered: 0,
+                duplicates: 1,
+                recovered: 2,
+            }
+        );
+    }
+}
@@ -0,0 +1,112 @@
+use serde::{Deserialize, Serialize};
+use std::io;
+
+/// Decides which transient socket errors are returned. Transient errors are
+/// expected while the peer is not up, e.g. connection refused, or are worth
+/// retrying, e.g. interrupted calls. They are always counted.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+pub enum TransientErrorPolicy {
+    /// Transient errors are ignored.
+    #[default]
+    Tolerate,
+    /// Every transient error is returned.
+    Fail,
+    /// Transient errors are returned once more than `max` occurred without a
+    /// successful socket call in between.
+    Limit { max: usize },
+}
+
+/// Returns true if `e` is expected to go away when the call is repeated.
+pub fn is_transient(e: &io::Error) -> bool {
+    matches!(
+        e.kind(),
+        io::ErrorKind::ConnectionRefused
+            | io::ErrorKind::ConnectionReset
+            | io::ErrorKind::Interrupted
+            | io::ErrorKind::WouldBlock
+            | io::ErrorKind::TimedOut
+    )
+}
+
+/// Counts the transient errors of socket calls and applies the policy.
+#[derive(Debug, Clone, Default)]
+pub struct TransientErrors {
+    policy: TransientErrorPolicy,
+    count: usize,
+    consecutive: usize,
+}
+
+impl TransientErrors {
+    pub fn new(policy: TransientErrorPolicy) -> Self {
+        Self {
+            policy,
+            count: 0,
+            consecutive: 0,
+        }
+    }
+
+    pub fn set_policy(&mut self, policy: TransientErrorPolicy) {
+        self.policy = policy;
+        self.consecutive = 0;
+    }
+
+    /// Returns the total number of transient errors.
+    pub fn count(&self) -> usize {
+        self.count
+    }
+
+    /// Returns the value of a successful call, `None` if the call failed with
+    /// a tolerated transient error, or the error.
+    pub fn check<T>(&mut self, result: io::Result<T>) -> io::Result<Option<T>> {
+        let e = match result {
+            Ok(value) => {
+                self.consecutive = 0;
+                return Ok(Some(value));
+            }
+            Err(e) => e,
+        };
+        if !is_transient(&e) {
+            return Err(e);
+        }
+        self.count += 1;
+        self.consecutive += 1;
+        match self.policy {
+            TransientErrorPolicy::Tolerate => Ok(None),
+            TransientErrorPolicy::Fail => Err(e),
+            TransientErrorPolicy::Limit { max } if self.consecutive <= max => Ok(None),
+            TransientErrorPolicy::Limit { .. } => Err(e),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn refused() -> io::Result<usize> {
+        Err(io::ErrorKind::ConnectionRefused.into())
+    }
+
+    #[test]
+    fn transient_errors() {
+        let mut errors = TransientErrors::default();
+        assert_eq!(errors.check(refused()).unwrap(), None);
+        assert_eq!(errors.check(Ok(3)).unwrap(), Some(3));
+        let denied: io::Result<usize> = Err(io::ErrorKind::PermissionDenied.into());
+        assert!(errors.check(denied).is_err());
+        assert_eq!(errors.count(), 1);
+
+        let mut errors = TransientErrors::new(TransientErrorPolicy::Limit { max: 2 });
+        assert_eq!(errors.check(refused()).unwrap(), None);
+        assert_eq!(errors.check(refused()).unwrap(), None);
+        assert!(errors.check(refused()).is_err());
+        // A successful call resets the limit.
+        assert_eq!(errors.check(Ok(1)).unwrap(), Some(1));
+        assert_eq!(errors.check(refused()).unwrap(), None);
+        assert_eq!(errors.count(), 4);
+
+        let mut errors = TransientErrors::new(TransientErrorPolicy::Fail);
+        assert!(errors.check(refused()).is_err());
+        assert_eq!(errors.count(), 1);
+    }
+}
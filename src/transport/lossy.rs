@@ -0,0 +1,155 @@
+use super::Transport;
+use crate::common::now;
+use crate::config::ChannelConfig;
+use std::collections::VecDeque;
+use std::io;
+use std::net::SocketAddr;
+
+/// Emulates a link with a limited capacity, a transmission delay and bursty
+/// losses after the Gilbert-Elliot model on top of another transport.
+///
+/// The link is configured like a channel of the network emulator, with the
+/// capacity in [kbit/s]. Sent datagrams are held back until they would arrive
+/// at the peer. They are only passed on while `send` or `recv` are called.
+#[derive(Debug)]
+pub struct LossyTransport<T> {
+    inner: T,
+    config: ChannelConfig,
+    // The datagrams in flight and the time they arrive at in [us].
+    in_flight: VecDeque<(u64, Vec<u8>)>,
+    // The time the link finishes transmitting the previous datagram.
+    busy_until: u64,
+    bad: bool,
+    // The state of the xorshift generator.
+    rng: u64,
+    lost: usize,
+}
+
+impl<T: Transport> LossyTransport<T> {
+    /// Creates the link on top of `inner`. Links with the same `seed` lose
+    /// the same datagrams.
+    pub fn new(inner: T, config: ChannelConfig, seed: u64) -> Self {
+        Self {
+            inner,
+            config,
+            in_flight: VecDeque::new(),
+            busy_until: 0,
+            bad: false,
+            // The generator never leaves the zero state.
+            rng: seed | 1,
+            lost: 0,
+        }
+    }
+
+    /// Returns the number of datagrams the link lost.
+    pub fn lost(&self) -> usize {
+        self.lost
+    }
+
+    /// Returns a uniformly distributed number in [0, 1).
+    fn random(&mut self) -> f64 {
+        self.rng ^= self.rng << 13;
+        self.rng ^= self.rng >> 7;
+        self.rng ^= self.rng << 17;
+        (self.rng >> 11) as f64 / (1u64 << 53) as f64
+    }
+
+    fn is_lost(&mut self) -> bool {
+        let ge = &self.config.gilbert_elliot_config;
+        let switch = match self.bad {
+            true => ge.prob_bad_to_good,
+            false => ge.prob_good_to_bad,
+        };
+        if self.random() < switch {
+            self.bad = !self.bad;
+        }
+        let ge = &self.config.gilbert_elliot_config;
+        let err_rate = match self.bad {
+            true => ge.err_rate_bad,
+            false => ge.err_rate_good,
+        };
+        self.random() < err_rate
+    }
+
+    /// Passes the datagrams that arrived by now on to the inner transport.
+    fn flush(&mut self) -> io::Result<()> {
+        let now = now();
+        while let Some((arrival, _)) = self.in_flight.front() {
+            if *arrival > now {
+                break;
+            }
+            let (_, bs) = self.in_flight.pop_front().unwrap();
+            self.inner.send(&bs)?;
+        }
+        Ok(())
+    }
+}
+
+impl<T: Transport> Transport for LossyTransport<T> {
+    fn send(&mut self, bs: &[u8]) -> io::Result<usize> {
+        if self.is_lost() {
+            self.lost += 1;
+            return self.flush().map(|_| bs.len());
+        }
+        let now = now();
+        // The capacity is in [kbit/s], so it is the number of bits per ms.
+        let transmission = (bs.len() * 8) as f64 * 1000.0 / self.config.capacity;
+        self.busy_until = std::cmp::max(now, self.busy_until) + transmission as u64;
+        let arrival = self.busy_until + self.config.transmission_delay_micros;
+        self.in_flight.push_back((arrival, bs.to_vec()));
+        self.flush().map(|_| bs.len())
+    }
+
+    fn recv(&mut self, buf: &mut [u8]) -> io::Result<(usize, Option<u64>)> {
+        self.flush()?;
+        self.inner.recv(buf)
+    }
+
+    fn addrs(&self) -> io::Result<(SocketAddr, SocketAddr)> {
+        self.inner.addrs()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::GilbertElliotConfig;
+    use crate::transport::MemoryTransport;
+
+    fn config(err_rate: f64) -> ChannelConfig {
+        ChannelConfig {
+            transmission_delay_micros: 2_000,
+            capacity: 1_000_000.0,
+            gilbert_elliot_config: GilbertElliotConfig {
+                prob_good_to_bad: 0.1,
+                prob_bad_to_good: 0.5,
+                err_rate_good: 0.0,
+                err_rate_bad: err_rate,
+            },
+        }
+    }
+
+    #[test]
+    fn lossy_transport() {
+        let (a, mut b) = MemoryTransport::pair();
+        let mut a = LossyTransport::new(a, config(0.0), 7);
+        let mut buf = [0; 8];
+        a.send(&[1, 2, 3]).unwrap();
+        // The datagram is delayed.
+        assert_eq!(
+            b.recv(&mut buf).unwrap_err().kind(),
+            io::ErrorKind::WouldBlock
+        );
+        std::thread::sleep(std::time::Duration::from_millis(3));
+        a.recv(&mut buf).unwrap_err();
+        assert_eq!(b.recv(&mut buf).unwrap().0, 3);
+
+        let (a, _b) = MemoryTransport::pair();
+        let mut a = LossyTransport::new(a, config(1.0), 7);
+        for _ in 0..1000 {
+            a.send(&[1]).unwrap();
+        }
+        // The link is in the bad state about a sixth of the time.
+        assert!(a.lost() > 100 && a.lost() < 250, "{}", a.lost());
+    }
+}
@@ -0,0 +1,83 @@
+use super::Transport;
+use crate::common::now;
+use std::collections::VecDeque;
+use std::io;
+use std::sync::{Arc, Mutex};
+
+// The datagrams in flight in one direction and the time they were sent at.
+type Queue = Arc<Mutex<VecDeque<(Vec<u8>, u64)>>>;
+
+/// One end of an in-memory link. The datagrams arrive instantly, in order
+/// and without loss.
+#[derive(Debug)]
+pub struct MemoryTransport {
+    tx: Queue,
+    rx: Queue,
+}
+
+impl MemoryTransport {
+    /// Creates both ends of a link.
+    pub fn pair() -> (Self, Self) {
+        let a = Queue::default();
+        let b = Queue::default();
+        (
+            Self {
+                tx: a.clone(),
+                rx: b.clone(),
+            },
+            Self { tx: b, rx: a },
+        )
+    }
+}
+
+impl Transport for MemoryTransport {
+    /// Fails with `ConnectionRefused` once the other end is dropped.
+    fn send(&mut self, bs: &[u8]) -> io::Result<usize> {
+        if Arc::strong_count(&self.tx) == 1 {
+            return Err(io::ErrorKind::ConnectionRefused.into());
+        }
+        let mut tx = self.tx.lock().unwrap();
+        tx.push_back((bs.to_vec(), now()));
+        Ok(bs.len())
+    }
+
+    fn recv(&mut self, buf: &mut [u8]) -> io::Result<(usize, Option<u64>)> {
+        let (bs, arrival) = match self.rx.lock().unwrap().pop_front() {
+            Some(datagram) => datagram,
+            None => return Err(io::ErrorKind::WouldBlock.into()),
+        };
+        let num_bytes = std::cmp::min(bs.len(), buf.len());
+        buf[..num_bytes].copy_from_slice(&bs[..num_bytes]);
+        Ok((num_bytes, Some(arrival)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn memory_transport() {
+        let (mut a, mut b) = MemoryTransport::pair();
+        let mut buf = [0; 4];
+        assert_eq!(
+            b.recv(&mut buf).unwrap_err().kind(),
+            io::ErrorKind::WouldBlock
+        );
+        a.send(&[1, 2]).unwrap();
+        a.send(&[3, 4, 5, 6, 7]).unwrap();
+        assert_eq!(b.recv(&mut buf).unwrap().0, 2);
+        assert_eq!(buf[..2], [1, 2]);
+        // Long datagrams are truncated.
+        assert_eq!(b.recv(&mut buf).unwrap().0, 4);
+        assert_eq!(buf, [3, 4, 5, 6]);
+
+        b.send(&[8]).unwrap();
+        assert_eq!(a.recv(&mut buf).unwrap().0, 1);
+        drop(b);
+        assert_eq!(
+            a.send(&[1]).unwrap_err().kind(),
+            io::ErrorKind::ConnectionRefused
+        );
+    }
+}
@@ -0,0 +1,47 @@
+use std::io;
+use std::net::{Ipv4Addr, SocketAddr, UdpSocket};
+
+mod lossy;
+mod memory;
+#[cfg(unix)]
+mod unix;
+
+pub use crate::config::{ChannelConfig, GilbertElliotConfig};
+pub use lossy::LossyTransport;
+pub use memory::MemoryTransport;
+
+/// A connected transport of datagrams between two peers.
+///
+/// Both calls must not block: `recv` returns an error of the kind
+/// `WouldBlock` if no datagram is queued.
+pub trait Transport {
+    /// Sends the datagram `bs` to the peer and returns its length.
+    fn send(&mut self, bs: &[u8]) -> io::Result<usize>;
+
+    /// Receives the next datagram into `buf` and returns its length and the
+    /// time it arrived at in [us], if the transport knows it. Longer
+    /// datagrams are truncated.
+    fn recv(&mut self, buf: &mut [u8]) -> io::Result<(usize, Option<u64>)>;
+
+    /// Returns the local and the peer address that are written to captures.
+    /// Transports without addresses use port 0 on the loopback.
+    fn addrs(&self) -> io::Result<(SocketAddr, SocketAddr)> {
+        let addr = SocketAddr::from((Ipv4Addr::LOCALHOST, 0));
+        Ok((addr, addr))
+    }
+}
+
+/// A connected and non-blocking UDP socket.
+impl Transport for UdpSocket {
+    fn send(&mut self, bs: &[u8]) -> io::Result<usize> {
+        UdpSocket::send(self, bs)
+    }
+
+    fn recv(&mut self, buf: &mut [u8]) -> io::Result<(usize, Option<u64>)> {
+        UdpSocket::recv(self, buf).map(|num_bytes| (num_bytes, None))
+    }
+
+    fn addrs(&self) -> io::Result<(SocketAddr, SocketAddr)> {
+        Ok((self.local_addr()?, self.peer_addr()?))
+    }
+}
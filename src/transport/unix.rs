@@ -0,0 +1,38 @@
+use super::Transport;
+use std::io;
+use std::os::unix::net::UnixDatagram;
+
+/// A connected and non-blocking Unix datagram socket.
+impl Transport for UnixDatagram {
+    fn send(&mut self, bs: &[u8]) -> io::Result<usize> {
+        UnixDatagram::send(self, bs)
+    }
+
+    fn recv(&mut self, buf: &mut [u8]) -> io::Result<(usize, Option<u64>)> {
+        UnixDatagram::recv(self, buf).map(|num_bytes| (num_bytes, None))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn unix_datagram() {
+        let (mut a, mut b) = UnixDatagram::pair().unwrap();
+        a.set_nonblocking(true).unwrap();
+        b.set_nonblocking(true).unwrap();
+        let mut buf = [0; 4];
+        assert_eq!(
+            Transport::recv(&mut b, &mut buf).unwrap_err().kind(),
+            io::ErrorKind::WouldBlock
+        );
+        assert_eq!(Transport::send(&mut a, &[1, 2, 3]).unwrap(), 3);
+        assert_eq!(Transport::recv(&mut b, &mut buf).unwrap(), (3, None));
+        assert_eq!(buf[..3], [1, 2, 3]);
+        // Long datagrams are truncated.
+        Transport::send(&mut b, &[4, 5, 6, 7, 8]).unwrap();
+        assert_eq!(Transport::recv(&mut a, &mut buf).unwrap(), (4, None));
+        assert_eq!(buf, [4, 5, 6, 7]);
+    }
+}